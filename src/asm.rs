@@ -0,0 +1,1107 @@
+//! Assembler for Octo's CHIP-8 assembly language.
+//!
+//! [`assemble`] turns Octo source into a ROM image loaded at 0x200 plus the
+//! addresses of its labels. It supports:
+//!
+//! - labels (`: name`, `:next name`), `:const`, `:alias`, `:calc`, `:byte`,
+//!   `:pointer`, `:org`, `:unpack`, `:call`, `:breakpoint` and `:macro`
+//! - every instruction of CHIP-8, SUPER-CHIP and XO-CHIP in Octo's syntax
+//! - `if ... then`, `if ... begin ... else ... end`, `loop ... again` and
+//!   `while`, including the `<`, `>`, `<=` and `>=` comparisons (which use
+//!   VF as a scratch register, just like Octo)
+//!
+//! Instructions the target platform doesn't have are reported as errors. All
+//! errors carry the line they were found on.
+//!
+//! `:calc` expressions follow Octo: operators have no precedence and are
+//! evaluated right to left, so use parentheses (with spaces around them).
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt
+};
+
+use crate::{
+    cpu::PROGRAM_START,
+    instruction::decode,
+    platform::Platform
+};
+
+/// An assembly error and the line it happened on (starting at 1)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// An assembled program
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Program {
+    /// The ROM, to be loaded at 0x200
+    pub bytes: Vec<u8>,
+    /// Labels and their addresses, sorted by address
+    pub labels: Vec<(String, u16)>,
+    /// Addresses marked with `:breakpoint`
+    pub breakpoints: Vec<(String, u16)>
+}
+
+impl Program {
+    /// One `address name` line per label, with breakpoints marked
+    pub fn symbol_map(&self) -> String {
+        let mut map = String::new();
+        for (name, addr) in &self.labels {
+            map.push_str(&format!("{:#06X} {}\n", addr, name));
+        }
+        for (name, addr) in &self.breakpoints {
+            map.push_str(&format!("{:#06X} {} (breakpoint)\n", addr, name));
+        }
+        map
+    }
+}
+
+/// Assemble `source` for `platform`
+pub fn assemble(source: &str, platform: Platform) -> Result<Program, AsmError> {
+    let mut assembler = Assembler::new(platform, tokenize(source));
+    assembler.run()?;
+    assembler.finish()
+}
+
+/// Deepest macros can expand inside each other, so one that uses itself
+/// is an error instead of a hang
+const MACRO_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Token {
+    text: String,
+    line: usize,
+    /// Macro expansions this token came out of, 0 for the source
+    depth: usize
+}
+
+/// Split on whitespace and drop `#` comments
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        for word in code.split_whitespace() {
+            tokens.push_back(Token { text: word.to_string(), line: index + 1, depth: 0 });
+        }
+    }
+    tokens
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>
+}
+
+/// A place in the output waiting for a label that wasn't defined yet
+#[derive(Clone, Copy, Debug)]
+enum FixupKind {
+    /// Low 12 bits of the opcode at the address
+    Addr12,
+    /// Full 16 bit word at the address
+    Addr16,
+    /// `nibble << 4` plus the high bits of the address, in one byte
+    UnpackHigh { nibble: u8 },
+    /// Low byte of the address
+    UnpackLow
+}
+
+struct Fixup {
+    at: usize,
+    kind: FixupKind,
+    name: String,
+    line: usize
+}
+
+/// An open `if ... begin`, `else` or `loop`
+enum Flow {
+    /// Jump to patch at `else` or `end`
+    If { jump: usize, line: usize },
+    /// Jump to patch at `end`
+    Else { jump: usize, line: usize },
+    /// Loop start and the `while` jumps to patch at `again`
+    Loop { start: usize, breaks: Vec<usize>, line: usize }
+}
+
+/// The two skips a condition compiles to: the one that skips when it holds,
+/// and the one that skips when it doesn't
+struct Condition {
+    skip_if_true: u16,
+    skip_if_false: u16
+}
+
+/// An operand that is either a register or a number
+enum Operand {
+    Register(u8),
+    Value(u8)
+}
+
+struct Assembler {
+    platform: Platform,
+    tokens: VecDeque<Token>,
+    line: usize,
+    /// Macro depth of the last token read
+    depth: usize,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    breakpoints: Vec<(String, u16)>,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>
+}
+
+impl Assembler {
+    fn new(platform: Platform, tokens: VecDeque<Token>) -> Self {
+        Assembler {
+            platform,
+            tokens,
+            line: 1,
+            depth: 0,
+            memory: vec![0; platform.memory_size()],
+            here: PROGRAM_START as usize,
+            end: PROGRAM_START as usize,
+            labels: HashMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            breakpoints: Vec::new(),
+            fixups: Vec::new(),
+            flow: Vec::new()
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, AsmError> {
+        Err(AsmError { line: self.line, message: message.into() })
+    }
+
+    fn next(&mut self) -> Result<String, AsmError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                self.depth = token.depth;
+                Ok(token.text)
+            },
+            None => self.error("Unexpected end of file")
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("Expected '{}' but found '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    /// A new name for a label, constant, alias or macro
+    fn name(&mut self) -> Result<String, AsmError> {
+        let name = self.next()?;
+        if is_reserved(&name) || parse_number(&name).is_some() || register_number(&name).is_some() {
+            return self.error(format!("'{}' can't be used as a name", name));
+        }
+        if self.labels.contains_key(&name) || self.consts.contains_key(&name) || self.aliases.contains_key(&name) || self.macros.contains_key(&name) {
+            return self.error(format!("'{}' is already defined", name));
+        }
+        Ok(name)
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AsmError> {
+        if self.here >= self.memory.len() {
+            return self.error(format!("Program doesn't fit in {} bytes of memory", self.memory.len()));
+        }
+
+        self.memory[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    /// Emit an instruction, checking the platform has it
+    fn emit_op(&mut self, opcode: u16) -> Result<(), AsmError> {
+        let instruction = decode(opcode);
+        if !self.platform.supports(&instruction) {
+            return self.error(format!("{} is not available on {}", instruction, self.platform.name()));
+        }
+
+        self.emit_byte((opcode >> 8) as u8)?;
+        self.emit_byte(opcode as u8)
+    }
+
+    fn patch_addr12(&mut self, at: usize, addr: usize) -> Result<(), AsmError> {
+        if addr > 0xFFF {
+            return self.error(format!("Address {:#X} doesn't fit in 12 bits", addr));
+        }
+        self.memory[at] = (self.memory[at] & 0xF0) | (addr >> 8) as u8;
+        self.memory[at + 1] = addr as u8;
+        Ok(())
+    }
+
+    /// Emit `prefix` with a 12 bit address, now or once the label is known
+    fn emit_addr_op(&mut self, prefix: u16) -> Result<(), AsmError> {
+        let at = self.here;
+        self.emit_op(prefix)?;
+        match self.address(FixupKind::Addr12, at)? {
+            Some(addr) => self.patch_addr12(at, addr as usize),
+            None => Ok(())
+        }
+    }
+
+    /// Read an address operand. Returns `None` if it refers to a label that
+    /// isn't defined yet, in which case a fixup at `at` is recorded.
+    fn address(&mut self, kind: FixupKind, at: usize) -> Result<Option<u16>, AsmError> {
+        let token = self.next()?;
+        let value = if token == "{" {
+            Some(self.calc()?)
+        } else {
+            parse_number(&token).or_else(|| self.consts.get(&token).copied())
+        };
+        if let Some(value) = value {
+            if !(0.0..=65535.0).contains(&value) {
+                return self.error(format!("Address {} is outside of memory", value));
+            }
+            return Ok(Some(value as u16));
+        }
+        if let Some(addr) = self.labels.get(&token) {
+            return Ok(Some(*addr));
+        }
+        if is_reserved(&token) || register_number(&token).is_some() {
+            return self.error(format!("Expected an address but found '{}'", token));
+        }
+
+        self.fixups.push(Fixup { at, kind, name: token, line: self.line });
+        Ok(None)
+    }
+
+    /// A byte operand: a number from -128 to 255 or a constant
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        let value = if token == "{" {
+            self.calc()?
+        } else {
+            match parse_number(&token).or_else(|| self.consts.get(&token).copied()).or_else(|| self.labels.get(&token).map(|addr| *addr as f64)) {
+                Some(value) => value,
+                None => return self.error(format!("Expected a number but found '{}'", token))
+            }
+        };
+
+        let value = value as i64;
+        if !(-128..=255).contains(&value) {
+            return self.error(format!("{} doesn't fit in a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    /// A number that fits in `bits` bits, e.g. a sprite height
+    fn small(&mut self, bits: u32) -> Result<u8, AsmError> {
+        let value = self.byte()?;
+        if value as u32 >= 1 << bits {
+            return self.error(format!("{} doesn't fit in {} bits", value, bits));
+        }
+        Ok(value)
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        match self.register_of(&token) {
+            Some(register) => Ok(register),
+            None => self.error(format!("Expected a register but found '{}'", token))
+        }
+    }
+
+    fn register_of(&self, token: &str) -> Option<u8> {
+        register_number(token).or_else(|| self.aliases.get(token).copied())
+    }
+
+    fn operand(&mut self) -> Result<Operand, AsmError> {
+        if let Some(register) = self.peek().and_then(|token| self.register_of(token)) {
+            self.next()?;
+            return Ok(Operand::Register(register));
+        }
+        Ok(Operand::Value(self.byte()?))
+    }
+
+    fn run(&mut self) -> Result<(), AsmError> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+
+        if let Some(flow) = self.flow.last() {
+            let (line, what) = match flow {
+                Flow::If { line, .. } => (*line, "begin"),
+                Flow::Else { line, .. } => (*line, "else"),
+                Flow::Loop { line, .. } => (*line, "loop")
+            };
+            return Err(AsmError { line, message: format!("This '{}' is never closed", what) });
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next()?;
+
+        if let Some(x) = self.register_of(&token) {
+            return self.register_statement(x);
+        }
+
+        if let Some(value) = parse_number(&token) {
+            let value = value as i64;
+            if !(-128..=255).contains(&value) {
+                return self.error(format!("{} doesn't fit in a byte", value));
+            }
+            return self.emit_byte(value as u8);
+        }
+
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(name, self.here)
+            },
+            ":next" => {
+                let name = self.name()?;
+                self.define_label(name, self.here + 1)
+            },
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.consts.insert(name, value);
+                Ok(())
+            },
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.consts.insert(name, value);
+                Ok(())
+            },
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+                Ok(())
+            },
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_byte(byte)
+            },
+            ":pointer" => {
+                let at = self.here;
+                self.emit_byte(0)?;
+                self.emit_byte(0)?;
+                if let Some(addr) = self.address(FixupKind::Addr16, at)? {
+                    self.memory[at] = (addr >> 8) as u8;
+                    self.memory[at + 1] = addr as u8;
+                }
+                Ok(())
+            },
+            ":org" => {
+                let addr = self.value()? as usize;
+                if addr < PROGRAM_START as usize || addr >= self.memory.len() {
+                    return self.error(format!(":org {:#X} is outside of program memory", addr));
+                }
+                self.here = addr;
+                Ok(())
+            },
+            ":unpack" => {
+                // v0 := nibble and high bits, v1 := low byte
+                let nibble = self.small(4)?;
+                let at = self.here;
+                self.emit_op(0x6000)?;
+                self.emit_op(0x6100)?;
+                match self.address(FixupKind::UnpackHigh { nibble }, at + 1)? {
+                    Some(addr) => {
+                        self.memory[at + 1] = (nibble << 4) | ((addr >> 8) as u8 & 0xF);
+                        self.memory[at + 3] = addr as u8;
+                    },
+                    None => {
+                        let name = self.fixups.last().map(|fixup| fixup.name.clone()).unwrap_or_default();
+                        self.fixups.push(Fixup { at: at + 3, kind: FixupKind::UnpackLow, name, line: self.line });
+                    }
+                }
+                Ok(())
+            },
+            ":call" => self.emit_addr_op(0x2000),
+            ":breakpoint" => {
+                let name = self.next()?;
+                self.breakpoints.push((name, self.here as u16));
+                Ok(())
+            },
+            ":monitor" => {
+                // Octo's memory monitors are a debugger feature, nothing to emit
+                self.next()?;
+                self.next()?;
+                Ok(())
+            },
+            ":macro" => self.define_macro(),
+            "clear" => self.emit_op(0x00E0),
+            "return" | ";" => self.emit_op(0x00EE),
+            "scroll-down" => {
+                let rows = self.small(4)?;
+                self.emit_op(0x00C0 | rows as u16)
+            },
+            "scroll-up" => {
+                let rows = self.small(4)?;
+                self.emit_op(0x00D0 | rows as u16)
+            },
+            "scroll-right" => self.emit_op(0x00FB),
+            "scroll-left" => self.emit_op(0x00FC),
+            "exit" => self.emit_op(0x00FD),
+            "lores" => self.emit_op(0x00FE),
+            "hires" => self.emit_op(0x00FF),
+            "jump" => self.emit_addr_op(0x1000),
+            "jump0" => self.emit_addr_op(0xB000),
+            "native" => {
+                // 0NNN runs machine code on the original hardware, emitted as is
+                let at = self.here;
+                self.emit_byte(0)?;
+                self.emit_byte(0)?;
+                match self.address(FixupKind::Addr12, at)? {
+                    Some(addr) => self.patch_addr12(at, addr as usize),
+                    None => Ok(())
+                }
+            },
+            "i" => self.i_statement(),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()? as u16;
+                let low = match token.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A
+                };
+                self.emit_op(0xF000 | (x << 8) | low)
+            },
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let rows = self.small(4)? as u16;
+                self.emit_op(0xD000 | (x << 8) | (y << 4) | rows)
+            },
+            "bcd" => {
+                let x = self.register()? as u16;
+                self.emit_op(0xF033 | (x << 8))
+            },
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    let low = if token == "save" { 0x2 } else { 0x3 };
+                    return self.emit_op(0x5000 | (x << 8) | (y << 4) | low);
+                }
+                let low = if token == "save" { 0x55 } else { 0x65 };
+                self.emit_op(0xF000 | (x << 8) | low)
+            },
+            "saveflags" | "loadflags" => {
+                let x = self.register()? as u16;
+                let low = if token == "saveflags" { 0x75 } else { 0x85 };
+                self.emit_op(0xF000 | (x << 8) | low)
+            },
+            "plane" => {
+                let mask = self.small(2)? as u16;
+                self.emit_op(0xF001 | (mask << 8))
+            },
+            "audio" => self.emit_op(0xF002),
+            "if" => self.if_statement(),
+            "else" => {
+                let Some(Flow::If { jump, .. }) = self.flow.pop() else {
+                    return self.error("'else' without 'if ... begin'");
+                };
+                let at = self.here;
+                self.emit_op(0x1000)?;
+                self.patch_addr12(jump, self.here)?;
+                self.flow.push(Flow::Else { jump: at, line: self.line });
+                Ok(())
+            },
+            "end" => {
+                match self.flow.pop() {
+                    Some(Flow::If { jump, .. }) | Some(Flow::Else { jump, .. }) => self.patch_addr12(jump, self.here),
+                    _ => self.error("'end' without 'if ... begin'")
+                }
+            },
+            "loop" => {
+                self.flow.push(Flow::Loop { start: self.here, breaks: Vec::new(), line: self.line });
+                Ok(())
+            },
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_op(condition.skip_if_true)?;
+                let at = self.here;
+                self.emit_op(0x1000)?;
+                match self.flow.iter_mut().rev().find(|flow| matches!(flow, Flow::Loop { .. })) {
+                    Some(Flow::Loop { breaks, .. }) => {
+                        breaks.push(at);
+                        Ok(())
+                    },
+                    _ => self.error("'while' outside of a loop")
+                }
+            },
+            "again" => {
+                let Some(Flow::Loop { start, breaks, .. }) = self.flow.pop() else {
+                    return self.error("'again' without 'loop'");
+                };
+                let at = self.here;
+                self.emit_op(0x1000)?;
+                self.patch_addr12(at, start)?;
+                for jump in breaks {
+                    self.patch_addr12(jump, self.here)?;
+                }
+                Ok(())
+            },
+            "then" | "begin" => self.error(format!("'{}' without 'if'", token)),
+            _ => {
+                if let Some(body) = self.expand_macro(&token)? {
+                    for token in body.into_iter().rev() {
+                        self.tokens.push_front(token);
+                    }
+                    return Ok(());
+                }
+                if self.consts.contains_key(&token) || is_reserved(&token) {
+                    return self.error(format!("Unexpected '{}'", token));
+                }
+
+                // A bare name calls the subroutine at that label
+                self.tokens.push_front(Token { text: token, line: self.line, depth: self.depth });
+                self.emit_addr_op(0x2000)
+            }
+        }
+    }
+
+    fn define_label(&mut self, name: String, addr: usize) -> Result<(), AsmError> {
+        if addr > 0xFFFF {
+            return self.error(format!("Label '{}' is outside of memory", name));
+        }
+        self.labels.insert(name, addr as u16);
+        Ok(())
+    }
+
+    /// A number or constant, as used by `:const` and `:org`
+    fn value(&mut self) -> Result<f64, AsmError> {
+        let token = self.next()?;
+        if token == "{" {
+            return self.calc();
+        }
+        match parse_number(&token).or_else(|| self.consts.get(&token).copied()).or_else(|| self.labels.get(&token).map(|addr| *addr as f64)) {
+            Some(value) => Ok(value),
+            None => self.error(format!("Expected a number but found '{}'", token))
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            params.push(token);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let Some(token) = self.tokens.pop_front() else {
+                return self.error(format!("Macro '{}' is never closed", name));
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                _ => ()
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    /// The body of macro `name` with its arguments filled in, if it is one
+    fn expand_macro(&mut self, name: &str) -> Result<Option<Vec<Token>>, AsmError> {
+        let Some(params) = self.macros.get(name).map(|m| m.params.clone()) else {
+            return Ok(None);
+        };
+
+        let depth = self.depth + 1;
+        if depth > MACRO_DEPTH {
+            return self.error(format!("Macro '{}' expands more than {} levels deep, does it use itself?", name, MACRO_DEPTH));
+        }
+
+        let mut args = HashMap::new();
+        for param in params {
+            let arg = self.next()?;
+            args.insert(param, arg);
+        }
+
+        let line = self.line;
+        let body = self.macros[name].body.iter()
+                    .map(|token| Token {
+                        text: args.get(&token.text).cloned().unwrap_or_else(|| token.text.clone()),
+                        line,
+                        depth
+                    })
+                    .collect();
+        Ok(Some(body))
+    }
+
+    fn i_statement(&mut self) -> Result<(), AsmError> {
+        let op = self.next()?;
+        match op.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    let x = self.register()? as u16;
+                    self.emit_op(0xF029 | (x << 8))
+                },
+                Some("bighex") => {
+                    self.next()?;
+                    let x = self.register()? as u16;
+                    self.emit_op(0xF030 | (x << 8))
+                },
+                Some("long") => {
+                    self.next()?;
+                    let at = self.here;
+                    self.emit_op(0xF000)?;
+                    self.emit_byte(0)?;
+                    self.emit_byte(0)?;
+                    if let Some(addr) = self.address(FixupKind::Addr16, at + 2)? {
+                        self.memory[at + 2] = (addr >> 8) as u8;
+                        self.memory[at + 3] = addr as u8;
+                    }
+                    Ok(())
+                },
+                _ => self.emit_addr_op(0xA000)
+            },
+            "+=" => {
+                let x = self.register()? as u16;
+                self.emit_op(0xF01E | (x << 8))
+            },
+            _ => self.error(format!("Unknown operator 'i {}'", op))
+        }
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), AsmError> {
+        let x16 = (x as u16) << 8;
+        let op = self.next()?;
+
+        match op.as_str() {
+            ":=" => match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    let mask = self.byte()? as u16;
+                    self.emit_op(0xC000 | x16 | mask)
+                },
+                Some("delay") => {
+                    self.next()?;
+                    self.emit_op(0xF007 | x16)
+                },
+                Some("key") => {
+                    self.next()?;
+                    self.emit_op(0xF00A | x16)
+                },
+                _ => match self.operand()? {
+                    Operand::Register(y) => self.emit_op(0x8000 | x16 | ((y as u16) << 4)),
+                    Operand::Value(byte) => self.emit_op(0x6000 | x16 | byte as u16)
+                }
+            },
+            "+=" => match self.operand()? {
+                Operand::Register(y) => self.emit_op(0x8004 | x16 | ((y as u16) << 4)),
+                Operand::Value(byte) => self.emit_op(0x7000 | x16 | byte as u16)
+            },
+            "-=" => match self.operand()? {
+                Operand::Register(y) => self.emit_op(0x8005 | x16 | ((y as u16) << 4)),
+                Operand::Value(byte) => self.emit_op(0x7000 | x16 | byte.wrapping_neg() as u16)
+            },
+            "=-" | "|=" | "&=" | "^=" | ">>=" | "<<=" => {
+                let y = self.register()? as u16;
+                let low = match op.as_str() {
+                    "=-" => 0x7,
+                    "|=" => 0x1,
+                    "&=" => 0x2,
+                    "^=" => 0x3,
+                    ">>=" => 0x6,
+                    _ => 0xE
+                };
+                self.emit_op(0x8000 | x16 | (y << 4) | low)
+            },
+            _ => self.error(format!("Unknown operator '{}'", op))
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<(), AsmError> {
+        let line = self.line;
+        let condition = self.condition()?;
+
+        match self.next()?.as_str() {
+            "then" => self.emit_op(condition.skip_if_false),
+            "begin" => {
+                self.emit_op(condition.skip_if_true)?;
+                let jump = self.here;
+                self.emit_op(0x1000)?;
+                self.flow.push(Flow::If { jump, line });
+                Ok(())
+            },
+            other => self.error(format!("Expected 'then' or 'begin' but found '{}'", other))
+        }
+    }
+
+    /// Parse a condition, emitting the VF setup that `<`, `>`, `<=` and `>=`
+    /// need
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.register()? as u16;
+        let op = self.next()?;
+
+        let skips = |skip_if_true: u16, skip_if_false: u16| Condition { skip_if_true, skip_if_false };
+
+        match op.as_str() {
+            "key" => Ok(skips(0xE09E | (x << 8), 0xE0A1 | (x << 8))),
+            "-key" => Ok(skips(0xE0A1 | (x << 8), 0xE09E | (x << 8))),
+            "==" | "!=" => {
+                let (equal, not_equal) = match self.operand()? {
+                    Operand::Register(y) => (0x5000 | (x << 8) | ((y as u16) << 4), 0x9000 | (x << 8) | ((y as u16) << 4)),
+                    Operand::Value(byte) => (0x3000 | (x << 8) | byte as u16, 0x4000 | (x << 8) | byte as u16)
+                };
+                // "Skip if equal" skips when == holds and when != doesn't
+                if op == "==" {
+                    Ok(skips(equal, not_equal))
+                } else {
+                    Ok(skips(not_equal, equal))
+                }
+            },
+            "<" | ">" | "<=" | ">=" => {
+                let y = self.operand()?;
+
+                // Set VF to (a >= b), with b compared as the right operand
+                // for < and >=, and swapped for > and <=
+                let swapped = op == ">" || op == "<=";
+                match (y, swapped) {
+                    (Operand::Register(y), false) => {
+                        self.emit_op(0x8F00 | (x << 4))?;
+                        self.emit_op(0x8F05 | ((y as u16) << 4))?;
+                    },
+                    (Operand::Register(y), true) => {
+                        self.emit_op(0x8F00 | ((y as u16) << 4))?;
+                        self.emit_op(0x8F05 | (x << 4))?;
+                    },
+                    (Operand::Value(byte), false) => {
+                        self.emit_op(0x6F00 | byte as u16)?;
+                        self.emit_op(0x8F07 | (x << 4))?;
+                    },
+                    (Operand::Value(byte), true) => {
+                        self.emit_op(0x6F00 | byte as u16)?;
+                        self.emit_op(0x8F05 | (x << 4))?;
+                    }
+                }
+
+                // < and > hold when VF is 0, <= and >= when it is 1
+                let vf_zero = Condition { skip_if_true: 0x3F00, skip_if_false: 0x4F00 };
+                let vf_one = Condition { skip_if_true: 0x4F00, skip_if_false: 0x3F00 };
+                Ok(if op == "<" || op == ">" { vf_zero } else { vf_one })
+            },
+            _ => self.error(format!("Unknown comparison '{}'", op))
+        }
+    }
+
+    /// Evaluate a `:calc` expression up to and including the closing `}`
+    fn calc(&mut self) -> Result<f64, AsmError> {
+        let value = self.expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    /// Octo style: `term (operator expression)?`, so right to left
+    fn expression(&mut self) -> Result<f64, AsmError> {
+        let left = self.term()?;
+
+        let Some(op) = self.peek().filter(|token| BINARY_OPERATORS.contains(token)).map(str::to_string) else {
+            return Ok(left);
+        };
+        self.next()?;
+        let right = self.expression()?;
+
+        let int = |value: f64| value as i64;
+        Ok(match op.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => {
+                if right == 0.0 {
+                    return self.error("Division by zero");
+                }
+                left / right
+            },
+            "%" => {
+                if right == 0.0 {
+                    return self.error("Division by zero");
+                }
+                left % right
+            },
+            "&" => (int(left) & int(right)) as f64,
+            "|" => (int(left) | int(right)) as f64,
+            "^" => (int(left) ^ int(right)) as f64,
+            "<<" => (int(left) << (int(right) & 63)) as f64,
+            ">>" => (int(left) >> (int(right) & 63)) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as u8 as f64,
+            ">" => (left > right) as u8 as f64,
+            "<=" => (left <= right) as u8 as f64,
+            ">=" => (left >= right) as u8 as f64,
+            "==" => (left == right) as u8 as f64,
+            _ => (left != right) as u8 as f64
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, AsmError> {
+        let token = self.next()?;
+
+        let unary = |value: f64| -> Option<f64> {
+            Some(match token.as_str() {
+                "-" => -value,
+                "~" => !(value as i64) as f64,
+                "!" => (value == 0.0) as u8 as f64,
+                "sin" => value.sin(),
+                "cos" => value.cos(),
+                "tan" => value.tan(),
+                "exp" => value.exp(),
+                "log" => value.ln(),
+                "abs" => value.abs(),
+                "sqrt" => value.sqrt(),
+                "sign" => value.signum(),
+                "ceil" => value.ceil(),
+                "floor" => value.floor(),
+                _ => return None
+            })
+        };
+
+        if UNARY_OPERATORS.contains(&token.as_str()) {
+            let value = self.term()?;
+            return Ok(unary(value).unwrap_or(value));
+        }
+
+        match token.as_str() {
+            "(" => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                Ok(value)
+            },
+            "@" => {
+                let addr = self.term()? as usize;
+                match self.memory.get(addr) {
+                    Some(byte) => Ok(*byte as f64),
+                    None => self.error(format!("@ {:#X} is outside of memory", addr))
+                }
+            },
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            "HERE" => Ok(self.here as f64),
+            _ => {
+                if let Some(value) = parse_number(&token).or_else(|| self.consts.get(&token).copied()) {
+                    return Ok(value);
+                }
+                if let Some(addr) = self.labels.get(&token) {
+                    return Ok(*addr as f64);
+                }
+                self.error(format!("'{}' is not defined yet", token))
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<Program, AsmError> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(addr) = self.labels.get(&fixup.name).copied() else {
+                return Err(AsmError { line: fixup.line, message: format!("'{}' is never defined", fixup.name) });
+            };
+
+            self.line = fixup.line;
+            match fixup.kind {
+                FixupKind::Addr12 => self.patch_addr12(fixup.at, addr as usize)?,
+                FixupKind::Addr16 => {
+                    self.memory[fixup.at] = (addr >> 8) as u8;
+                    self.memory[fixup.at + 1] = addr as u8;
+                },
+                FixupKind::UnpackHigh { nibble } => self.memory[fixup.at] = (nibble << 4) | ((addr >> 8) as u8 & 0xF),
+                FixupKind::UnpackLow => self.memory[fixup.at] = addr as u8
+            }
+        }
+
+        let mut labels: Vec<(String, u16)> = self.labels.into_iter().collect();
+        labels.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+
+        Ok(
+            Program {
+                bytes: self.memory[PROGRAM_START as usize..self.end].to_vec(),
+                labels,
+                breakpoints: self.breakpoints
+            }
+        )
+    }
+}
+
+const BINARY_OPERATORS: [&str; 19] = ["+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", ">", "<=", ">=", "==", "!="];
+
+const UNARY_OPERATORS: [&str; 13] = ["-", "~", "!", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil", "floor"];
+
+/// Words that mean something on their own and can't be names
+fn is_reserved(word: &str) -> bool {
+    const RESERVED: [&str; 47] = [
+        ":", ":next", ":const", ":calc", ":alias", ":byte", ":pointer", ":org", ":unpack", ":call",
+        ":breakpoint", ":monitor", ":macro", "clear", "return", ";", "scroll-down", "scroll-up",
+        "scroll-right", "scroll-left", "exit", "lores", "hires", "jump", "jump0", "native", "i",
+        "delay", "buzzer", "pitch", "sprite", "bcd", "save", "load", "saveflags", "loadflags",
+        "plane", "audio", "if", "then", "begin", "else", "end", "loop", "again", "while", "key"
+    ];
+    RESERVED.contains(&word) || word == "{" || word == "}"
+}
+
+/// `v0` to `vf` (either case)
+fn register_number(word: &str) -> Option<u8> {
+    let digit = word.strip_prefix('v').or_else(|| word.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// Decimal, `0x` hex or `0b` binary, optionally negative
+fn parse_number(word: &str) -> Option<f64> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, word)
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        match assemble(source, Platform::Chip8) {
+            Ok(program) => program.bytes,
+            Err(error) => panic!("{}", error)
+        }
+    }
+
+    fn failure(source: &str, platform: Platform) -> AsmError {
+        assemble(source, platform).expect_err("should not assemble")
+    }
+
+    #[test]
+    fn labels_and_forward_fixups() {
+        let program = assemble(": main jump later : back clear : later jump back", Platform::Chip8).unwrap();
+        assert_eq!(program.bytes, vec![0x12, 0x04, 0x00, 0xE0, 0x12, 0x02]);
+        assert_eq!(
+            program.labels,
+            vec![(String::from("main"), 0x200), (String::from("back"), 0x202), (String::from("later"), 0x204)]
+        );
+
+        // :next points at the second byte of the next instruction
+        let program = assemble(":next second v0 := 7 i := second", Platform::Chip8).unwrap();
+        assert_eq!(program.bytes, vec![0x60, 0x07, 0xA2, 0x01]);
+    }
+
+    #[test]
+    fn consts_aliases_and_macros() {
+        assert_eq!(bytes(":const SPEED 3 :alias px v4 px += SPEED"), vec![0x74, 0x03]);
+        assert_eq!(bytes(":macro move r n { r += n } move v2 5 move v3 1"), vec![0x72, 0x05, 0x73, 0x01]);
+        // Macros can use other macros
+        assert_eq!(bytes(":macro set r { r := 1 } :macro both { set v0 set v1 } both"), vec![0x60, 0x01, 0x61, 0x01]);
+    }
+
+    #[test]
+    fn calc_goes_right_to_left() {
+        assert_eq!(bytes(":calc A { 10 - 4 - 3 } :byte A"), vec![9]);
+        assert_eq!(bytes(":calc B { 2 * 3 + 1 } :byte B"), vec![8]);
+        assert_eq!(bytes(":calc C { ( 2 * 3 ) + 1 } :byte C"), vec![7]);
+        assert_eq!(bytes(": start :calc D { HERE + 2 } :byte { D & 0xFF }"), vec![0x02]);
+    }
+
+    #[test]
+    fn if_then_and_if_begin_else_end() {
+        // Skip the next instruction unless v0 == 5
+        assert_eq!(bytes("if v0 == 5 then v1 := 1"), vec![0x40, 0x05, 0x61, 0x01]);
+
+        assert_eq!(
+            bytes("if v0 != 2 begin v1 := 1 else v1 := 2 end"),
+            vec![0x40, 0x02, 0x12, 0x08, 0x61, 0x01, 0x12, 0x0A, 0x61, 0x02]
+        );
+
+        // < goes through VF: vF := v0, vF -= v1, then test the borrow
+        assert_eq!(bytes("if v0 < v1 then clear"), vec![0x8F, 0x00, 0x8F, 0x15, 0x4F, 0x00, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn loop_while_again() {
+        assert_eq!(
+            bytes("loop v0 += 1 while v0 != 10 again"),
+            vec![0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn unpack_before_and_after_the_label() {
+        assert_eq!(bytes(":unpack 0xA data : data 0x11"), vec![0x60, 0xA2, 0x61, 0x04, 0x11]);
+        assert_eq!(bytes(": data 0x11 :unpack 0xA data"), vec![0x11, 0x60, 0xA2, 0x61, 0x00]);
+    }
+
+    #[test]
+    fn long_i_needs_xo_chip() {
+        let error = failure("clear\ni := long 0x1234", Platform::Chip8);
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("not available on"), "{}", error.message);
+
+        let program = assemble("clear\ni := long 0x1234", Platform::XoChip).unwrap();
+        assert_eq!(program.bytes, vec![0x00, 0xE0, 0xF0, 0x00, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        let error = failure("v0 := 1\nv1 := 2\nv2 += 300", Platform::Chip8);
+        assert_eq!(error.to_string(), "line 3: 300 doesn't fit in a byte");
+
+        let error = failure("clear\njump nowhere\n", Platform::Chip8);
+        assert_eq!(error.to_string(), "line 2: 'nowhere' is never defined");
+
+        let error = failure("clear\nloop\nv0 += 1\n", Platform::Chip8);
+        assert_eq!(error.to_string(), "line 2: This 'loop' is never closed");
+
+        let error = failure(":macro m { m }\n\nm\n", Platform::Chip8);
+        assert_eq!(error.line, 3);
+    }
+}
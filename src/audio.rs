@@ -0,0 +1,277 @@
+//! Beeper driven by the sound timer.
+//!
+//! The machine only decides *when* a tone should be playing (while ST is
+//! non-zero) and hands that to an [`AudioBackend`]. Backends decide what to
+//! do with it: [`NullAudio`] ignores it, [`RecordingAudio`] writes it down for
+//! tests, and the `Speaker` behind the `audio` feature plays it.
+//!
+//! The tone can be set in the `[audio]` section of the config file (or
+//! `[audio rom]` for one ROM):
+//!
+//! ```text
+//! [audio]
+//! waveform = triangle
+//! frequency = 220
+//! volume = 0.5
+//! ```
+
+use std::{cell::RefCell, f32::consts::TAU, rc::Rc};
+
+use crate::config::Config;
+
+/// Names for [`Waveform::from_name`]
+pub const WAVEFORMS: [&str; 4] = ["square", "triangle", "sawtooth", "sine"];
+
+/// Range of frequencies a tone can be set to, in hz
+pub const MIN_FREQUENCY: f32 = 20.0;
+pub const MAX_FREQUENCY: f32 = 20000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+    /// XO-CHIP 128 bit pattern, played MSB first once per period
+    Pattern([u8; 16])
+}
+
+impl Waveform {
+    /// One of [`WAVEFORMS`]. Patterns only come from XO-CHIP programs.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None
+        }
+    }
+}
+
+/// What the beeper sounds like
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    pub frequency: f32,     // in hz
+    pub volume: f32         // 0.0 to 1.0
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25
+        }
+    }
+}
+
+impl Tone {
+    /// Set `waveform`, `frequency` or `volume` from text
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name.to_ascii_lowercase().as_str() {
+            "waveform" => {
+                self.waveform = Waveform::from_name(value)
+                                    .ok_or(format!("unknown waveform '{}', expected one of {}", value, WAVEFORMS.join(", ")))?;
+            },
+            "frequency" => {
+                self.frequency = value.parse().ok()
+                                    .filter(|hz| (MIN_FREQUENCY..=MAX_FREQUENCY).contains(hz))
+                                    .ok_or(format!("invalid frequency '{}', expected {} to {} hz", value, MIN_FREQUENCY, MAX_FREQUENCY))?;
+            },
+            "volume" => {
+                self.volume = value.parse().ok()
+                                .filter(|volume| (0.0..=1.0).contains(volume))
+                                .ok_or(format!("invalid volume '{}', expected 0.0 to 1.0", value))?;
+            },
+            _ => return Err(format!("unknown audio setting '{}', expected waveform, frequency or volume", name))
+        }
+        Ok(())
+    }
+
+    /// Start from `base` and apply the `[audio]` sections of `config` that
+    /// apply to `rom`. Errors name the line they're on.
+    pub fn from_config(base: Tone, config: &Config, rom: Option<&str>) -> Result<Self, String> {
+        let mut tone = base;
+
+        for section in config.sections("audio", rom) {
+            for entry in &section.entries {
+                tone.set(&entry.name, &entry.value).map_err(|message| format!("line {}: {}", entry.line, message))?;
+            }
+        }
+
+        Ok(tone)
+    }
+
+    /// Amplitude of the tone at `phase`, where one period is 0.0..1.0
+    pub fn sample(&self, phase: f32) -> f32 {
+        let value = match self.waveform {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - (4.0 * (phase - 0.5).abs()),
+            Waveform::Sawtooth => (2.0 * phase) - 1.0,
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Pattern(pattern) => {
+                let bit = ((phase * 128.0) as usize).min(127);
+                if (pattern[bit / 8] >> (7 - (bit % 8))) & 0x1 == 1 { 1.0 } else { -1.0 }
+            }
+        };
+
+        value * self.volume.clamp(0.0, 1.0)
+    }
+}
+
+/// Produces samples of a [`Tone`] at a fixed sample rate
+#[derive(Clone, Debug)]
+pub struct Oscillator {
+    pub tone: Tone,
+    sample_rate: f32,
+    phase: f32
+}
+
+impl Oscillator {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        Oscillator {
+            tone,
+            sample_rate: sample_rate as f32,
+            phase: 0.0
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = self.tone.sample(self.phase);
+        self.phase = (self.phase + (self.tone.frequency / self.sample_rate)).fract();
+        sample
+    }
+}
+
+pub trait AudioBackend {
+    /// Change what the beep sounds like
+    fn set_tone(&mut self, tone: Tone);
+
+    /// Start or stop the beep. `frame` is the emulated frame it happened on.
+    fn set_playing(&mut self, frame: u64, playing: bool);
+}
+
+/// Backend that discards everything
+pub struct NullAudio;
+
+impl AudioBackend for NullAudio {
+    fn set_tone(&mut self, _tone: Tone) {}
+
+    fn set_playing(&mut self, _frame: u64, _playing: bool) {}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BeepEvent {
+    pub frame: u64,
+    pub playing: bool
+}
+
+/// Backend that records when beeps start and stop.
+///
+/// Clones share the same log, so keep one clone around and hand the other to
+/// the machine.
+#[derive(Clone, Default)]
+pub struct RecordingAudio {
+    events: Rc<RefCell<Vec<BeepEvent>>>
+}
+
+impl RecordingAudio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<BeepEvent> {
+        self.events.borrow().clone()
+    }
+}
+
+impl AudioBackend for RecordingAudio {
+    fn set_tone(&mut self, _tone: Tone) {}
+
+    fn set_playing(&mut self, frame: u64, playing: bool) {
+        self.events.borrow_mut().push(BeepEvent { frame, playing });
+    }
+}
+
+/// Frequency a pattern loops at for an XO-CHIP pitch register value. The
+/// pattern plays at 4000 * 2 ^ ((pitch - 64) / 48) bits per second.
+pub fn pattern_frequency(pitch: u8) -> f32 {
+    let bits_per_second = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+    bits_per_second / 128.0
+}
+
+/// Tracks whether the beep is on and tells the backend when that changes
+pub struct Beeper {
+    backend: Box<dyn AudioBackend>,
+    tone: Tone,
+    pattern: Option<([u8; 16], u8)>,
+    playing: bool
+}
+
+impl Default for Beeper {
+    fn default() -> Self {
+        Self::new(Box::new(NullAudio))
+    }
+}
+
+impl Beeper {
+    pub fn new(mut backend: Box<dyn AudioBackend>) -> Self {
+        let tone = Tone::default();
+        backend.set_tone(tone);
+
+        Beeper {
+            backend,
+            tone,
+            pattern: None,
+            playing: false
+        }
+    }
+
+    pub fn set_backend(&mut self, mut backend: Box<dyn AudioBackend>) {
+        backend.set_tone(self.effective_tone());
+        self.backend = backend;
+        self.playing = false;
+    }
+
+    pub fn tone(&self) -> Tone {
+        self.tone
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+        self.backend.set_tone(self.effective_tone());
+    }
+
+    /// Play an XO-CHIP pattern at `pitch` instead of the configured tone
+    pub fn set_pattern(&mut self, pattern: Option<([u8; 16], u8)>) {
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            self.backend.set_tone(self.effective_tone());
+        }
+    }
+
+    /// The configured tone, or the pattern at the configured volume
+    pub fn effective_tone(&self) -> Tone {
+        match self.pattern {
+            Some((pattern, pitch)) => Tone {
+                waveform: Waveform::Pattern(pattern),
+                frequency: pattern_frequency(pitch),
+                volume: self.tone.volume
+            },
+            None => self.tone
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn update(&mut self, frame: u64, playing: bool) {
+        if playing != self.playing {
+            self.playing = playing;
+            self.backend.set_playing(frame, playing);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    env, fs, path::Path
+};
+
+use chip8::{
+    asm::assemble,
+    platform::Platform
+};
+
+struct Options {
+    source_path: String,
+    platform: Platform,
+    output: Option<String>,
+    symbols: Option<String>
+}
+
+fn usage(program: &str) -> String {
+    format!(
+        "Usage: {} [options] <source.8o>\n\
+         \n\
+         Options:\n\
+         \x20 --platform <name>     chip8 (default), schip or xochip\n\
+         \x20 -o, --output <path>   rom to write (default: the source with .ch8)\n\
+         \x20 --symbols <path>      symbol map to write (default: the source with .sym)",
+        program
+    )
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut source_path = None;
+    let mut platform = Platform::Chip8;
+    let mut output = None;
+    let mut symbols = None;
+
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+
+        match arg.as_str() {
+            "--platform" => {
+                let name = value()?;
+                platform = Platform::from_name(name).ok_or(format!("Unknown platform '{}'", name))?;
+            },
+            "-o" | "--output" => output = Some(value()?.clone()),
+            "--symbols" => symbols = Some(value()?.clone()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+            _ if source_path.is_none() => source_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument {}", arg))
+        }
+    }
+
+    Ok(
+        Options {
+            source_path: source_path.ok_or("Missing source path")?,
+            platform,
+            output,
+            symbols
+        }
+    )
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, usage(&args[0]));
+            std::process::exit(2);
+        }
+    };
+
+    let source = match fs::read_to_string(&options.source_path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Could not open {}: {}", options.source_path, error);
+            std::process::exit(1);
+        }
+    };
+
+    let program = match assemble(&source, options.platform) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{}:{}: {}", options.source_path, error.line, error.message);
+            std::process::exit(1);
+        }
+    };
+
+    let source_path = Path::new(&options.source_path);
+    let output = options.output.map(Into::into).unwrap_or_else(|| source_path.with_extension("ch8"));
+    let symbols = options.symbols.map(Into::into).unwrap_or_else(|| source_path.with_extension("sym"));
+
+    if let Err(error) = fs::write(&output, &program.bytes) {
+        eprintln!("Could not write {}: {}", output.display(), error);
+        std::process::exit(1);
+    }
+    if let Err(error) = fs::write(&symbols, program.symbol_map()) {
+        eprintln!("Could not write {}: {}", symbols.display(), error);
+        std::process::exit(1);
+    }
+
+    println!("Wrote {} bytes to {}", program.bytes.len(), output.display());
+}
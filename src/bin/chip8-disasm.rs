@@ -0,0 +1,92 @@
+use std::{
+    env, fs
+};
+
+use chip8::{
+    disasm::disassemble,
+    platform::Platform
+};
+
+struct Options {
+    rom_path: String,
+    platform: Platform,
+    output: Option<String>
+}
+
+fn usage(program: &str) -> String {
+    format!(
+        "Usage: {} [options] <rom.ch8>\n\
+         \n\
+         Options:\n\
+         \x20 --platform <name>     chip8 (default), schip or xochip\n\
+         \x20 -o, --output <path>   source to write (default: standard output)",
+        program
+    )
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut platform = Platform::Chip8;
+    let mut output = None;
+
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+
+        match arg.as_str() {
+            "--platform" => {
+                let name = value()?;
+                platform = Platform::from_name(name).ok_or(format!("Unknown platform '{}'", name))?;
+            },
+            "-o" | "--output" => output = Some(value()?.clone()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument {}", arg))
+        }
+    }
+
+    Ok(
+        Options {
+            rom_path: rom_path.ok_or("Missing rom path")?,
+            platform,
+            output
+        }
+    )
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, usage(&args[0]));
+            std::process::exit(2);
+        }
+    };
+
+    let rom = match fs::read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("Could not open {}: {}", options.rom_path, error);
+            std::process::exit(1);
+        }
+    };
+
+    let source = format!(
+        "# Disassembled from {} for {}\n\n{}",
+        options.rom_path,
+        options.platform.name(),
+        disassemble(&rom, options.platform)
+    );
+
+    match options.output {
+        Some(output) => {
+            if let Err(error) = fs::write(&output, source) {
+                eprintln!("Could not write {}: {}", output, error);
+                std::process::exit(1);
+            }
+        },
+        None => print!("{}", source)
+    }
+}
@@ -0,0 +1,135 @@
+//! The emulator's config file.
+//!
+//! It's a list of `name = value` settings in sections. A section header is
+//! `[name]`, or `[name rom]` for settings that only apply while running the
+//! ROM `rom` (its file name without the extension). Lines starting with `#`
+//! are comments, and so is the rest of a line after a `#` with spaces on
+//! both sides, which leaves colors like `#FF0000` alone:
+//!
+//! ```text
+//! [keymap]
+//! preset = azerty
+//!
+//! # Pong is nicer with the arrow keys
+//! [keymap pong]
+//! 1 = up
+//! 4 = down
+//! ```
+//!
+//! This module only splits the file up; what the settings mean is up to the
+//! modules that read each section.
+
+use std::{env, fs, path::{Path, PathBuf}};
+
+/// Name of the config file, in the current directory or the user's config
+/// directory
+pub const CONFIG_FILE_NAME: &str = "chip8.conf";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigEntry {
+    /// Line number in the file, from 1
+    pub line: usize,
+    pub name: String,
+    pub value: String
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigSection {
+    pub name: String,
+    /// Only applies to this ROM
+    pub rom: Option<String>,
+    pub entries: Vec<ConfigEntry>
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    pub sections: Vec<ConfigSection>
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut sections: Vec<ConfigSection> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header.strip_suffix(']').ok_or(format!("line {}: section header is missing its ]", line_number))?;
+                let mut words = header.split_whitespace();
+                let name = words.next().ok_or(format!("line {}: section has no name", line_number))?;
+                let rom = words.next();
+                if words.next().is_some() {
+                    return Err(format!("line {}: expected [section] or [section rom]", line_number));
+                }
+
+                sections.push(ConfigSection {
+                    name: name.to_ascii_lowercase(),
+                    rom: rom.map(String::from),
+                    entries: Vec::new()
+                });
+                continue;
+            }
+
+            let (name, value) = line.split_once('=').ok_or(format!("line {}: expected name = value", line_number))?;
+            let section = sections.last_mut().ok_or(format!("line {}: setting outside of a section", line_number))?;
+            section.entries.push(ConfigEntry {
+                line: line_number,
+                name: name.trim().to_ascii_lowercase(),
+                value: value.trim().to_string()
+            });
+        }
+
+        Ok(Config { sections })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("Could not open {}: {}", path.display(), error))?;
+        Self::parse(&text).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// The config file to use when none is given: `chip8.conf` in the current
+    /// directory, or else in `$XDG_CONFIG_HOME/chip-8` (`~/.config/chip-8`)
+    pub fn default_path() -> Option<PathBuf> {
+        let local = PathBuf::from(CONFIG_FILE_NAME);
+        if local.is_file() {
+            return Some(local);
+        }
+
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+                            .map(PathBuf::from)
+                            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        let path = config_home.join("chip-8").join(CONFIG_FILE_NAME);
+        path.is_file().then_some(path)
+    }
+
+    /// Sections called `name` that apply to `rom`: the general ones first,
+    /// then the ones for that ROM, each in file order
+    pub fn sections<'a>(&'a self, name: &'a str, rom: Option<&'a str>) -> impl Iterator<Item = &'a ConfigSection> {
+        let general = self.sections.iter().filter(move |section| section.name == name && section.rom.is_none());
+        let for_rom = self.sections.iter().filter(move |section| {
+            section.name == name && section.rom.is_some() && section.rom.as_deref() == rom
+        });
+        general.chain(for_rom)
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    if line.trim_start().starts_with('#') {
+        return "";
+    }
+
+    let bytes = line.as_bytes();
+    let start = (1..bytes.len()).find(|&index| {
+        bytes[index] == b'#'
+            && bytes[index - 1].is_ascii_whitespace()
+            && bytes.get(index + 1).is_none_or(|next| next.is_ascii_whitespace())
+    });
+    match start {
+        Some(start) => &line[..start],
+        None => line
+    }
+}
@@ -0,0 +1,596 @@
+use crate::{
+    display::Display,
+    fault::{Chip8Fault, FaultPolicy},
+    instruction::{decode_long, Instruction},
+    platform::Platform,
+    quirks::Quirks,
+    rng::Rng,
+    timer::Timers
+};
+
+pub const RAM_SIZE: usize = 4096;
+pub const PROGRAM_START: u16 = 0x200;
+pub const FONT_START: u16 = 0x50;
+pub const BIG_FONT_START: u16 = 0xA0;
+
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+];
+
+/// SUPER-CHIP 8x10 hex digits, A-F are Octo's additions
+pub const BIG_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
+/// Registers from Vx to Vy for 5XY2/5XY3, which also work backwards
+fn register_range(x: u8, y: u8) -> Vec<usize> {
+    if x <= y {
+        (x..=y).map(usize::from).collect()
+    } else {
+        (y..=x).rev().map(usize::from).collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chip8CPU {
+    pub ram: Vec<u8>,
+    pub registers: [u8; 16],
+    pub stack: [u16; 16],
+    pub pc: u16,
+    pub i: u16,
+    pub sp: u8,     // number of return addresses on the stack
+    pub timers: Timers,
+    pub fault_policy: FaultPolicy,
+    pub halted: Option<Chip8Fault>,
+    pub quirks: Quirks,
+    pub platform: Platform,
+    pub rpl: [u8; 16],  // SUPER-CHIP user flags
+    pub audio_pattern: Option<[u8; 16]>,    // XO-CHIP audio, set by F002
+    pub pitch: u8,                          // XO-CHIP audio, set by FX3A
+    pub exited: bool,   // set by 00FD
+    pub rng: Rng,
+    pub waiting_for_vblank: bool    // set by DXYN when quirks.wait_for_vblank is on
+}
+
+impl Default for Chip8CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Chip8CPU {
+    pub fn new() -> Self {
+        Chip8CPU {
+            ram: vec![0; RAM_SIZE],
+            registers: [0; 16],
+            stack: [0; 16],
+            pc: PROGRAM_START,
+            i: 0x0,
+            sp: 0,
+            timers: Timers::new(),
+            fault_policy: FaultPolicy::Halt,
+            halted: None,
+            quirks: Quirks::default(),
+            platform: Platform::default(),
+            rpl: [0; 16],
+            audio_pattern: None,
+            pitch: 64,
+            exited: false,
+            rng: Rng::default(),
+            waiting_for_vblank: false
+        }
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), &'static str> {
+        if rom.len() + self.pc as usize > self.ram.len() {
+            return Err("Out of memory");
+        }
+
+        for (i, byte) in rom.iter().enumerate() {
+            self.ram[self.pc as usize + i] = *byte;
+        }
+
+        Ok(())
+    }
+
+    pub fn load_font(&mut self, font: &[u8]) -> Result<(),  &'static str> {
+
+        if FONT_START as usize + font.len() >= PROGRAM_START as usize {
+            return Err( "Overran program memory");
+        }
+
+        for (i, byte) in font.iter().enumerate() {
+            self.ram[FONT_START as usize + i] = *byte;
+        }
+
+        Ok(())
+    }
+
+    pub fn load_big_font(&mut self, font: &[u8]) -> Result<(),  &'static str> {
+
+        if BIG_FONT_START as usize + font.len() >= PROGRAM_START as usize {
+            return Err( "Overran program memory");
+        }
+
+        for (i, byte) in font.iter().enumerate() {
+            self.ram[BIG_FONT_START as usize + i] = *byte;
+        }
+
+        Ok(())
+    }
+
+    /// Report a fault according to the fault policy. Under `Halt` the PC is
+    /// put back on the faulting instruction and the CPU stops.
+    fn fault(&mut self, fault: Chip8Fault) -> Result<(), Chip8Fault> {
+        match self.fault_policy {
+            FaultPolicy::Halt => {
+                self.pc = fault.pc();
+                self.halted = Some(fault);
+                Err(fault)
+            },
+            FaultPolicy::Wrap | FaultPolicy::Ignore => Ok(())
+        }
+    }
+
+    /// Check that `len` bytes starting at `addr` are in memory. Returns
+    /// whether the access should go ahead; accesses always wrap around the
+    /// end of memory, so this only matters under `Ignore`.
+    fn check_memory(&mut self, addr: u16, len: usize, pc: u16, opcode: u16) -> Result<bool, Chip8Fault> {
+        let end = addr as usize + len;
+        if len == 0 || end <= self.ram.len() {
+            return Ok(true);
+        }
+
+        self.fault(Chip8Fault::MemoryOutOfBounds { pc, opcode, addr: end - 1 })?;
+        Ok(self.fault_policy == FaultPolicy::Wrap)
+    }
+
+    fn read(&self, addr: usize) -> u8 {
+        self.ram[addr % self.ram.len()]
+    }
+
+    fn write(&mut self, addr: usize, value: u8) {
+        let len = self.ram.len();
+        self.ram[addr % len] = value;
+    }
+
+    /// The opcode at the PC
+    pub fn fetch(&self) -> u16 {
+        self.fetch_at(self.pc)
+    }
+
+    /// The opcode at `addr`
+    pub fn fetch_at(&self, addr: u16) -> u16 {
+        ((self.read(addr as usize) as u16) << 8) | self.read(addr as usize + 1) as u16
+    }
+
+    /// Decode the instruction at `addr`, including the address of `F000 NNNN`
+    pub fn decode_at(&self, addr: u16) -> Instruction {
+        decode_long(self.fetch_at(addr), self.fetch_at(addr.wrapping_add(2)))
+    }
+
+    /// Skip the next instruction. On XO-CHIP that may be a 4 byte `F000 NNNN`.
+    fn skip(&mut self) {
+        let size = if self.platform == Platform::XoChip {
+            self.decode_at(self.pc).size()
+        } else {
+            2
+        };
+
+        self.pc = self.pc.wrapping_add(size);
+    }
+
+    /// Execute one instruction.
+    ///
+    /// Once the CPU has halted on a fault every tick returns that fault
+    /// without doing anything until `halted` is cleared. After 00FD ticks do
+    /// nothing at all.
+    pub fn tick(&mut self, display: &mut Display, keyboard: &[bool; 16]) -> Result<(), Chip8Fault> {
+        if let Some(fault) = self.halted {
+            return Err(fault);
+        }
+
+        if self.exited {
+            return Ok(());
+        }
+
+        // Fetch instruction
+        let pc = self.pc;
+        let instruction = self.fetch();
+
+        // Move to next instruction
+        self.pc = self.pc.wrapping_add(2);
+
+        // Decode and run instruction
+        let decoded = self.decode_at(pc);
+        if !self.platform.supports(&decoded) {
+            self.fault(Chip8Fault::InvalidOpcode { pc, opcode: instruction })?;
+            return Ok(());
+        }
+
+        match decoded {
+            Instruction::ScrollDown { rows } => {
+                display.scroll_down(rows as usize);
+            },
+            Instruction::ScrollUp { rows } => {
+                display.scroll_up(rows as usize);
+            },
+            Instruction::Cls => {
+                display.clear();
+            },
+            Instruction::ScrollRight => {
+                display.scroll_right(4);
+            },
+            Instruction::ScrollLeft => {
+                display.scroll_left(4);
+            },
+            Instruction::Exit => {
+                self.exited = true;
+            },
+            Instruction::Lores => {
+                display.set_hires(false);
+            },
+            Instruction::Hires => {
+                display.set_hires(true);
+            },
+            Instruction::Ret => {
+                if self.sp == 0 {
+                    self.fault(Chip8Fault::StackUnderflow { pc, opcode: instruction })?;
+                    if self.fault_policy == FaultPolicy::Wrap {
+                        self.sp = self.stack.len() as u8;
+                    }
+                }
+
+                if self.sp > 0 {
+                    self.sp -= 1;
+                    self.pc = self.stack[self.sp as usize];
+                }
+            },
+            Instruction::Jump { addr } => {
+                self.pc = addr;
+            },
+            Instruction::Call { addr } => {
+                if self.sp as usize == self.stack.len() {
+                    self.fault(Chip8Fault::StackOverflow { pc, opcode: instruction })?;
+                    if self.fault_policy == FaultPolicy::Wrap {
+                        self.sp = 0;
+                    }
+                }
+
+                if (self.sp as usize) < self.stack.len() {
+                    self.stack[self.sp as usize] = self.pc;
+                    self.sp += 1;
+                    self.pc = addr;
+                }
+            },
+            Instruction::SkipEqByte { x, byte } => {
+                if self.registers[x as usize] == byte {
+                    self.skip();
+                }
+            },
+            Instruction::SkipNeByte { x, byte } => {
+                if self.registers[x as usize] != byte {
+                    self.skip();
+                }
+            },
+            Instruction::SkipEqReg { x, y } => {
+                if self.registers[x as usize] == self.registers[y as usize] {
+                    self.skip();
+                }
+            },
+            Instruction::SaveRange { x, y } => {
+                // I is left alone
+                let registers = register_range(x, y);
+                if self.check_memory(self.i, registers.len(), pc, instruction)? {
+                    for (offset, register) in registers.into_iter().enumerate() {
+                        self.write(self.i as usize + offset, self.registers[register]);
+                    }
+                }
+            },
+            Instruction::LoadRange { x, y } => {
+                let registers = register_range(x, y);
+                if self.check_memory(self.i, registers.len(), pc, instruction)? {
+                    for (offset, register) in registers.into_iter().enumerate() {
+                        self.registers[register] = self.read(self.i as usize + offset);
+                    }
+                }
+            },
+            Instruction::LoadByte { x, byte } => {
+                self.registers[x as usize] = byte;
+            },
+            Instruction::AddByte { x, byte } => {
+                // Set register = register + value, VF is left alone
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(byte);
+            },
+            Instruction::LoadReg { x, y } => {
+                self.registers[x as usize] = self.registers[y as usize];
+            },
+            Instruction::Or { x, y } => {
+                self.registers[x as usize] |= self.registers[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
+            },
+            Instruction::And { x, y } => {
+                self.registers[x as usize] &= self.registers[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
+            },
+            Instruction::Xor { x, y } => {
+                self.registers[x as usize] ^= self.registers[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
+            },
+            Instruction::AddReg { x, y } => {
+                let (val, overflow) = self.registers[x as usize].overflowing_add(self.registers[y as usize]);
+                self.registers[x as usize] = val;
+                self.registers[0xF] = overflow as u8;
+            },
+            Instruction::Sub { x, y } => {
+                // VF is set to NOT borrow
+                let (val, overflow) = self.registers[x as usize].overflowing_sub(self.registers[y as usize]);
+                self.registers[x as usize] = val;
+                self.registers[0xF] = !overflow as u8;
+            },
+            Instruction::ShiftRight { x, y } => {
+                let source = if self.quirks.shift_uses_vy { y } else { x };
+                let value = self.registers[source as usize];
+                self.registers[x as usize] = value >> 1;
+                self.registers[0xF] = value & 0x1;
+            },
+            Instruction::SubN { x, y } => {
+                let (val, overflow) = self.registers[y as usize].overflowing_sub(self.registers[x as usize]);
+                self.registers[x as usize] = val;
+                self.registers[0xF] = !overflow as u8;
+            },
+            Instruction::ShiftLeft { x, y } => {
+                let source = if self.quirks.shift_uses_vy { y } else { x };
+                let value = self.registers[source as usize];
+                self.registers[x as usize] = value << 1;
+                self.registers[0xF] = (value & 0x80) >> 7;
+            },
+            Instruction::SkipNeReg { x, y } => {
+                if self.registers[x as usize] != self.registers[y as usize] {
+                    self.skip();
+                }
+            },
+            Instruction::LoadI { addr } => {
+                self.i = addr;
+            },
+            Instruction::JumpV0 { addr } => {
+                // BXNN jumps relative to VX on SUPER-CHIP
+                let register = if self.quirks.jump_uses_vx { (addr >> 8) as usize } else { 0x0 };
+                self.pc = addr + self.registers[register] as u16;
+            },
+            Instruction::Random { x, byte } => {
+                self.registers[x as usize] = self.rng.next_byte() & byte;
+            },
+            Instruction::Draw { x, y, rows } => {
+                let x = self.registers[x as usize] as usize % display.width();
+                let y = self.registers[y as usize] as usize % display.height();
+
+                // DXY0 draws a 16x16 sprite made of two bytes per row, on
+                // CHIP-8 it draws 0 rows like the original interpreter did
+                let (width, rows) = match rows {
+                    0 if self.platform != Platform::Chip8 => (16, 16),
+                    _ => (8, rows as usize)
+                };
+                let bytes_per_row = width / 8;
+                let sprite_size = rows * bytes_per_row;
+
+                // With both XO-CHIP planes selected the sprite for plane 2
+                // follows the one for plane 1
+                let planes: Vec<u8> = [0b01, 0b10].into_iter()
+                                        .filter(|plane| display.selected_planes() & plane != 0)
+                                        .collect();
+
+                self.registers[0xF] = 0;
+
+                if !self.check_memory(self.i, sprite_size * planes.len(), pc, instruction)? {
+                    return Ok(());
+                }
+
+                if self.quirks.wait_for_vblank {
+                    self.waiting_for_vblank = true;
+                }
+
+                let clip = self.quirks.clip_sprites;
+
+                // Draw pixels (each byte is a row starting at x, y). Each bit in the byte is a pixel (i.e. 0x00111100 would be __####__)
+                for (index, plane) in planes.into_iter().enumerate() {
+                    let sprite = self.i as usize + (index * sprite_size);
+                    for row in 0..rows {
+                        if clip && y + row >= display.height() {
+                            break;
+                        }
+                        let pixel_y = (y + row) % display.height();
+
+                        // Row data left aligned in 16 bits
+                        let address = sprite + (row * bytes_per_row);
+                        let mut row_bits = (self.read(address) as u16) << 8;
+                        if bytes_per_row == 2 {
+                            row_bits |= self.read(address + 1) as u16;
+                        }
+
+                        // Each bit in row is a pixel starting at x, y and moving to the right (xor bit with pixel)
+                        for column in 0..width {
+                            // Sprites are either clipped or wrapped at the right edge of the screen
+                            if clip && x + column >= display.width() {
+                                break;
+                            }
+                            let pixel_x = (x + column) % display.width();
+
+                            // If the current pixel we are looking at in the row is 1 XOR it onto the screen
+                            if ((row_bits >> (15 - column)) & 0x1) == 1 {
+
+                                // Flip the pixel, if it was already on set VF to 1
+                                if display.flip_pixel_in(pixel_x, pixel_y, plane) {
+                                    self.registers[0xF] = 1;
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            Instruction::SkipKey { x } => {
+                let key = (self.registers[x as usize] & 0xF) as usize;
+                if keyboard[key] {
+                    self.skip();
+                }
+            },
+            Instruction::SkipNotKey { x } => {
+                let key = (self.registers[x as usize] & 0xF) as usize;
+                if !keyboard[key] {
+                    self.skip();
+                }
+            },
+            Instruction::LoadLongI { addr } => {
+                // Step over the address word
+                self.i = addr;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::SelectPlanes { mask } => {
+                display.select_planes(mask);
+            },
+            Instruction::LoadAudio => {
+                let mut pattern = [0; 16];
+                if self.check_memory(self.i, pattern.len(), pc, instruction)? {
+                    for (offset, byte) in pattern.iter_mut().enumerate() {
+                        *byte = self.read(self.i as usize + offset);
+                    }
+                    self.audio_pattern = Some(pattern);
+                }
+            },
+            Instruction::LoadDelay { x } => {
+                self.registers[x as usize] = self.timers.dt;
+            },
+            Instruction::WaitKey { x } => {
+                // Check if a key is pressed
+                let mut pressed = false;
+                for (key, state) in keyboard.iter().enumerate() {
+                    if *state {
+                        pressed = true;
+                        self.registers[x as usize] = key as u8;
+                    }
+                }
+
+                // If no keys are pressed, decrement pc
+                if !pressed {
+                    // Decrement pc, essentially causing the program to loop
+                    self.pc = pc;
+                }
+            },
+            Instruction::SetDelay { x } => {
+                self.timers.dt = self.registers[x as usize];
+            },
+            Instruction::SetSound { x } => {
+                self.timers.st = self.registers[x as usize];
+            },
+            Instruction::AddI { x } => {
+                self.i = self.i.wrapping_add(self.registers[x as usize] as u16);
+            },
+            Instruction::LoadFont { x } => {
+                let key = (self.registers[x as usize] & 0xF) as u16;
+                self.i = FONT_START + (key * 5);
+            },
+            Instruction::LoadBigFont { x } => {
+                let key = (self.registers[x as usize] & 0xF) as u16;
+                self.i = BIG_FONT_START + (key * 10);
+            },
+            Instruction::SetPitch { x } => {
+                self.pitch = self.registers[x as usize];
+            },
+            Instruction::StoreBcd { x } => {
+                let val = self.registers[x as usize];
+                let ones = val % 10;
+                let tens = (val / 10) % 10;
+                let hundreds = (val / 100) % 10;
+
+                if self.check_memory(self.i, 3, pc, instruction)? {
+                    self.write(self.i as usize, hundreds);
+                    self.write(self.i as usize + 1, tens);
+                    self.write(self.i as usize + 2, ones);
+                }
+            },
+            Instruction::Store { x } => {
+                // Store all registers from 0..=x into memory starting at i
+                let x = x as usize;
+                if self.check_memory(self.i, x + 1, pc, instruction)? {
+                    for index in 0..=x {
+                        self.write(self.i as usize + index, self.registers[index]);
+                    }
+                }
+
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+            },
+            Instruction::Load { x } => {
+                let x = x as usize;
+                if self.check_memory(self.i, x + 1, pc, instruction)? {
+                    for index in 0..=x {
+                        self.registers[index] = self.read(self.i as usize + index);
+                    }
+                }
+
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+            },
+            Instruction::StoreFlags { x } => {
+                let x = x as usize;
+                self.rpl[..=x].copy_from_slice(&self.registers[..=x]);
+            },
+            Instruction::LoadFlags { x } => {
+                let x = x as usize;
+                self.registers[..=x].copy_from_slice(&self.rpl[..=x]);
+            },
+            Instruction::Invalid { .. } => {
+                self.fault(Chip8Fault::InvalidOpcode { pc, opcode: instruction })?;
+            }
+        }
+
+        // The next instruction has to be fully inside memory
+        if self.pc as usize + 1 >= self.ram.len() {
+            self.fault(Chip8Fault::PcOutOfRange { pc, opcode: instruction, target: self.pc as usize })?;
+            // Landing on the last byte would fault again on every tick, so
+            // an instruction hanging off the end starts over at 0
+            let target = self.pc as usize % self.ram.len();
+            self.pc = if target + 1 >= self.ram.len() { 0 } else { target as u16 };
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,622 @@
+//! Pausing, stepping and inspecting a running machine.
+//!
+//! The [`Debugger`] sits between a frontend and its [`Chip8Machine`]: instead
+//! of calling [`Chip8Machine::run_frame`] the frontend calls
+//! [`Debugger::run_frame`], which does nothing while paused and otherwise
+//! runs the frame one instruction at a time, stopping on breakpoints,
+//! watchpoints, faults and finished steps.
+//!
+//! Commands are plain text (see [`HELP`]) so any frontend can offer a REPL
+//! by feeding lines to [`Debugger::execute`] and printing what comes back.
+//! Views added with `display` are shown again by [`Debugger::views`] every
+//! time the machine stops, so they follow along while stepping.
+
+use std::{collections::BTreeSet, fmt, fmt::Write};
+
+use crate::{
+    fault::Chip8Fault,
+    instruction::Instruction,
+    machine::Chip8Machine,
+    platform::Platform
+};
+
+pub const HELP: &str = "\
+c, continue             resume
+p, pause                pause
+s, step [n]             execute n instructions (default 1)
+n, next                 step over a CALL
+f, finish               run until the current subroutine returns
+b, break <addr>         add a breakpoint
+d, delete <addr>        remove a breakpoint
+w, watch <addr> [r|w|rw] add a memory watchpoint (default w)
+u, unwatch <addr>       remove a watchpoint
+l, list                 list breakpoints and watchpoints
+r, regs                 show V0-VF, I, PC, SP, DT and ST
+stack                   show the stack
+x <addr> [len]          dump memory (default 16 bytes)
+display [regs|stack|<addr> [len]]
+                        show registers, the stack or memory every time
+                        the machine stops (no argument shows them now)
+undisplay               stop showing them
+dis [addr] [count]      disassemble (default PC, 8 instructions)
+set <reg> <value>       set V0-VF, I, PC, SP, DT or ST
+poke <addr> <byte>...   write bytes to memory
+h, help                 show this
+
+Numbers are decimal, or hex with a 0x prefix.";
+
+/// Kind of memory access a watchpoint triggers on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite
+}
+
+impl Access {
+    fn matches(&self, access: Access) -> bool {
+        *self == Access::ReadWrite || *self == access
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::ReadWrite => write!(f, "read/write")
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u16,
+    pub access: Access
+}
+
+/// Why the debugger paused
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// About to execute the instruction at `pc`
+    Breakpoint { pc: u16 },
+    /// The instruction at `pc` accessed a watched address
+    Watchpoint { pc: u16, addr: u16, access: Access },
+    /// A step, step over or step out finished
+    Step,
+    Fault(Chip8Fault),
+    /// The program ran 00FD
+    Exited
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint { pc } => write!(f, "breakpoint at {:#05X}", pc),
+            StopReason::Watchpoint { pc, addr, access } => write!(f, "{} of {:#05X} by {:#05X}", access, addr, pc),
+            StopReason::Step => write!(f, "step"),
+            StopReason::Fault(fault) => write!(f, "{}", fault),
+            StopReason::Exited => write!(f, "program exited")
+        }
+    }
+}
+
+/// Something `display` shows every time the machine stops
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum View {
+    Registers,
+    Stack,
+    Memory { addr: u16, len: usize }
+}
+
+/// Where a resumed run should stop on its own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    /// Run freely
+    None,
+    /// After this many more instructions
+    Steps(u32),
+    /// When execution is back at `pc` with the same stack depth
+    Return { pc: u16, sp: u8 },
+    /// When the stack is shallower than `sp`
+    Out { sp: u8 }
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    views: Vec<View>,
+    paused: bool,
+    target: Target,
+    // Set on resume so a breakpoint on the current PC doesn't stop it again
+    resuming: bool
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    /// A debugger that starts paused
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            views: Vec::new(),
+            paused: true,
+            target: Target::None,
+            resuming: false
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.target = Target::None;
+    }
+
+    pub fn resume(&mut self) {
+        self.resume_until(Target::None);
+    }
+
+    fn resume_until(&mut self, target: Target) {
+        self.paused = false;
+        self.target = target;
+        self.resuming = true;
+    }
+
+    /// Execute `count` instructions
+    pub fn step(&mut self, count: u32) {
+        self.resume_until(Target::Steps(count.max(1)));
+    }
+
+    /// Step, but run a CALL through to its return
+    pub fn step_over(&mut self, machine: &Chip8Machine) {
+        let pc = machine.cpu.pc;
+        match machine.cpu.decode_at(pc) {
+            instruction @ Instruction::Call { .. } => self.resume_until(Target::Return {
+                pc: pc.wrapping_add(instruction.size()),
+                sp: machine.cpu.sp
+            }),
+            _ => self.step(1)
+        }
+    }
+
+    /// Run until the current subroutine returns. Returns false outside of one.
+    pub fn step_out(&mut self, machine: &Chip8Machine) -> bool {
+        if machine.cpu.sp == 0 {
+            return false;
+        }
+
+        self.resume_until(Target::Out { sp: machine.cpu.sp });
+        true
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Watch `addr`, replacing any watchpoint already on it
+    pub fn add_watchpoint(&mut self, addr: u16, access: Access) {
+        self.remove_watchpoint(addr);
+        self.watchpoints.push(Watchpoint { addr, access });
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.addr != addr);
+        self.watchpoints.len() != count
+    }
+
+    /// Show `view` every time the machine stops, unless it already is
+    pub fn add_view(&mut self, view: View) {
+        if !self.views.contains(&view) {
+            self.views.push(view);
+        }
+    }
+
+    pub fn clear_views(&mut self) {
+        self.views.clear();
+    }
+
+    /// Everything added with [`Debugger::add_view`] as it is now, for the
+    /// frontend to show after every stop
+    pub fn views(&self, machine: &Chip8Machine) -> String {
+        let views: Vec<String> = self.views.iter().map(|view| match *view {
+            View::Registers => registers(machine),
+            View::Stack => stack(machine),
+            View::Memory { addr, len } => dump(machine, addr, len)
+        }).collect();
+
+        views.join("\n")
+    }
+
+    /// Run the rest of the current frame unless paused. Returns why it
+    /// stopped if it paused part way through.
+    pub fn run_frame(&mut self, machine: &mut Chip8Machine) -> Option<StopReason> {
+        while !self.paused {
+            match self.tick(machine) {
+                Ok(false) => (),
+                Ok(true) => return None,
+                Err(reason) => {
+                    self.pause();
+                    return Some(reason);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// One machine tick with all the checks around it. Returns whether it
+    /// ended a frame, or the reason to stop.
+    fn tick(&mut self, machine: &mut Chip8Machine) -> Result<bool, StopReason> {
+        let cpu = &machine.cpu;
+        let pc = cpu.pc;
+        let executes = !cpu.waiting_for_vblank && cpu.halted.is_none() && !cpu.exited;
+
+        if executes && !self.resuming && self.breakpoints.contains(&pc) {
+            return Err(StopReason::Breakpoint { pc });
+        }
+        self.resuming = false;
+
+        let access = if executes { memory_access(machine) } else { None };
+        let new_frame = machine.tick().map_err(StopReason::Fault)?;
+
+        if let Some((kind, start, len)) = access {
+            let size = machine.cpu.ram.len();
+            for watchpoint in &self.watchpoints {
+                let offset = (watchpoint.addr as usize + size - start % size) % size;
+                if offset < len && watchpoint.access.matches(kind) {
+                    return Err(StopReason::Watchpoint { pc, addr: watchpoint.addr, access: kind });
+                }
+            }
+        }
+
+        if machine.has_exited() {
+            return Err(StopReason::Exited);
+        }
+
+        if executes {
+            let done = match &mut self.target {
+                Target::None => false,
+                Target::Steps(count) => {
+                    *count -= 1;
+                    *count == 0
+                },
+                Target::Return { pc, sp } => machine.cpu.pc == *pc && machine.cpu.sp == *sp,
+                Target::Out { sp } => machine.cpu.sp < *sp
+            };
+
+            if done {
+                return Err(StopReason::Step);
+            }
+        }
+
+        Ok(new_frame)
+    }
+
+    /// Run one command line and return what to print
+    pub fn execute(&mut self, machine: &mut Chip8Machine, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(String::new());
+        };
+
+        let arg = |index: usize| -> Result<u32, String> {
+            let text = args.get(index).ok_or(format!("{} needs more arguments", command))?;
+            parse_number(text)
+        };
+        let address = |index: usize| -> Result<u16, String> {
+            let addr = arg(index)?;
+            if addr as usize >= machine.cpu.ram.len() {
+                return Err(format!("{:#X} is outside of memory", addr));
+            }
+            Ok(addr as u16)
+        };
+
+        // A halted CPU won't run again, so say why once and stay paused
+        // instead of stopping on the same fault over and over
+        if matches!(command, "c" | "continue" | "s" | "step" | "n" | "next" | "f" | "finish")
+            && let Some(fault) = machine.halted() {
+            self.pause();
+            return Err(format!("Can't run, the CPU halted: {}", fault));
+        }
+
+        match command {
+            "c" | "continue" => {
+                self.resume();
+                Ok(String::from("Running"))
+            },
+            "p" | "pause" => {
+                self.pause();
+                Ok(self.with_views(machine, location(machine)))
+            },
+            "s" | "step" => {
+                let count = if args.is_empty() { 1 } else { arg(0)? };
+                self.step(count);
+                Ok(String::new())
+            },
+            "n" | "next" => {
+                self.step_over(machine);
+                Ok(String::new())
+            },
+            "f" | "finish" => {
+                if self.step_out(machine) {
+                    Ok(String::new())
+                } else {
+                    Err(String::from("Not inside a subroutine"))
+                }
+            },
+            "b" | "break" => {
+                let addr = address(0)?;
+                self.add_breakpoint(addr);
+                Ok(format!("Breakpoint at {:#05X}", addr))
+            },
+            "d" | "delete" => {
+                let addr = address(0)?;
+                if self.remove_breakpoint(addr) {
+                    Ok(format!("Removed breakpoint at {:#05X}", addr))
+                } else {
+                    Err(format!("No breakpoint at {:#05X}", addr))
+                }
+            },
+            "w" | "watch" => {
+                let addr = address(0)?;
+                let access = match args.get(1).copied() {
+                    None | Some("w") => Access::Write,
+                    Some("r") => Access::Read,
+                    Some("rw") => Access::ReadWrite,
+                    Some(other) => return Err(format!("Unknown access '{}', use r, w or rw", other))
+                };
+                self.add_watchpoint(addr, access);
+                Ok(format!("Watching {:#05X} for {}", addr, access))
+            },
+            "u" | "unwatch" => {
+                let addr = address(0)?;
+                if self.remove_watchpoint(addr) {
+                    Ok(format!("Removed watchpoint at {:#05X}", addr))
+                } else {
+                    Err(format!("No watchpoint at {:#05X}", addr))
+                }
+            },
+            "l" | "list" => {
+                let mut output = String::new();
+                for pc in self.breakpoints() {
+                    let _ = writeln!(output, "break {:#05X}", pc);
+                }
+                for watchpoint in self.watchpoints() {
+                    let _ = writeln!(output, "watch {:#05X} {}", watchpoint.addr, watchpoint.access);
+                }
+                Ok(output.trim_end().to_string())
+            },
+            "r" | "regs" => Ok(registers(machine)),
+            "stack" => Ok(stack(machine)),
+            "x" => {
+                let addr = address(0)?;
+                let len = if args.len() > 1 { arg(1)? as usize } else { 16 };
+                // Stop at the end of memory instead of wrapping around
+                let left = machine.cpu.ram.len() - addr as usize;
+                let mut output = dump(machine, addr, len.min(left));
+                if len > left {
+                    let _ = write!(output, "\n(only {} bytes left before the end of memory)", left);
+                }
+                Ok(output)
+            },
+            "dis" => {
+                let addr = if args.is_empty() { machine.cpu.pc } else { address(0)? };
+                let count = if args.len() > 1 { arg(1)? } else { 8 };
+                let mut output = disassemble(machine, addr, count);
+                let shown = output.lines().count();
+                if shown < count as usize {
+                    if shown > 0 {
+                        output.push('\n');
+                    }
+                    let _ = write!(output, "(only {} instructions left before the end of memory)", shown);
+                }
+                Ok(output)
+            },
+            "set" => {
+                let register = args.first().ok_or("set needs a register and a value")?.to_ascii_uppercase();
+                let value = arg(1)?;
+                set_register(machine, &register, value)?;
+                Ok(self.with_views(machine, registers(machine)))
+            },
+            "poke" => {
+                let addr = address(0)?;
+                if args.len() < 2 {
+                    return Err(String::from("poke needs at least one byte"));
+                }
+                for (offset, text) in args[1..].iter().enumerate() {
+                    let byte = parse_number(text)?;
+                    if byte > 0xFF {
+                        return Err(format!("{} doesn't fit in a byte", text));
+                    }
+                    let index = (addr as usize + offset) % machine.cpu.ram.len();
+                    machine.cpu.ram[index] = byte as u8;
+                }
+                Ok(self.with_views(machine, dump(machine, addr, args.len() - 1)))
+            },
+            "display" => {
+                let view = match args.first().copied() {
+                    None => return Ok(self.views(machine)),
+                    Some("r" | "regs") => View::Registers,
+                    Some("stack") => View::Stack,
+                    Some(_) => {
+                        let addr = address(0)?;
+                        let len = if args.len() > 1 { arg(1)? as usize } else { 16 };
+                        View::Memory { addr, len: len.min(machine.cpu.ram.len() - addr as usize) }
+                    }
+                };
+                self.add_view(view);
+                Ok(self.views(machine))
+            },
+            "undisplay" => {
+                self.clear_views();
+                Ok(String::new())
+            },
+            "h" | "help" => Ok(String::from(HELP)),
+            _ => Err(format!("Unknown command '{}', try help", command))
+        }
+    }
+
+    /// `output`, followed by the views if there are any
+    fn with_views(&self, machine: &Chip8Machine, output: String) -> String {
+        let views = self.views(machine);
+        if views.is_empty() {
+            output
+        } else {
+            format!("{}\n{}", output, views)
+        }
+    }
+}
+
+/// The memory the next instruction will read or write: kind, start and length
+fn memory_access(machine: &Chip8Machine) -> Option<(Access, usize, usize)> {
+    let cpu = &machine.cpu;
+    let i = cpu.i as usize;
+
+    match cpu.decode_at(cpu.pc) {
+        Instruction::Draw { rows, .. } => {
+            let sprite_size = if rows == 0 && cpu.platform != Platform::Chip8 { 32 } else { rows as usize };
+            let planes = machine.display.selected_planes().count_ones() as usize;
+            Some((Access::Read, i, sprite_size * planes))
+        },
+        Instruction::SaveRange { x, y } => Some((Access::Write, i, x.abs_diff(y) as usize + 1)),
+        Instruction::LoadRange { x, y } => Some((Access::Read, i, x.abs_diff(y) as usize + 1)),
+        Instruction::LoadAudio => Some((Access::Read, i, 16)),
+        Instruction::StoreBcd { .. } => Some((Access::Write, i, 3)),
+        Instruction::Store { x } => Some((Access::Write, i, x as usize + 1)),
+        Instruction::Load { x } => Some((Access::Read, i, x as usize + 1)),
+        _ => None
+    }
+}
+
+fn parse_number(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse()
+    };
+
+    parsed.map_err(|_| format!("Invalid number '{}'", text))
+}
+
+fn set_register(machine: &mut Chip8Machine, register: &str, value: u32) -> Result<(), String> {
+    let cpu = &mut machine.cpu;
+    let byte = || u8::try_from(value).map_err(|_| format!("{:#X} doesn't fit in {}", value, register));
+
+    match register {
+        "I" => cpu.i = u16::try_from(value).map_err(|_| format!("{:#X} doesn't fit in I", value))?,
+        "PC" => {
+            if value as usize >= cpu.ram.len() {
+                return Err(format!("{:#X} is outside of memory", value));
+            }
+            cpu.pc = value as u16;
+        },
+        "SP" => {
+            if value as usize > cpu.stack.len() {
+                return Err(format!("SP can be at most {}", cpu.stack.len()));
+            }
+            cpu.sp = value as u8;
+        },
+        "DT" => cpu.timers.dt = byte()?,
+        "ST" => cpu.timers.st = byte()?,
+        _ => {
+            let index = register.strip_prefix('V')
+                            .filter(|digit| digit.len() == 1)
+                            .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                            .ok_or(format!("Unknown register '{}'", register))?;
+            cpu.registers[index as usize] = byte()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The PC and the instruction there
+pub fn location(machine: &Chip8Machine) -> String {
+    let pc = machine.cpu.pc;
+    format!("{:04X}  {:04X}  {}", pc, machine.cpu.fetch_at(pc), machine.cpu.decode_at(pc))
+}
+
+pub fn registers(machine: &Chip8Machine) -> String {
+    let cpu = &machine.cpu;
+    let mut output = String::new();
+
+    for (index, value) in cpu.registers.iter().enumerate() {
+        let _ = write!(output, "V{:X}={:02X}{}", index, value, if index % 8 == 7 { "\n" } else { " " });
+    }
+    let _ = write!(output, "I={:04X} PC={:04X} SP={:X} DT={:02X} ST={:02X}", cpu.i, cpu.pc, cpu.sp, cpu.timers.dt, cpu.timers.st);
+
+    output
+}
+
+pub fn stack(machine: &Chip8Machine) -> String {
+    let cpu = &machine.cpu;
+    if cpu.sp == 0 {
+        return String::from("Stack is empty");
+    }
+
+    // Innermost call first
+    let mut output = String::new();
+    for depth in (0..cpu.sp as usize).rev() {
+        let _ = writeln!(output, "#{:<2} {:04X}", depth, cpu.stack[depth]);
+    }
+
+    output.trim_end().to_string()
+}
+
+pub fn dump(machine: &Chip8Machine, addr: u16, len: usize) -> String {
+    let ram = &machine.cpu.ram;
+    let mut output = String::new();
+
+    for row in (0..len).step_by(16) {
+        let start = addr as usize + row;
+        let _ = write!(output, "{:04X} ", start % ram.len());
+        for offset in row..(row + 16).min(len) {
+            let _ = write!(output, " {:02X}", ram[(addr as usize + offset) % ram.len()]);
+        }
+        output.push('\n');
+    }
+
+    output.trim_end().to_string()
+}
+
+/// Disassemble `count` instructions from `addr`, or as many as fit before
+/// the end of memory
+pub fn disassemble(machine: &Chip8Machine, addr: u16, count: u32) -> String {
+    let cpu = &machine.cpu;
+    let mut output = String::new();
+    // Counted past u16 so the end of 64K XO-CHIP memory doesn't wrap
+    let mut position = addr as usize;
+
+    for _ in 0..count {
+        let pc = position as u16;
+        let instruction = cpu.decode_at(pc);
+        position += instruction.size() as usize;
+        if position > cpu.ram.len() {
+            break;
+        }
+        let marker = if pc == cpu.pc { ">" } else { " " };
+        let _ = writeln!(output, "{}{:04X}  {:04X}  {}", marker, pc, cpu.fetch_at(pc), instruction);
+    }
+
+    output.trim_end().to_string()
+}
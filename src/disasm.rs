@@ -0,0 +1,245 @@
+//! Disassembler that separates code from data.
+//!
+//! [`disassemble`] follows the control flow of a ROM from 0x200: jumps,
+//! calls, both sides of every skip and the jump tables `BNNN` points at.
+//! Whatever that reaches is code, everything else is data. The result is
+//! Octo source that [`crate::asm::assemble`] turns back into the same ROM:
+//! jump and call targets get labels, and data is written one byte per line
+//! with the bits drawn as sprite art in a comment.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    cpu::PROGRAM_START,
+    instruction::{decode_long, Instruction},
+    platform::Platform
+};
+
+/// Most entries followed in a `BNNN` jump table, enough for any V0
+const JUMP_TABLE_ENTRIES: usize = 128;
+
+/// What the analysis found out about a ROM
+pub struct Analysis {
+    /// Instructions by address, for every address reached as code
+    pub code: BTreeMap<u16, Instruction>,
+    /// Labels by address
+    pub labels: BTreeMap<u16, String>
+}
+
+/// Kinds of label, in order of preference when an address gets several
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Main,
+    Sub,
+    Label,
+    Table,
+    Data
+}
+
+/// Follow every path through `rom` from 0x200
+pub fn analyze(rom: &[u8], platform: Platform) -> Analysis {
+    let start = PROGRAM_START as usize;
+    let end = start + rom.len();
+    let in_rom = |addr: usize| (start..end).contains(&addr);
+
+    let word = |addr: usize| -> u16 {
+        let byte = |addr: usize| if in_rom(addr) { rom[addr - start] as u16 } else { 0 };
+        (byte(addr) << 8) | byte(addr + 1)
+    };
+    let decode_at = |addr: usize| decode_long(word(addr), word(addr + 2));
+
+    let mut code = BTreeMap::new();
+    let mut kinds: BTreeMap<u16, LabelKind> = BTreeMap::new();
+    let mut label = |addr: u16, kind: LabelKind| {
+        if in_rom(addr as usize) {
+            let entry = kinds.entry(addr).or_insert(kind);
+            *entry = (*entry).min(kind);
+        }
+    };
+
+    label(PROGRAM_START, LabelKind::Main);
+    let mut pending = vec![start];
+
+    while let Some(addr) = pending.pop() {
+        let instruction = decode_at(addr);
+        let size = instruction.size() as usize;
+
+        // Stop at anything that can't run here, it's probably data
+        if !in_rom(addr) || !in_rom(addr + size - 1) || code.contains_key(&(addr as u16)) || !platform.supports(&instruction) {
+            continue;
+        }
+        code.insert(addr as u16, instruction);
+
+        let next = addr + size;
+        match instruction {
+            Instruction::Ret | Instruction::Exit => (),
+            Instruction::Jump { addr: target } => {
+                // A jump to itself is how most ROMs stop
+                label(target, LabelKind::Label);
+                pending.push(target as usize);
+            },
+            Instruction::Call { addr: target } => {
+                label(target, LabelKind::Sub);
+                pending.push(target as usize);
+                pending.push(next);
+            },
+            Instruction::JumpV0 { addr: table } => {
+                // Assume a table of jumps, the usual way BNNN is used
+                label(table, LabelKind::Table);
+                for entry in 0..JUMP_TABLE_ENTRIES {
+                    let entry = table as usize + entry * 2;
+                    if !in_rom(entry) || !matches!(decode_at(entry), Instruction::Jump { .. }) {
+                        break;
+                    }
+                    pending.push(entry);
+                }
+            },
+            Instruction::SkipEqByte { .. }
+            | Instruction::SkipNeByte { .. }
+            | Instruction::SkipEqReg { .. }
+            | Instruction::SkipNeReg { .. }
+            | Instruction::SkipKey { .. }
+            | Instruction::SkipNotKey { .. } => {
+                // XO-CHIP skips over the whole of a long instruction
+                let skipped = if platform == Platform::XoChip { decode_at(next).size() as usize } else { 2 };
+                pending.push(next);
+                pending.push(next + skipped);
+            },
+            Instruction::LoadI { addr: data } | Instruction::LoadLongI { addr: data } => {
+                label(data, LabelKind::Data);
+                pending.push(next);
+            },
+            _ => pending.push(next)
+        }
+    }
+
+    let labels = kinds.into_iter()
+                    .map(|(addr, kind)| {
+                        let name = match kind {
+                            LabelKind::Main => String::from("main"),
+                            LabelKind::Sub => format!("sub_{:04x}", addr),
+                            LabelKind::Label => format!("label_{:04x}", addr),
+                            LabelKind::Table => format!("table_{:04x}", addr),
+                            LabelKind::Data => format!("data_{:04x}", addr)
+                        };
+                        (addr, name)
+                    })
+                    .collect();
+
+    Analysis { code, labels }
+}
+
+/// Disassemble `rom` into Octo source
+pub fn disassemble(rom: &[u8], platform: Platform) -> String {
+    let analysis = analyze(rom, platform);
+    let start = PROGRAM_START as usize;
+    let end = start + rom.len();
+
+    let mut output = String::new();
+    let mut addr = start;
+    while addr < end {
+        if let Some(name) = analysis.labels.get(&(addr as u16)) {
+            output.push_str(&format!("\n: {}\n", name));
+        }
+
+        let instruction = analysis.code.get(&(addr as u16));
+        let size = instruction.map(|instruction| instruction.size() as usize).unwrap_or(1);
+
+        // Octo can put a label on the second byte of an instruction with
+        // :next, anything else inside one means it has to be written as data
+        let inner_labels: Vec<usize> = (addr + 1..addr + size).filter(|inner| analysis.labels.contains_key(&(*inner as u16))).collect();
+        let fits = inner_labels.iter().all(|inner| *inner == addr + 1) && inner_labels.iter().all(|inner| !analysis.code.contains_key(&(*inner as u16)));
+
+        match instruction {
+            Some(instruction) if fits => {
+                if let Some(inner) = inner_labels.first() {
+                    output.push_str(&format!(":next {}\n", analysis.labels[&(*inner as u16)]));
+                }
+
+                let bytes: Vec<String> = rom[addr - start..addr - start + size].iter().map(|byte| format!("{:02X}", byte)).collect();
+                let text = octo(instruction, &analysis.labels);
+                output.push_str(&format!("  {:<28}# {:04X}: {}\n", text, addr, bytes.join("")));
+                addr += size;
+            },
+            _ => {
+                let byte = rom[addr - start];
+                output.push_str(&format!("  {:<28}# {:04X}: {}\n", format!("0x{:02X}", byte), addr, sprite_art(byte)));
+                addr += 1;
+            }
+        }
+    }
+
+    output.trim_start().to_string()
+}
+
+/// A byte as 8 pixels, `#` for set and `.` for clear
+pub fn sprite_art(byte: u8) -> String {
+    (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect()
+}
+
+/// An instruction in Octo's syntax, with labels for known addresses
+fn octo(instruction: &Instruction, labels: &BTreeMap<u16, String>) -> String {
+    let target = |addr: u16| labels.get(&addr).cloned().unwrap_or_else(|| format!("0x{:03X}", addr));
+
+    match *instruction {
+        Instruction::ScrollDown { rows } => format!("scroll-down {}", rows),
+        Instruction::ScrollUp { rows } => format!("scroll-up {}", rows),
+        Instruction::Cls => String::from("clear"),
+        Instruction::Ret => String::from("return"),
+        Instruction::ScrollRight => String::from("scroll-right"),
+        Instruction::ScrollLeft => String::from("scroll-left"),
+        Instruction::Exit => String::from("exit"),
+        Instruction::Lores => String::from("lores"),
+        Instruction::Hires => String::from("hires"),
+        Instruction::Jump { addr } => format!("jump {}", target(addr)),
+        Instruction::Call { addr } => match labels.get(&addr) {
+            Some(name) => name.clone(),
+            None => format!(":call 0x{:03X}", addr)
+        },
+        // Octo's "if ... then" skips when the condition is false, so every
+        // skip is written with the opposite condition
+        Instruction::SkipEqByte { x, byte } => format!("if v{:x} != 0x{:02X} then", x, byte),
+        Instruction::SkipNeByte { x, byte } => format!("if v{:x} == 0x{:02X} then", x, byte),
+        Instruction::SkipEqReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SkipNeReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::SkipKey { x } => format!("if v{:x} -key then", x),
+        Instruction::SkipNotKey { x } => format!("if v{:x} key then", x),
+        Instruction::SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+        Instruction::LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        Instruction::LoadByte { x, byte } => format!("v{:x} := 0x{:02X}", x, byte),
+        Instruction::AddByte { x, byte } => format!("v{:x} += 0x{:02X}", x, byte),
+        Instruction::LoadReg { x, y } => format!("v{:x} := v{:x}", x, y),
+        Instruction::Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        Instruction::And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Instruction::Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::AddReg { x, y } => format!("v{:x} += v{:x}", x, y),
+        Instruction::Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+        Instruction::ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::SubN { x, y } => format!("v{:x} =- v{:x}", x, y),
+        Instruction::ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::LoadI { addr } => format!("i := {}", target(addr)),
+        Instruction::JumpV0 { addr } => format!("jump0 {}", target(addr)),
+        Instruction::Random { x, byte } => format!("v{:x} := random 0x{:02X}", x, byte),
+        Instruction::Draw { x, y, rows } => format!("sprite v{:x} v{:x} {}", x, y, rows),
+        Instruction::LoadLongI { addr } => match labels.get(&addr) {
+            Some(name) => format!("i := long {}", name),
+            None => format!("i := long 0x{:04X}", addr)
+        },
+        Instruction::SelectPlanes { mask } => format!("plane {}", mask),
+        Instruction::LoadAudio => String::from("audio"),
+        Instruction::LoadDelay { x } => format!("v{:x} := delay", x),
+        Instruction::WaitKey { x } => format!("v{:x} := key", x),
+        Instruction::SetDelay { x } => format!("delay := v{:x}", x),
+        Instruction::SetSound { x } => format!("buzzer := v{:x}", x),
+        Instruction::AddI { x } => format!("i += v{:x}", x),
+        Instruction::LoadFont { x } => format!("i := hex v{:x}", x),
+        Instruction::LoadBigFont { x } => format!("i := bighex v{:x}", x),
+        Instruction::SetPitch { x } => format!("pitch := v{:x}", x),
+        Instruction::StoreBcd { x } => format!("bcd v{:x}", x),
+        Instruction::Store { x } => format!("save v{:x}", x),
+        Instruction::Load { x } => format!("load v{:x}", x),
+        Instruction::StoreFlags { x } => format!("saveflags v{:x}", x),
+        Instruction::LoadFlags { x } => format!("loadflags v{:x}", x),
+        Instruction::Invalid { opcode } => format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF)
+    }
+}
@@ -0,0 +1,195 @@
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// Number of bit-planes (XO-CHIP has two, everything else only uses the first)
+pub const PLANES: usize = 2;
+
+/// The logical screen of the machine.
+///
+/// Every pixel holds one bit per plane, so a pixel value is 0-3. This is the
+/// source of truth for drawing and collision detection. Turning it into host
+/// pixels is the job of a [`crate::render::Renderer`].
+///
+/// Drawing, clearing and scrolling only touch the planes selected with
+/// [`Display::select_planes`] (XO-CHIP's FN01). Only plane 1 is selected by
+/// default, which makes this behave like a plain 1-bit screen.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    hires: bool,
+    selected: u8,
+    pixels: Vec<u8>
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Self::with_size(DISPLAY_WIDTH, DISPLAY_HEIGHT)
+    }
+
+    pub fn with_size(width: usize, height: usize) -> Self {
+        Display {
+            width,
+            height,
+            hires: false,
+            selected: 0b01,
+            pixels: vec![0; width * height]
+        }
+    }
+
+    /// Rebuild a display from its saved pieces, checking they fit together
+    pub fn from_parts(width: usize, height: usize, hires: bool, selected: u8, pixels: Vec<u8>) -> Result<Self, &'static str> {
+        let size = if hires { (HIRES_WIDTH, HIRES_HEIGHT) } else { (DISPLAY_WIDTH, DISPLAY_HEIGHT) };
+        if (width, height) != size || pixels.len() != width * height {
+            return Err("Display size doesn't match its mode");
+        }
+        if selected > 0b11 || pixels.iter().any(|pixel| *pixel > 0b11) {
+            return Err("Display has planes that don't exist");
+        }
+
+        Ok(
+            Display {
+                width,
+                height,
+                hires,
+                selected,
+                pixels
+            }
+        )
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switch between 64x32 lores and 128x64 hires (SUPER-CHIP). The screen
+    /// is cleared whenever the resolution changes.
+    pub fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+        };
+
+        self.hires = hires;
+        if width != self.width || height != self.height {
+            self.width = width;
+            self.height = height;
+            self.pixels = vec![0; width * height];
+        }
+    }
+
+    /// Bit mask of the planes drawing operations apply to
+    pub fn selected_planes(&self) -> u8 {
+        self.selected
+    }
+
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected = mask & 0b11;
+    }
+
+    /// True if the pixel is set in any plane
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[x + (y * self.width)] != 0
+    }
+
+    /// The pixel's bits for every plane (bit 0 is plane 1)
+    pub fn get_pixel_planes(&self, x: usize, y: usize) -> u8 {
+        self.pixels[x + (y * self.width)]
+    }
+
+    /// Set or unset the pixel in the selected planes
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        let pixel = &mut self.pixels[x + (y * self.width)];
+        if on {
+            *pixel |= self.selected;
+        } else {
+            *pixel &= !self.selected;
+        }
+    }
+
+    /// XOR a pixel on in `plane` (a single plane bit), returning true if it
+    /// was already set there (a collision)
+    pub fn flip_pixel_in(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let pixel = &mut self.pixels[x + (y * self.width)];
+        let collision = *pixel & plane != 0;
+        *pixel ^= plane;
+        collision
+    }
+
+    /// XOR a pixel on in plane 1, returning true if it was already set
+    pub fn flip_pixel(&mut self, x: usize, y: usize) -> bool {
+        self.flip_pixel_in(x, y, 0b01)
+    }
+
+    pub fn clear(&mut self) {
+        let keep = !self.selected;
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= keep;
+        }
+    }
+
+    /// Move a rectangle of pixels in the selected planes by (dx, dy),
+    /// blanking whatever scrolls in
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let selected = self.selected;
+        let source = self.pixels.clone();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let from_x = x as isize - dx;
+                let from_y = y as isize - dy;
+                let inside = from_x >= 0 && from_y >= 0 && (from_x as usize) < self.width && (from_y as usize) < self.height;
+                let moved = if inside {
+                    source[from_x as usize + (from_y as usize * self.width)] & selected
+                } else {
+                    0
+                };
+
+                let pixel = &mut self.pixels[x + (y * self.width)];
+                *pixel = (*pixel & !selected) | moved;
+            }
+        }
+    }
+
+    /// Move everything down by `rows`, blanking the rows scrolled in at the top
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize);
+    }
+
+    /// Move everything up by `rows` (XO-CHIP)
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize));
+    }
+
+    /// Move everything right by `columns`
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.scroll(columns as isize, 0);
+    }
+
+    /// Move everything left by `columns`
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.scroll(-(columns as isize), 0);
+    }
+
+    /// Pixel plane bits in row-major order
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
@@ -0,0 +1,71 @@
+//! Faults raised by the CPU when a ROM does something the machine can't do.
+
+use std::fmt;
+
+/// A fault raised while executing the instruction `opcode` at `pc`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip8Fault {
+    /// CALL with all 16 stack slots in use
+    StackOverflow { pc: u16, opcode: u16 },
+    /// RET with an empty stack
+    StackUnderflow { pc: u16, opcode: u16 },
+    /// The instruction moved the PC to `target`, past the end of memory
+    PcOutOfRange { pc: u16, opcode: u16, target: usize },
+    /// The instruction touched memory at `addr`, past the end of memory
+    MemoryOutOfBounds { pc: u16, opcode: u16, addr: usize },
+    /// The opcode doesn't decode to any instruction
+    InvalidOpcode { pc: u16, opcode: u16 }
+}
+
+impl Chip8Fault {
+    pub fn pc(&self) -> u16 {
+        match *self {
+            Chip8Fault::StackOverflow { pc, .. }
+            | Chip8Fault::StackUnderflow { pc, .. }
+            | Chip8Fault::PcOutOfRange { pc, .. }
+            | Chip8Fault::MemoryOutOfBounds { pc, .. }
+            | Chip8Fault::InvalidOpcode { pc, .. } => pc
+        }
+    }
+
+    pub fn opcode(&self) -> u16 {
+        match *self {
+            Chip8Fault::StackOverflow { opcode, .. }
+            | Chip8Fault::StackUnderflow { opcode, .. }
+            | Chip8Fault::PcOutOfRange { opcode, .. }
+            | Chip8Fault::MemoryOutOfBounds { opcode, .. }
+            | Chip8Fault::InvalidOpcode { opcode, .. } => opcode
+        }
+    }
+}
+
+impl fmt::Display for Chip8Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Chip8Fault::StackOverflow { .. } => write!(f, "stack overflow")?,
+            Chip8Fault::StackUnderflow { .. } => write!(f, "stack underflow")?,
+            Chip8Fault::PcOutOfRange { target, .. } => write!(f, "pc out of range ({:#X})", target)?,
+            Chip8Fault::MemoryOutOfBounds { addr, .. } => write!(f, "memory access out of bounds ({:#X})", addr)?,
+            Chip8Fault::InvalidOpcode { .. } => write!(f, "invalid opcode")?
+        }
+
+        write!(f, " at {:#05X} executing {:#06X}", self.pc(), self.opcode())
+    }
+}
+
+impl std::error::Error for Chip8Fault {}
+
+/// What the CPU does when it hits a fault
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FaultPolicy {
+    /// Stop executing and report the fault from every following tick
+    #[default]
+    Halt,
+    /// Wrap the stack pointer, PC and addresses around and carry on. A PC
+    /// left on the last byte of memory goes to 0. Invalid opcodes are
+    /// treated as no-ops.
+    Wrap,
+    /// Skip the faulting operation and carry on. The PC has nowhere sensible
+    /// to go when it leaves memory, so it wraps just like with `Wrap`.
+    Ignore
+}
@@ -0,0 +1,572 @@
+//! GDB remote serial protocol stub.
+//!
+//! [`GdbServer`] listens on a local TCP port and lets a GDB-compatible client
+//! attach to the running machine. Until a client connects the machine runs
+//! normally; attaching pauses it. Breakpoints and stepping go through a
+//! [`Debugger`], so they behave exactly like the terminal debugger's.
+//!
+//! The register map (also sent as a target description) is:
+//!
+//! ```text
+//! 0-15   V0-VF    8 bit
+//! 16     I        16 bit
+//! 17     PC       16 bit
+//! 18     SP       8 bit (number of return addresses on the stack)
+//! 19     DT       8 bit
+//! 20     ST       8 bit
+//! ```
+//!
+//! Supported packets: `?`, `g`, `G`, `p`, `P`, `m`, `M`, `c`, `s`, `Z0`-`Z4`,
+//! `z0`-`z4`, `D`, `k`, `qSupported`, `qXfer:features:read`, `qAttached`,
+//! `QStartNoAckMode` and the thread queries GDB insists on. Everything else
+//! gets the empty "unsupported" reply. Ctrl-C pauses a running machine.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    thread,
+    time::Duration
+};
+
+use crate::{
+    debugger::{Access, Debugger, StopReason},
+    fault::Chip8Fault,
+    machine::Chip8Machine
+};
+
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Size of each register in bytes, by register number
+const REGISTER_SIZES: [usize; 21] = [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 1, 1, 1];
+
+/// Largest packet we accept or send
+const PACKET_SIZE: usize = 0x4000;
+
+/// Most unread input kept while waiting for the end of a packet. Anything
+/// longer can't be a packet we accept, so it's dropped.
+const INPUT_LIMIT: usize = 2 * PACKET_SIZE;
+
+/// One connected client
+struct Connection {
+    stream: TcpStream,
+    input: Vec<u8>,
+    acks: bool
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        Ok(
+            Connection {
+                stream,
+                input: Vec::new(),
+                acks: true
+            }
+        )
+    }
+
+    /// Read whatever has arrived. Returns false once the client hung up.
+    fn receive(&mut self) -> io::Result<bool> {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(false),
+                Ok(count) => self.input.extend_from_slice(&buffer[..count]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(error)
+            }
+        }
+    }
+
+    /// Take the next complete packet (or Ctrl-C, returned as "\x03") out of
+    /// the input
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let Some(&first) = self.input.first() else {
+                return Ok(None);
+            };
+
+            match first {
+                0x03 => {
+                    self.input.remove(0);
+                    return Ok(Some(String::from("\x03")));
+                },
+                b'$' => {
+                    let Some(end) = self.input.iter().position(|byte| *byte == b'#') else {
+                        if self.input.len() > INPUT_LIMIT {
+                            self.input.clear();
+                        }
+                        return Ok(None);
+                    };
+                    if self.input.len() < end + 3 {
+                        return Ok(None);
+                    }
+
+                    let body = self.input[1..end].to_vec();
+                    let checksum = std::str::from_utf8(&self.input[end + 1..end + 3]).ok()
+                                    .and_then(|text| u8::from_str_radix(text, 16).ok());
+                    self.input.drain(..end + 3);
+
+                    let valid = checksum == Some(checksum_of(&body));
+                    if self.acks {
+                        self.write(if valid { b"+" } else { b"-" })?;
+                    }
+                    if valid {
+                        return Ok(Some(String::from_utf8_lossy(&body).into_owned()));
+                    }
+                },
+                // Acks from the client and line noise
+                _ => {
+                    self.input.remove(0);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, body: &str) -> io::Result<()> {
+        let mut escaped = Vec::with_capacity(body.len());
+        for byte in body.bytes() {
+            if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+                escaped.push(b'}');
+                escaped.push(byte ^ 0x20);
+            } else {
+                escaped.push(byte);
+            }
+        }
+
+        let mut packet = vec![b'$'];
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", checksum_of(&escaped)).as_bytes());
+        self.write(&packet)
+    }
+
+    /// Write everything, waiting out a full socket buffer
+    fn write(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            match self.stream.write(bytes) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(count) => bytes = &bytes[count..],
+                Err(error) if error.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(error)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Listens for a client and serves it between frames
+pub struct GdbServer {
+    listener: TcpListener,
+    connection: Option<Connection>,
+    debugger: Debugger,
+    // The client is waiting on a `c` or `s`
+    running: bool,
+    // Why the machine last stopped, for `?`
+    last_stop: Option<StopReason>
+}
+
+impl GdbServer {
+    /// Listen on `addr`, e.g. "127.0.0.1:9000". Use port 0 to pick a free one.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        let mut debugger = Debugger::new();
+        debugger.resume();
+
+        Ok(
+            GdbServer {
+                listener,
+                connection: None,
+                debugger,
+                running: false,
+                last_stop: None
+            }
+        )
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// True while a client has the machine stopped
+    pub fn is_paused(&self) -> bool {
+        self.debugger.is_paused()
+    }
+
+    /// Accept a client if one is waiting, handle its packets and then run a
+    /// frame unless it has the machine stopped
+    pub fn run_frame(&mut self, machine: &mut Chip8Machine) -> io::Result<()> {
+        if self.connection.is_none() {
+            self.accept()?;
+        }
+
+        if let Err(error) = self.serve(machine) {
+            eprintln!("GDB client disconnected: {}", error);
+            self.disconnect();
+        }
+
+        if let Some(reason) = self.debugger.run_frame(machine) {
+            self.last_stop = Some(reason);
+            if self.connection.is_none() {
+                // Nobody to tell, keep going unless the machine can't
+                if !matches!(reason, StopReason::Fault(_) | StopReason::Exited) {
+                    self.debugger.resume();
+                }
+            } else if self.running {
+                self.running = false;
+                let reply = stop_reply(reason);
+                if let Some(connection) = self.connection.as_mut()
+                    && let Err(error) = connection.send(&reply) {
+                    eprintln!("GDB client disconnected: {}", error);
+                    self.disconnect();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        match self.listener.accept() {
+            Ok((stream, addr)) => {
+                eprintln!("GDB client connected from {}", addr);
+                self.connection = Some(Connection::new(stream)?);
+                self.debugger.pause();
+                self.running = false;
+                Ok(())
+            },
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(error) => Err(error)
+        }
+    }
+
+    /// Let the machine run on its own again
+    fn disconnect(&mut self) {
+        self.connection = None;
+        self.running = false;
+        self.debugger.resume();
+    }
+
+    fn serve(&mut self, machine: &mut Chip8Machine) -> io::Result<()> {
+        let Some(connection) = self.connection.as_mut() else {
+            return Ok(());
+        };
+
+        if !connection.receive()? {
+            eprintln!("GDB client disconnected");
+            self.disconnect();
+            return Ok(());
+        }
+
+        while let Some(connection) = self.connection.as_mut()
+            && let Some(packet) = connection.next_packet()? {
+            self.handle(machine, &packet)?;
+        }
+
+        Ok(())
+    }
+
+    fn reply(&mut self, body: &str) -> io::Result<()> {
+        match self.connection.as_mut() {
+            Some(connection) => connection.send(body),
+            None => Ok(())
+        }
+    }
+
+    fn handle(&mut self, machine: &mut Chip8Machine, packet: &str) -> io::Result<()> {
+        if packet == "\x03" {
+            if self.running {
+                self.debugger.pause();
+                self.running = false;
+                return self.reply("S02");
+            }
+            return Ok(());
+        }
+
+        // The empty packet is valid, it just asks for nothing
+        let Some(command) = packet.get(..1) else {
+            return self.reply("");
+        };
+        let args = &packet[1..];
+        match command {
+            "?" => {
+                let reply = self.last_stop.map(stop_reply).unwrap_or_else(|| String::from("S05"));
+                self.reply(&reply)
+            },
+            "g" => {
+                let registers: String = (0..REGISTER_SIZES.len()).map(|register| read_register(machine, register)).collect();
+                self.reply(&registers)
+            },
+            "G" => {
+                let reply = write_registers(machine, args);
+                self.reply(reply)
+            },
+            "p" => {
+                let reply = match usize::from_str_radix(args, 16) {
+                    Ok(register) if register < REGISTER_SIZES.len() => read_register(machine, register),
+                    _ => String::from("E00")
+                };
+                self.reply(&reply)
+            },
+            "P" => {
+                let reply = args.split_once('=')
+                                .and_then(|(register, value)| Some((usize::from_str_radix(register, 16).ok()?, decode_hex(value)?)))
+                                .filter(|(register, value)| *register < REGISTER_SIZES.len() && value.len() == REGISTER_SIZES[*register])
+                                .map(|(register, value)| {
+                                    write_register(machine, register, &value);
+                                    "OK"
+                                })
+                                .unwrap_or("E00");
+                self.reply(reply)
+            },
+            "m" => {
+                let reply = match parse_range(args) {
+                    Some((addr, len)) if len <= PACKET_SIZE / 2 && addr.checked_add(len).is_some() => {
+                        let ram = &machine.cpu.ram;
+                        (addr..addr + len).map(|addr| format!("{:02x}", ram[addr % ram.len()])).collect()
+                    },
+                    _ => String::from("E01")
+                };
+                self.reply(&reply)
+            },
+            "M" => {
+                let reply = args.split_once(':')
+                                .and_then(|(range, data)| Some((parse_range(range)?, decode_hex(data)?)))
+                                .filter(|((addr, len), data)| *len == data.len() && addr.checked_add(*len).is_some())
+                                .map(|((addr, _), data)| {
+                                    let size = machine.cpu.ram.len();
+                                    for (offset, byte) in data.into_iter().enumerate() {
+                                        machine.cpu.ram[(addr + offset) % size] = byte;
+                                    }
+                                    "OK"
+                                })
+                                .unwrap_or("E01");
+                self.reply(reply)
+            },
+            "c" | "s" => {
+                // An optional address to resume from, which has to be in RAM
+                if !args.is_empty() {
+                    match usize::from_str_radix(args, 16) {
+                        Ok(addr) if addr < machine.cpu.ram.len() => machine.cpu.pc = addr as u16,
+                        _ => return self.reply("E01")
+                    }
+                }
+
+                if command == "c" {
+                    self.debugger.resume();
+                } else {
+                    self.debugger.step(1);
+                }
+                self.running = true;
+                Ok(())
+            },
+            "Z" | "z" => {
+                let reply = self.set_breakpoint(command == "Z", args);
+                self.reply(reply)
+            },
+            "D" => {
+                self.reply("OK")?;
+                eprintln!("GDB client detached");
+                self.disconnect();
+                Ok(())
+            },
+            "k" => {
+                eprintln!("GDB client killed the session");
+                self.disconnect();
+                Ok(())
+            },
+            "H" => self.reply("OK"),
+            "q" | "Q" => self.query(packet),
+            _ => self.reply("")
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> io::Result<()> {
+        if packet.starts_with("qSupported") {
+            let features = format!("PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+", PACKET_SIZE);
+            return self.reply(&features);
+        }
+
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let reply = match parse_range(range) {
+                Some((offset, len)) if offset < TARGET_XML.len() => {
+                    let Some(end) = offset.checked_add(len) else {
+                        return self.reply("E01");
+                    };
+                    let end = end.min(TARGET_XML.len());
+                    let more = if end < TARGET_XML.len() { "m" } else { "l" };
+                    format!("{}{}", more, &TARGET_XML[offset..end])
+                },
+                Some(_) => String::from("l"),
+                None => String::from("E00")
+            };
+            return self.reply(&reply);
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.reply("OK")?;
+                if let Some(connection) = self.connection.as_mut() {
+                    connection.acks = false;
+                }
+                Ok(())
+            },
+            "qAttached" => self.reply("1"),
+            "qC" => self.reply("QC1"),
+            "qfThreadInfo" => self.reply("m1"),
+            "qsThreadInfo" => self.reply("l"),
+            _ => self.reply("")
+        }
+    }
+
+    /// Handle `Z`/`z` `type,addr,kind`. Types 0 and 1 are breakpoints, 2-4
+    /// are write, read and access watchpoints.
+    fn set_breakpoint(&mut self, insert: bool, args: &str) -> &'static str {
+        let mut fields = args.split(',');
+        let (Some(kind), Some(addr)) = (fields.next(), fields.next().and_then(|addr| u16::from_str_radix(addr, 16).ok())) else {
+            return "E00";
+        };
+
+        let access = match kind {
+            "0" | "1" => None,
+            "2" => Some(Access::Write),
+            "3" => Some(Access::Read),
+            "4" => Some(Access::ReadWrite),
+            _ => return ""
+        };
+
+        match (access, insert) {
+            (None, true) => self.debugger.add_breakpoint(addr),
+            (None, false) => {
+                self.debugger.remove_breakpoint(addr);
+            },
+            (Some(access), true) => self.debugger.add_watchpoint(addr, access),
+            (Some(_), false) => {
+                self.debugger.remove_watchpoint(addr);
+            }
+        }
+
+        "OK"
+    }
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Breakpoint { .. } => String::from("T05swbreak:;"),
+        StopReason::Watchpoint { addr, access, .. } => {
+            let kind = match access {
+                Access::Write => "watch",
+                Access::Read => "rwatch",
+                Access::ReadWrite => "awatch"
+            };
+            format!("T05{}:{:x};", kind, addr)
+        },
+        StopReason::Step => String::from("S05"),
+        // SIGILL for bad opcodes, SIGSEGV for everything else
+        StopReason::Fault(Chip8Fault::InvalidOpcode { .. }) => String::from("S04"),
+        StopReason::Fault(_) => String::from("S0b"),
+        StopReason::Exited => String::from("W00")
+    }
+}
+
+fn checksum_of(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Parse `addr,len` in hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((usize::from_str_radix(addr, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+/// A register as little endian hex
+fn read_register(machine: &Chip8Machine, register: usize) -> String {
+    let cpu = &machine.cpu;
+    let value = match register {
+        0..=15 => cpu.registers[register] as u16,
+        16 => cpu.i,
+        17 => cpu.pc,
+        18 => cpu.sp as u16,
+        19 => cpu.timers.dt as u16,
+        _ => cpu.timers.st as u16
+    };
+
+    value.to_le_bytes()[..REGISTER_SIZES[register]].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Set a register from its little endian bytes
+fn write_register(machine: &mut Chip8Machine, register: usize, bytes: &[u8]) {
+    let cpu = &mut machine.cpu;
+    let value = bytes.iter().rev().fold(0u16, |value, byte| (value << 8) | *byte as u16);
+
+    match register {
+        0..=15 => cpu.registers[register] = value as u8,
+        16 => cpu.i = value,
+        17 => cpu.pc = (value as usize % cpu.ram.len()) as u16,
+        18 => cpu.sp = (value as u8).min(cpu.stack.len() as u8),
+        19 => cpu.timers.dt = value as u8,
+        _ => cpu.timers.st = value as u8
+    }
+}
+
+fn write_registers(machine: &mut Chip8Machine, hex: &str) -> &'static str {
+    let Some(bytes) = decode_hex(hex) else {
+        return "E00";
+    };
+    if bytes.len() != REGISTER_SIZES.iter().sum::<usize>() {
+        return "E00";
+    }
+
+    let mut offset = 0;
+    for (register, size) in REGISTER_SIZES.into_iter().enumerate() {
+        write_register(machine, register, &bytes[offset..offset + size]);
+        offset += size;
+    }
+
+    "OK"
+}
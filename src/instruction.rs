@@ -0,0 +1,336 @@
+//! Decoding of raw opcodes into typed instructions.
+//!
+//! [`decode`] is the only place that knows how opcodes are laid out. The CPU
+//! executes [`Instruction`]s, and anything that wants to show code (traces,
+//! the debugger, the disassembler) prints them with their `Display` impl,
+//! which gives the mnemonics from Cowgod's Chip-8 reference (and Octo's for
+//! XO-CHIP). SUPER-CHIP and XO-CHIP instructions are always decoded, whether
+//! the platform has them is up to [`crate::platform::Platform::supports`].
+//!
+//! XO-CHIP's `F000 NNNN` is the only instruction longer than one word. Use
+//! [`decode_long`] wherever the following word is available.
+
+use std::fmt;
+
+/// A decoded instruction. `x` and `y` are register indices (0x0 - 0xF).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    ScrollDown { rows: u8 },            // 00CN
+    ScrollUp { rows: u8 },              // 00DN
+    Cls,                                // 00E0
+    Ret,                                // 00EE
+    ScrollRight,                        // 00FB
+    ScrollLeft,                         // 00FC
+    Exit,                               // 00FD
+    Lores,                              // 00FE
+    Hires,                              // 00FF
+    Jump { addr: u16 },                 // 1NNN
+    Call { addr: u16 },                 // 2NNN
+    SkipEqByte { x: u8, byte: u8 },     // 3XNN
+    SkipNeByte { x: u8, byte: u8 },     // 4XNN
+    SkipEqReg { x: u8, y: u8 },         // 5XY0
+    SaveRange { x: u8, y: u8 },         // 5XY2
+    LoadRange { x: u8, y: u8 },         // 5XY3
+    LoadByte { x: u8, byte: u8 },       // 6XNN
+    AddByte { x: u8, byte: u8 },        // 7XNN
+    LoadReg { x: u8, y: u8 },           // 8XY0
+    Or { x: u8, y: u8 },                // 8XY1
+    And { x: u8, y: u8 },               // 8XY2
+    Xor { x: u8, y: u8 },               // 8XY3
+    AddReg { x: u8, y: u8 },            // 8XY4
+    Sub { x: u8, y: u8 },               // 8XY5
+    ShiftRight { x: u8, y: u8 },        // 8XY6
+    SubN { x: u8, y: u8 },              // 8XY7
+    ShiftLeft { x: u8, y: u8 },         // 8XYE
+    SkipNeReg { x: u8, y: u8 },         // 9XY0
+    LoadI { addr: u16 },                // ANNN
+    JumpV0 { addr: u16 },               // BNNN
+    Random { x: u8, byte: u8 },         // CXNN
+    Draw { x: u8, y: u8, rows: u8 },    // DXYN, DXY0 draws a 16x16 sprite except on CHIP-8
+    SkipKey { x: u8 },                  // EX9E
+    SkipNotKey { x: u8 },               // EXA1
+    LoadLongI { addr: u16 },            // F000 NNNN
+    SelectPlanes { mask: u8 },          // FN01
+    LoadAudio,                          // F002
+    LoadDelay { x: u8 },                // FX07
+    WaitKey { x: u8 },                  // FX0A
+    SetDelay { x: u8 },                 // FX15
+    SetSound { x: u8 },                 // FX18
+    AddI { x: u8 },                     // FX1E
+    LoadFont { x: u8 },                 // FX29
+    LoadBigFont { x: u8 },              // FX30
+    SetPitch { x: u8 },                 // FX3A
+    StoreBcd { x: u8 },                 // FX33
+    Store { x: u8 },                    // FX55
+    Load { x: u8 },                     // FX65
+    StoreFlags { x: u8 },               // FX75
+    LoadFlags { x: u8 },                // FX85
+    Invalid { opcode: u16 }
+}
+
+impl Instruction {
+    /// Size of the instruction in bytes
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadLongI { .. } => 4,
+            _ => 2
+        }
+    }
+}
+
+/// Decode a single opcode. `F000` decodes to `LoadLongI` with an address of
+/// 0 since the address is in the next word, see [`decode_long`].
+pub fn decode(opcode: u16) -> Instruction {
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let byte = (opcode & 0x00FF) as u8;
+    let addr = opcode & 0x0FFF;
+
+    match opcode >> 12 {
+        0x0 => match opcode {
+            0x00C0..=0x00CF => Instruction::ScrollDown { rows: n },
+            0x00D0..=0x00DF => Instruction::ScrollUp { rows: n },
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::Lores,
+            0x00FF => Instruction::Hires,
+            _ => Instruction::Invalid { opcode }
+        },
+        0x1 => Instruction::Jump { addr },
+        0x2 => Instruction::Call { addr },
+        0x3 => Instruction::SkipEqByte { x, byte },
+        0x4 => Instruction::SkipNeByte { x, byte },
+        0x5 => match n {
+            0x0 => Instruction::SkipEqReg { x, y },
+            0x2 => Instruction::SaveRange { x, y },
+            0x3 => Instruction::LoadRange { x, y },
+            _ => Instruction::Invalid { opcode }
+        },
+        0x6 => Instruction::LoadByte { x, byte },
+        0x7 => Instruction::AddByte { x, byte },
+        0x8 => match n {
+            0x0 => Instruction::LoadReg { x, y },
+            0x1 => Instruction::Or { x, y },
+            0x2 => Instruction::And { x, y },
+            0x3 => Instruction::Xor { x, y },
+            0x4 => Instruction::AddReg { x, y },
+            0x5 => Instruction::Sub { x, y },
+            0x6 => Instruction::ShiftRight { x, y },
+            0x7 => Instruction::SubN { x, y },
+            0xE => Instruction::ShiftLeft { x, y },
+            _ => Instruction::Invalid { opcode }
+        },
+        0x9 if n == 0 => Instruction::SkipNeReg { x, y },
+        0xA => Instruction::LoadI { addr },
+        0xB => Instruction::JumpV0 { addr },
+        0xC => Instruction::Random { x, byte },
+        0xD => Instruction::Draw { x, y, rows: n },
+        0xE => match byte {
+            0x9E => Instruction::SkipKey { x },
+            0xA1 => Instruction::SkipNotKey { x },
+            _ => Instruction::Invalid { opcode }
+        },
+        0xF => match byte {
+            0x00 if x == 0 => Instruction::LoadLongI { addr: 0 },
+            0x01 => Instruction::SelectPlanes { mask: x },
+            0x02 if x == 0 => Instruction::LoadAudio,
+            0x07 => Instruction::LoadDelay { x },
+            0x0A => Instruction::WaitKey { x },
+            0x15 => Instruction::SetDelay { x },
+            0x18 => Instruction::SetSound { x },
+            0x1E => Instruction::AddI { x },
+            0x29 => Instruction::LoadFont { x },
+            0x30 => Instruction::LoadBigFont { x },
+            0x3A => Instruction::SetPitch { x },
+            0x33 => Instruction::StoreBcd { x },
+            0x55 => Instruction::Store { x },
+            0x65 => Instruction::Load { x },
+            0x75 => Instruction::StoreFlags { x },
+            0x85 => Instruction::LoadFlags { x },
+            _ => Instruction::Invalid { opcode }
+        },
+        _ => Instruction::Invalid { opcode }
+    }
+}
+
+/// Decode an opcode along with the word that follows it, which only matters
+/// for `F000 NNNN`
+pub fn decode_long(opcode: u16, next: u16) -> Instruction {
+    match decode(opcode) {
+        Instruction::LoadLongI { .. } => Instruction::LoadLongI { addr: next },
+        instruction => instruction
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::ScrollDown { rows } => write!(f, "SCD {:#X}", rows),
+            Instruction::ScrollUp { rows } => write!(f, "SCU {:#X}", rows),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Lores => write!(f, "LOW"),
+            Instruction::Hires => write!(f, "HIGH"),
+            Instruction::Jump { addr } => write!(f, "JP {:#05X}", addr),
+            Instruction::Call { addr } => write!(f, "CALL {:#05X}", addr),
+            Instruction::SkipEqByte { x, byte } => write!(f, "SE V{:X}, {:#04X}", x, byte),
+            Instruction::SkipNeByte { x, byte } => write!(f, "SNE V{:X}, {:#04X}", x, byte),
+            Instruction::SkipEqReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Instruction::LoadByte { x, byte } => write!(f, "LD V{:X}, {:#04X}", x, byte),
+            Instruction::AddByte { x, byte } => write!(f, "ADD V{:X}, {:#04X}", x, byte),
+            Instruction::LoadReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubN { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNeReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI { addr } => write!(f, "LD I, {:#05X}", addr),
+            Instruction::JumpV0 { addr } => write!(f, "JP V0, {:#05X}", addr),
+            Instruction::Random { x, byte } => write!(f, "RND V{:X}, {:#04X}", x, byte),
+            Instruction::Draw { x, y, rows } => write!(f, "DRW V{:X}, V{:X}, {:#X}", x, y, rows),
+            Instruction::SkipKey { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadLongI { addr } => write!(f, "LD I, LONG {:#06X}", addr),
+            Instruction::SelectPlanes { mask } => write!(f, "PLANE {}", mask),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::LoadDelay { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::LoadBigFont { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::SetPitch { x } => write!(f, "PITCH V{:X}", x),
+            Instruction::StoreBcd { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::Store { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            Instruction::Invalid { opcode } => write!(f, "DW {:#06X}", opcode)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    fn invalid(opcode: u16) -> Instruction {
+        Invalid { opcode }
+    }
+
+    #[test]
+    fn system_and_screen() {
+        assert_eq!(decode(0x00E0), Cls);
+        assert_eq!(decode(0x00EE), Ret);
+        assert_eq!(decode(0x00C7), ScrollDown { rows: 7 });
+        assert_eq!(decode(0x00DF), ScrollUp { rows: 15 });
+        assert_eq!(decode(0x00FB), ScrollRight);
+        assert_eq!(decode(0x00FC), ScrollLeft);
+        assert_eq!(decode(0x00FD), Exit);
+        assert_eq!(decode(0x00FE), Lores);
+        assert_eq!(decode(0x00FF), Hires);
+        // 0NNN machine code calls aren't emulated
+        assert_eq!(decode(0x0123), invalid(0x0123));
+        assert_eq!(decode(0x00E1), invalid(0x00E1));
+    }
+
+    #[test]
+    fn jumps_and_skips() {
+        assert_eq!(decode(0x1ABC), Jump { addr: 0xABC });
+        assert_eq!(decode(0x2DEF), Call { addr: 0xDEF });
+        assert_eq!(decode(0x3A42), SkipEqByte { x: 0xA, byte: 0x42 });
+        assert_eq!(decode(0x4B42), SkipNeByte { x: 0xB, byte: 0x42 });
+        assert_eq!(decode(0x5120), SkipEqReg { x: 1, y: 2 });
+        assert_eq!(decode(0x9120), SkipNeReg { x: 1, y: 2 });
+        assert_eq!(decode(0xB123), JumpV0 { addr: 0x123 });
+        assert_eq!(decode(0xE39E), SkipKey { x: 3 });
+        assert_eq!(decode(0xE3A1), SkipNotKey { x: 3 });
+        assert_eq!(decode(0x5121), invalid(0x5121));
+        assert_eq!(decode(0x9121), invalid(0x9121));
+        assert_eq!(decode(0xE3A2), invalid(0xE3A2));
+    }
+
+    #[test]
+    fn registers_and_arithmetic() {
+        assert_eq!(decode(0x6AFF), LoadByte { x: 0xA, byte: 0xFF });
+        assert_eq!(decode(0x7A01), AddByte { x: 0xA, byte: 0x01 });
+        assert_eq!(decode(0x8120), LoadReg { x: 1, y: 2 });
+        assert_eq!(decode(0x8121), Or { x: 1, y: 2 });
+        assert_eq!(decode(0x8122), And { x: 1, y: 2 });
+        assert_eq!(decode(0x8123), Xor { x: 1, y: 2 });
+        assert_eq!(decode(0x8124), AddReg { x: 1, y: 2 });
+        assert_eq!(decode(0x8125), Sub { x: 1, y: 2 });
+        assert_eq!(decode(0x8126), ShiftRight { x: 1, y: 2 });
+        assert_eq!(decode(0x8127), SubN { x: 1, y: 2 });
+        assert_eq!(decode(0x812E), ShiftLeft { x: 1, y: 2 });
+        assert_eq!(decode(0xC30F), Random { x: 3, byte: 0x0F });
+        for n in [0x8, 0x9, 0xA, 0xB, 0xC, 0xD, 0xF] {
+            assert_eq!(decode(0x8120 | n), invalid(0x8120 | n));
+        }
+    }
+
+    #[test]
+    fn memory_and_drawing() {
+        assert_eq!(decode(0xA123), LoadI { addr: 0x123 });
+        assert_eq!(decode(0xD125), Draw { x: 1, y: 2, rows: 5 });
+        assert_eq!(decode(0xD120), Draw { x: 1, y: 2, rows: 0 });
+        assert_eq!(decode(0xF407), LoadDelay { x: 4 });
+        assert_eq!(decode(0xF40A), WaitKey { x: 4 });
+        assert_eq!(decode(0xF415), SetDelay { x: 4 });
+        assert_eq!(decode(0xF418), SetSound { x: 4 });
+        assert_eq!(decode(0xF41E), AddI { x: 4 });
+        assert_eq!(decode(0xF429), LoadFont { x: 4 });
+        assert_eq!(decode(0xF430), LoadBigFont { x: 4 });
+        assert_eq!(decode(0xF433), StoreBcd { x: 4 });
+        assert_eq!(decode(0xF455), Store { x: 4 });
+        assert_eq!(decode(0xF465), Load { x: 4 });
+        assert_eq!(decode(0xF475), StoreFlags { x: 4 });
+        assert_eq!(decode(0xF485), LoadFlags { x: 4 });
+        assert_eq!(decode(0xF499), invalid(0xF499));
+    }
+
+    #[test]
+    fn xochip() {
+        assert_eq!(decode(0x5232), SaveRange { x: 2, y: 3 });
+        assert_eq!(decode(0x5233), LoadRange { x: 2, y: 3 });
+        assert_eq!(decode(0xF301), SelectPlanes { mask: 3 });
+        assert_eq!(decode(0xF002), LoadAudio);
+        assert_eq!(decode(0xF43A), SetPitch { x: 4 });
+        assert_eq!(decode(0x00D2), ScrollUp { rows: 2 });
+
+        // F000 takes its address from the next word
+        assert_eq!(decode(0xF000), LoadLongI { addr: 0 });
+        assert_eq!(decode_long(0xF000, 0xBEEF), LoadLongI { addr: 0xBEEF });
+        assert_eq!(decode_long(0x6012, 0xBEEF), LoadByte { x: 0, byte: 0x12 });
+        assert_eq!(decode_long(0xF000, 0).size(), 4);
+        assert_eq!(decode(0x6012).size(), 2);
+
+        // Only with X = 0
+        assert_eq!(decode(0xF100), invalid(0xF100));
+        assert_eq!(decode(0xF102), invalid(0xF102));
+    }
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(decode(0x00E0).to_string(), "CLS");
+        assert_eq!(decode(0x1ABC).to_string(), "JP 0xABC");
+        assert_eq!(decode(0x3A42).to_string(), "SE VA, 0x42");
+        assert_eq!(decode(0x8124).to_string(), "ADD V1, V2");
+        assert_eq!(decode(0xD125).to_string(), "DRW V1, V2, 0x5");
+    }
+}
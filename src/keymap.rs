@@ -0,0 +1,194 @@
+//! Which host keys press which CHIP-8 keys.
+//!
+//! The CHIP-8 keypad is a 4x4 grid:
+//!
+//! ```text
+//! 1 2 3 C
+//! 4 5 6 D
+//! 7 8 9 E
+//! A 0 B F
+//! ```
+//!
+//! The presets put it on the same physical keys on every layout, the left
+//! four keys of the top four rows. Host keys are named like `a`, `1`,
+//! `space`, `up` or `numpad5` (see [`HOST_KEYS`]), independent of any
+//! windowing library, and a CHIP-8 key can have any number of them.
+//!
+//! The `[keymap]` section of the config file starts from a preset and
+//! rebinds keys, with `[keymap rom]` sections for single ROMs:
+//!
+//! ```text
+//! [keymap]
+//! preset = dvorak
+//! 5 = comma up   # replaces the preset's binding for 5
+//! ```
+
+use crate::config::Config;
+
+/// Names of all the host keys that can be bound
+pub const HOST_KEYS: [&str; 72] = [
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9",
+    "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m",
+    "n", "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z",
+    "up", "down", "left", "right", "space", "enter", "tab", "escape",
+    "apostrophe", "comma", "period", "semicolon", "slash", "minus", "equal", "backquote",
+    "leftbracket", "rightbracket", "backslash", "leftshift", "rightshift", "leftctrl", "rightctrl",
+    "numpad0", "numpad1", "numpad2", "numpad3", "numpad4", "numpad5", "numpad6", "numpad7", "numpad8", "numpad9",
+    "numpadenter", "numpadplus", "numpadminus"
+];
+
+/// Preset names for [`Keymap::preset`]
+pub const KEYMAP_PRESETS: [&str; 4] = ["qwerty", "azerty", "dvorak", "arrows"];
+
+/// CHIP-8 keys in keypad order, left to right and top to bottom
+const KEYPAD: [u8; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    /// Host key names bound to each CHIP-8 key
+    bindings: [Vec<String>; 16]
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::qwerty()
+    }
+}
+
+impl Keymap {
+    /// A keymap with nothing bound
+    pub fn empty() -> Self {
+        Keymap { bindings: Default::default() }
+    }
+
+    /// 1234, QWER, ASDF, ZXCV
+    pub fn qwerty() -> Self {
+        Self::from_keypad(["1", "2", "3", "4", "q", "w", "e", "r", "a", "s", "d", "f", "z", "x", "c", "v"])
+    }
+
+    /// 1234, AZER, QSDF, WXCV
+    pub fn azerty() -> Self {
+        Self::from_keypad(["1", "2", "3", "4", "a", "z", "e", "r", "q", "s", "d", "f", "w", "x", "c", "v"])
+    }
+
+    /// 1234, ',.P, AOEU, ;QJK
+    pub fn dvorak() -> Self {
+        Self::from_keypad(["1", "2", "3", "4", "apostrophe", "comma", "period", "p", "a", "o", "e", "u", "semicolon", "q", "j", "k"])
+    }
+
+    /// QWERTY, plus the arrow keys on 2, 4, 6 and 8 and space on 5, which
+    /// is how most games move
+    pub fn arrows() -> Self {
+        let mut keymap = Self::qwerty();
+        for (host, key) in [("up", 0x2), ("left", 0x4), ("right", 0x6), ("down", 0x8), ("space", 0x5)] {
+            keymap.bindings[key].push(host.to_string());
+        }
+        keymap
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "qwerty" => Some(Self::qwerty()),
+            "azerty" => Some(Self::azerty()),
+            "dvorak" => Some(Self::dvorak()),
+            "arrows" => Some(Self::arrows()),
+            _ => None
+        }
+    }
+
+    /// Host keys in keypad order
+    fn from_keypad(hosts: [&str; 16]) -> Self {
+        let mut keymap = Self::empty();
+        for (host, key) in hosts.iter().zip(KEYPAD) {
+            keymap.bindings[key as usize].push(host.to_string());
+        }
+        keymap
+    }
+
+    /// Replace the host keys bound to CHIP-8 key `key`
+    pub fn bind(&mut self, key: u8, hosts: &[&str]) -> Result<(), String> {
+        let key = key as usize;
+        if key >= 16 {
+            return Err(format!("there is no CHIP-8 key {:X}", key));
+        }
+        if let Some(unknown) = hosts.iter().find(|host| !HOST_KEYS.contains(&host.to_ascii_lowercase().as_str())) {
+            return Err(format!("unknown host key '{}'", unknown));
+        }
+
+        self.bindings[key] = hosts.iter().map(|host| host.to_ascii_lowercase()).collect();
+        Ok(())
+    }
+
+    /// Host keys bound to CHIP-8 key `key`
+    pub fn hosts(&self, key: u8) -> &[String] {
+        &self.bindings[key as usize & 0xF]
+    }
+
+    /// Every (host key, CHIP-8 key) pair
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.bindings.iter()
+            .enumerate()
+            .flat_map(|(key, hosts)| hosts.iter().map(move |host| (host.as_str(), key as u8)))
+    }
+
+    /// The first host key bound to more than one CHIP-8 key, and the two
+    /// keys it's bound to
+    fn conflict(&self) -> Option<(&str, u8, u8)> {
+        let bindings: Vec<(&str, u8)> = self.bindings().collect();
+        bindings.iter().enumerate().find_map(|(index, (host, key))| {
+            bindings[index + 1..].iter()
+                .find(|(other, other_key)| other == host && other_key != key)
+                .map(|(_, other_key)| (*host, *key, *other_key))
+        })
+    }
+
+    /// Start from `base` and apply the `[keymap]` sections of `config` that
+    /// apply to `rom`. Errors name the line they're on.
+    ///
+    /// A CHIP-8 key can only be bound once per section, and no host key can
+    /// end up pressing two CHIP-8 keys.
+    pub fn from_config(base: Keymap, config: &Config, rom: Option<&str>) -> Result<Self, String> {
+        let mut keymap = base;
+        // Line each CHIP-8 key was last bound on, for the conflict error
+        let mut bound_on = [None; 16];
+
+        for section in config.sections("keymap", rom) {
+            let mut seen = [None; 16];
+
+            for entry in &section.entries {
+                let error = |message: String| format!("line {}: {}", entry.line, message);
+
+                if entry.name == "preset" {
+                    keymap = Self::preset(&entry.value).ok_or_else(|| {
+                        error(format!("unknown keymap preset '{}', expected one of {}", entry.value, KEYMAP_PRESETS.join(", ")))
+                    })?;
+                    bound_on = [None; 16];
+                    continue;
+                }
+
+                let key = match u8::from_str_radix(&entry.name, 16) {
+                    Ok(key) if entry.name.len() == 1 => key,
+                    _ => return Err(error(format!("'{}' isn't a CHIP-8 key, expected 0-9 or A-F", entry.name)))
+                };
+                if let Some(line) = seen[key as usize] {
+                    return Err(error(format!("CHIP-8 key {:X} is already bound on line {}", key, line)));
+                }
+                seen[key as usize] = Some(entry.line);
+                bound_on[key as usize] = Some(entry.line);
+
+                let hosts: Vec<&str> = entry.value.split_whitespace().collect();
+                keymap.bind(key, &hosts).map_err(error)?;
+            }
+        }
+
+        if let Some((host, first, second)) = keymap.conflict() {
+            let message = format!("host key '{}' is bound to both CHIP-8 key {:X} and {:X}", host, first, second);
+            return Err(match bound_on[first as usize].max(bound_on[second as usize]) {
+                Some(line) => format!("line {}: {}", line, message),
+                None => message
+            });
+        }
+
+        Ok(keymap)
+    }
+}
@@ -0,0 +1,58 @@
+//! Core of the CHIP-8 emulator.
+//!
+//! Everything needed to run a ROM lives here and does not depend on any
+//! windowing library: the CPU, its memory and timers, and a 64x32 logical
+//! framebuffer. Frontends (like the minifb window behind the `minifb`
+//! feature) drive a [`Chip8Machine`] and turn its display into host pixels
+//! with a [`Renderer`].
+
+pub mod asm;
+pub mod audio;
+pub mod config;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod fault;
+pub mod gdb;
+pub mod instruction;
+pub mod keymap;
+pub mod machine;
+pub mod movie;
+pub mod phosphor;
+pub mod platform;
+pub mod quirks;
+pub mod render;
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+pub mod screenshot;
+pub mod timer;
+pub mod trace;
+pub mod video;
+
+#[cfg(feature = "audio")]
+pub mod speaker;
+
+#[cfg(feature = "minifb")]
+pub mod window;
+
+pub use audio::{AudioBackend, NullAudio, RecordingAudio, Tone, Waveform};
+pub use cpu::Chip8CPU;
+pub use debugger::Debugger;
+pub use display::Display;
+pub use fault::{Chip8Fault, FaultPolicy};
+pub use instruction::{decode, Instruction};
+pub use keymap::Keymap;
+pub use machine::Chip8Machine;
+pub use movie::Movie;
+pub use platform::Platform;
+pub use quirks::Quirks;
+pub use render::{Palette, Renderer};
+pub use rewind::RewindBuffer;
+pub use rng::{Rng, RngKind};
+pub use savestate::SaveState;
+pub use screenshot::{ImageFormat, Screenshot};
+pub use timer::{FrameClock, Timers};
+pub use trace::{TraceCategories, Tracer};
+pub use video::{VideoFormat, VideoRecorder};
@@ -0,0 +1,233 @@
+use crate::{
+    audio::{AudioBackend, Beeper, Tone},
+    cpu::{Chip8CPU, BIG_FONT, FONT},
+    display::Display,
+    fault::{Chip8Fault, FaultPolicy},
+    instruction::Instruction,
+    platform::Platform,
+    quirks::Quirks,
+    rng::{Rng, RngKind},
+    savestate::SaveState,
+    timer::FrameClock,
+    trace::Tracer
+};
+
+/// Instructions per second used when nothing else is configured, the speed
+/// the emulator has always run at
+pub const DEFAULT_CLOCK_HZ: u32 = 256;
+
+/// A complete CHIP-8 machine: CPU, memory, timers, display and keypad.
+///
+/// The machine has no notion of wall-clock time or of a window. Emulated time
+/// is counted in instructions: at a clock of `clock_hz` instructions per
+/// second the delay and sound timers tick 60 times every `clock_hz`
+/// instructions. Frontends usually call [`Chip8Machine::run_frame`] once per
+/// 60 Hz frame and present the display and read input in between.
+pub struct Chip8Machine {
+    pub cpu: Chip8CPU,
+    pub display: Display,
+    pub keys: [bool; 16],
+    clock: FrameClock,
+    beeper: Beeper,
+    tracer: Option<Tracer>,
+    traced_keys: [bool; 16]
+}
+
+impl Default for Chip8Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Chip8Machine {
+    pub fn new() -> Self {
+        let mut cpu = Chip8CPU::new();
+
+        // The fonts always fit below the program area
+        cpu.load_font(&FONT).unwrap();
+        cpu.load_big_font(&BIG_FONT).unwrap();
+
+        Chip8Machine {
+            cpu,
+            display: Display::new(),
+            keys: [false; 16],
+            clock: FrameClock::new(DEFAULT_CLOCK_HZ),
+            beeper: Beeper::default(),
+            tracer: None,
+            traced_keys: [false; 16]
+        }
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), &'static str> {
+        self.cpu.load_rom(rom)
+    }
+
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        self.keys[key] = pressed;
+    }
+
+    /// Set the instruction rate in Hz. Timers keep running at 60 Hz.
+    pub fn set_clock(&mut self, hz: u32) {
+        self.clock.set_clock_hz(hz);
+    }
+
+    pub fn clock_hz(&self) -> u32 {
+        self.clock.clock_hz()
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.cpu.platform
+    }
+
+    /// Switch the instruction set, along with the quirks it usually needs.
+    /// Call [`Chip8Machine::set_quirks`] afterwards to override them.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.cpu.platform = platform;
+        self.cpu.quirks = platform.default_quirks();
+        self.cpu.ram.resize(platform.memory_size(), 0);
+    }
+
+    /// True once the program has run 00FD
+    pub fn has_exited(&self) -> bool {
+        self.cpu.exited
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.cpu.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.cpu.quirks = quirks;
+    }
+
+    /// Reseed the CXNN random number generator
+    pub fn seed_rng(&mut self, kind: RngKind, seed: u64) {
+        self.cpu.rng = Rng::new(kind, seed);
+    }
+
+    pub fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        self.cpu.fault_policy = policy;
+    }
+
+    /// The fault the CPU halted on, if any
+    pub fn halted(&self) -> Option<Chip8Fault> {
+        self.cpu.halted
+    }
+
+    /// Send the beeper to a different audio backend
+    pub fn set_audio(&mut self, backend: Box<dyn AudioBackend>) {
+        self.beeper.set_backend(backend);
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.beeper.set_tone(tone);
+    }
+
+    /// True while the sound timer is running
+    pub fn is_beeping(&self) -> bool {
+        self.beeper.is_playing()
+    }
+
+    /// Start tracing to `tracer`, or stop tracing with `None`
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// Number of 60 Hz frames emulated so far
+    pub fn frame(&self) -> u64 {
+        self.clock.frame()
+    }
+
+    /// Snapshot the complete machine state
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            cpu: self.cpu.clone(),
+            display: self.display.clone(),
+            keys: self.keys,
+            clock: self.clock
+        }
+    }
+
+    /// Put the machine back exactly as it was when `state` was saved. Audio,
+    /// tracing and other frontend settings are left alone.
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.cpu = state.cpu.clone();
+        self.display = state.display.clone();
+        self.keys = state.keys;
+        self.clock = state.clock;
+        self.beeper.set_pattern(self.cpu.audio_pattern.map(|pattern| (pattern, self.cpu.pitch)));
+        self.beeper.update(self.clock.frame(), self.cpu.timers.st > 0);
+    }
+
+    /// Execute a single instruction. Returns true if it ended a frame, in
+    /// which case the timers have already been counted down.
+    ///
+    /// A halted CPU doesn't advance emulated time.
+    pub fn tick(&mut self) -> Result<bool, Chip8Fault> {
+        // After a draw with the vblank quirk the CPU idles until the next frame
+        if self.cpu.waiting_for_vblank {
+            return Ok(self.end_tick());
+        }
+
+        let Some(tracer) = self.tracer.as_mut() else {
+            self.cpu.tick(&mut self.display, &self.keys)?;
+            return Ok(self.end_tick());
+        };
+
+        let frame = self.clock.frame();
+        if self.keys != self.traced_keys {
+            self.traced_keys = self.keys;
+            tracer.input(frame, &self.cpu, &self.keys);
+        }
+
+        let opcode = self.cpu.fetch();
+        let instruction = self.cpu.decode_at(self.cpu.pc);
+        tracer.instruction(frame, &self.cpu, opcode, &instruction);
+
+        // Coordinates have to be read before the draw, it may overwrite VF
+        let draw = match instruction {
+            Instruction::Draw { x, y, rows } => Some((self.cpu.registers[x as usize], self.cpu.registers[y as usize], rows)),
+            _ => None
+        };
+
+        self.cpu.tick(&mut self.display, &self.keys)?;
+
+        if let Some((x, y, rows)) = draw {
+            tracer.draw(frame, &self.cpu, x, y, rows);
+        }
+
+        let new_frame = self.end_tick();
+        if new_frame && let Some(tracer) = self.tracer.as_mut() {
+            tracer.timers(self.clock.frame(), &self.cpu);
+        }
+
+        Ok(new_frame)
+    }
+
+    /// Advance emulated time by one instruction, returns true on a frame boundary
+    fn end_tick(&mut self) -> bool {
+        let frames = self.clock.step();
+        for _ in 0..frames {
+            self.cpu.timers.tick();
+        }
+
+        if frames > 0 {
+            self.cpu.waiting_for_vblank = false;
+        }
+
+        self.beeper.set_pattern(self.cpu.audio_pattern.map(|pattern| (pattern, self.cpu.pitch)));
+        self.beeper.update(self.clock.frame(), self.cpu.timers.st > 0);
+
+        frames > 0
+    }
+
+    /// Execute instructions up to and including the next frame boundary
+    pub fn run_frame(&mut self) -> Result<(), Chip8Fault> {
+        while !self.tick()? {}
+        Ok(())
+    }
+}
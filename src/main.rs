@@ -1,584 +1,561 @@
 use std::{
-    cell::{self, RefCell}, env, fs, rc::Rc, thread, time::{
-        Duration,
-        Instant
-    }
+    env, fs, io::{self, BufWriter, Write}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}
 };
 
-use minifb::{
-    self, 
-    Window, 
-    WindowOptions
+use chip8::{
+    audio::Tone,
+    config::Config,
+    keymap::{Keymap, KEYMAP_PRESETS},
+    machine::{Chip8Machine, DEFAULT_CLOCK_HZ},
+    movie::Movie,
+    phosphor::{PhosphorMode, MAX_PHOSPHOR_FRAMES},
+    platform::Platform,
+    quirks::Quirks,
+    render::{Palette, Scaling, PALETTE_PRESETS},
+    rewind::{DEFAULT_REWIND_BUDGET, DEFAULT_REWIND_DEPTH},
+    rng::RngKind,
+    screenshot::ImageFormat,
+    trace::{TraceCategories, Tracer},
+    video::{VideoFormat, VideoRecorder},
+    window::{Chip8Emulator, WindowSettings, RECORD_SCALE}
 };
 
-const WIDTH: usize = 1024;
-const WIDTH_MULT: usize = WIDTH / 64;
-const HEIGHT: usize = 512;
-const HEIGHT_MULT: usize = HEIGHT / 32;
-const CLEAR_VAL: u32 = 0x004D4D4D;
-
-enum EmulatorError {
-    DisplayCreationError
+struct Options {
+    rom_path: String,
+    platform: Option<Platform>,
+    clock: Option<u32>,
+    quirks: Option<Quirks>,
+    rng: Option<RngKind>,
+    seed: Option<u64>,
+    trace: TraceCategories,
+    trace_file: Option<String>,
+    rewind_frames: usize,
+    rewind_budget: usize,
+    debug: bool,
+    gdb_port: Option<u16>,
+    screenshot_format: ImageFormat,
+    record: Option<String>,
+    record_format: Option<VideoFormat>,
+    headless: Option<u64>,
+    record_movie: Option<String>,
+    play_movie: Option<String>,
+    config: Option<String>,
+    keymap: Option<Keymap>,
+    palette: Option<Palette>,
+    colors: Vec<(String, String)>,
+    beep_indicator: bool,
+    tones: Vec<(String, String)>,
+    window_scale: Option<usize>,
+    window_size: Option<(usize, usize)>,
+    scaling: Option<Scaling>,
+    fullscreen: bool,
+    phosphor: Option<PhosphorMode>
 }
 
-struct Display {
-    window: Window,
-    framebuffer: Box<[u32; WIDTH * HEIGHT]>
+fn usage(program: &str) -> String {
+    format!(
+        "Usage: {} [options] <rom-path>\n\
+         \n\
+         Options:\n\
+         \x20 --platform <name>             chip8 (default), schip or xochip\n\
+         \x20 --clock <hz>                  instructions per second (default: {})\n\
+         \x20 --quirks <preset>             vip, schip, xochip or modern (default: the platform's)\n\
+         \x20 --rng <kind>                  xorshift (default) or lcg for CXNN\n\
+         \x20 --seed <n>                    seed for CXNN, random if not given\n\
+         \x20 --trace <list>                trace instructions,draws,input,timers (or all)\n\
+         \x20 --trace-file <path>           write the trace to a file instead of stdout\n\
+         \x20 --rewind <seconds>            history kept for rewinding (default: 60, 0 turns it off)\n\
+         \x20 --rewind-memory <mb>          most memory the rewind history may use (default: 16, 0 turns it off)\n\
+         \x20 --debug                       start paused with a debugger on the terminal\n\
+         \x20 --gdb <port>                  serve the GDB remote protocol on 127.0.0.1:<port>\n\
+         \x20 --screenshot-format <format>  png (default), ppm or pbm for F12 screenshots\n\
+         \x20 --record <path>               record a video from the start, - for stdout\n\
+         \x20 --record-format <format>      gif, y4m or ppm (default: from the extension)\n\
+         \x20 --headless <frames>           run up to that many frames without a window, then exit\n\
+         \x20 --record-movie <path>         record the keys pressed on every frame\n\
+         \x20 --play-movie <path>           replay a recorded movie instead of reading the keyboard\n\
+         \x20 --config <path>               config file (default: chip8.conf here or in ~/.config/chip-8)\n\
+         \x20 --keymap <preset>             qwerty (default), azerty, dvorak or arrows, instead of the config's\n\
+         \x20 --palette <preset>            default, octo, lcd, hotdog or gray, instead of the config's\n\
+         \x20 --color <name>=<#RRGGBB>      set background, foreground, plane2, overlap, buzzer or silence\n\
+         \x20 --beep-indicator              show the buzzer color around the display while beeping\n\
+         \x20 --tone <name>=<value>         set the beep's waveform (square, triangle, sawtooth, sine), frequency or volume\n\
+         \x20 --scale <n>                   open the window n times the 64x32 display (default: 16)\n\
+         \x20 --window <width>x<height>     open the window at this size instead\n\
+         \x20 --scaling <mode>              integer (default) or fit, F10 switches while running\n\
+         \x20 --fullscreen                  borderless window on top at the top left, where supported\n\
+         \x20 --phosphor <mode>[:<frames>]  fade (default: 4 frames) or blend (default: 2) to hide flicker",
+        program, DEFAULT_CLOCK_HZ
+    )
 }
 
-impl Display {
-    pub fn new(name: &str, width: usize, height: usize) -> Result<Self, minifb::Error> {
-        let window = Window::new(name, width, height, WindowOptions::default())?;
-        
-        Ok(
-            Display {
-                window: window,
-                framebuffer: Box::new([0x004D4D4D; WIDTH * HEIGHT])
-            }
-        )
-    }
-
-    pub fn draw_pixel(&mut self, x: usize, y: usize, color: u32) {
-        for i in 0..WIDTH_MULT {
-            for j in 0..HEIGHT_MULT {
-                self.framebuffer[((x * WIDTH_MULT) + i) + (((y * HEIGHT_MULT) + j) * WIDTH)] = color;
-            }
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut platform = None;
+    let mut clock = None;
+    let mut quirks = None;
+    let mut rng = None;
+    let mut seed = None;
+    let mut trace = TraceCategories::default();
+    let mut trace_file = None;
+    let mut rewind_frames = DEFAULT_REWIND_DEPTH;
+    let mut rewind_budget = DEFAULT_REWIND_BUDGET;
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut screenshot_format = ImageFormat::default();
+    let mut record = None;
+    let mut record_format = None;
+    let mut headless = None;
+    let mut record_movie = None;
+    let mut play_movie = None;
+    let mut config = None;
+    let mut keymap = None;
+    let mut palette = None;
+    let mut colors = Vec::new();
+    let mut beep_indicator = false;
+    let mut tones = Vec::new();
+    let mut window_scale = None;
+    let mut window_size = None;
+    let mut scaling = None;
+    let mut fullscreen = false;
+    let mut phosphor = None;
+
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--debug" {
+            debug = true;
+            continue;
         }
-    }
 
-    pub fn get_pixel_color(&self, x: usize, y: usize) -> u32 {
-        self.framebuffer[(x * WIDTH_MULT) + (WIDTH * (y * HEIGHT_MULT))]
-    }
+        // Every other option takes a value
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
 
-    pub fn is_pixel_clear(&self, x: usize, y: usize) -> bool {
-        self.framebuffer[(x * WIDTH_MULT) + (WIDTH * (y * HEIGHT_MULT))] == CLEAR_VAL
+        match arg.as_str() {
+            "--platform" => {
+                let name = value()?;
+                platform = Some(Platform::from_name(name).ok_or(format!("Unknown platform '{}'", name))?);
+            },
+            "--clock" => {
+                let text = value()?;
+                clock = Some(text.parse().ok().filter(|hz| *hz > 0).ok_or(format!("Invalid clock '{}'", text))?);
+            },
+            "--quirks" => {
+                let name = value()?;
+                quirks = Some(Quirks::from_name(name).ok_or(format!("Unknown quirks preset '{}', expected vip, schip, xochip or modern", name))?);
+            },
+            "--rng" => {
+                let name = value()?;
+                rng = Some(RngKind::from_name(name).ok_or(format!("Unknown rng '{}'", name))?);
+            },
+            "--seed" => {
+                let text = value()?;
+                seed = Some(text.parse().map_err(|_| format!("Invalid seed '{}'", text))?);
+            },
+            "--trace" => trace = TraceCategories::parse(value()?)?,
+            "--trace-file" => trace_file = Some(value()?.clone()),
+            "--rewind" => {
+                let text = value()?;
+                rewind_frames = text.parse::<usize>().ok()
+                                    .and_then(|seconds| seconds.checked_mul(60))
+                                    .ok_or(format!("Invalid rewind length '{}'", text))?;
+            },
+            "--gdb" => {
+                let text = value()?;
+                gdb_port = Some(text.parse().map_err(|_| format!("Invalid port '{}'", text))?);
+            },
+            "--screenshot-format" => {
+                let name = value()?;
+                screenshot_format = ImageFormat::from_name(name).ok_or(format!("Unknown image format '{}'", name))?;
+            },
+            "--record" => record = Some(value()?.clone()),
+            "--record-format" => {
+                let name = value()?;
+                record_format = Some(VideoFormat::from_name(name).ok_or(format!("Unknown video format '{}'", name))?);
+            },
+            "--headless" => {
+                let text = value()?;
+                headless = Some(text.parse().map_err(|_| format!("Invalid frame count '{}'", text))?);
+            },
+            "--record-movie" => record_movie = Some(value()?.clone()),
+            "--play-movie" => play_movie = Some(value()?.clone()),
+            "--config" => config = Some(value()?.clone()),
+            "--keymap" => {
+                let name = value()?;
+                keymap = Some(Keymap::preset(name).ok_or(format!("Unknown keymap '{}', expected one of {}", name, KEYMAP_PRESETS.join(", ")))?);
+            },
+            "--palette" => {
+                let name = value()?;
+                palette = Some(Palette::preset(name).ok_or(format!("Unknown palette '{}', expected one of {}", name, PALETTE_PRESETS.join(", ")))?);
+            },
+            "--color" => {
+                let text = value()?;
+                let (name, color) = text.split_once('=').ok_or(format!("Expected --color <name>=<#RRGGBB>, got '{}'", text))?;
+                // Checked now so a typo is caught before anything starts
+                Palette::default().set(name, color).map_err(|error| format!("Invalid --color: {}", error))?;
+                colors.push((name.to_string(), color.to_string()));
+            },
+            "--beep-indicator" => beep_indicator = true,
+            "--tone" => {
+                let text = value()?;
+                let (name, setting) = text.split_once('=').ok_or(format!("Expected --tone <name>=<value>, got '{}'", text))?;
+                Tone::default().set(name, setting).map_err(|error| format!("Invalid --tone: {}", error))?;
+                tones.push((name.to_string(), setting.to_string()));
+            },
+            "--scale" => {
+                let text = value()?;
+                window_scale = Some(text.parse().ok().filter(|scale| *scale > 0).ok_or(format!("Invalid scale '{}'", text))?);
+            },
+            "--window" => {
+                let text = value()?;
+                let size = text.split_once('x').and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+                window_size = Some(size.filter(|(width, height)| *width > 0 && *height > 0)
+                                    .ok_or(format!("Invalid window size '{}', expected <width>x<height>", text))?);
+            },
+            "--scaling" => {
+                let name = value()?;
+                scaling = Some(Scaling::from_name(name).ok_or(format!("Unknown scaling '{}', expected integer or fit", name))?);
+            },
+            "--fullscreen" => fullscreen = true,
+            "--phosphor" => {
+                let text = value()?;
+                phosphor = Some(PhosphorMode::parse(text).ok_or(format!("Invalid phosphor mode '{}', expected off, fade, blend, fade:<frames> or blend:<frames> with 1-{} frames", text, MAX_PHOSPHOR_FRAMES))?);
+            },
+            "--rewind-memory" => {
+                let text = value()?;
+                rewind_budget = text.parse::<usize>().ok()
+                                    .and_then(|megabytes| megabytes.checked_mul(1024 * 1024))
+                                    .ok_or(format!("Invalid rewind memory '{}'", text))?;
+            },
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument {}", arg))
+        }
     }
 
-    pub fn clear(&mut self) {
-        *self.framebuffer = [CLEAR_VAL; WIDTH * HEIGHT];
+    if debug && gdb_port.is_some() {
+        return Err(String::from("--debug and --gdb can't be used together"));
     }
 
-    pub fn clear_pixel(&mut self, x: usize, y: usize) {
-        for i in 0..WIDTH_MULT {
-            for j in 0..HEIGHT_MULT {
-                self.framebuffer[((x * WIDTH_MULT) + i) + (((y * HEIGHT_MULT) + j) * WIDTH)] = CLEAR_VAL;
-            }
+    // A movie only replays in sync on the setup it was recorded with
+    if play_movie.is_some() {
+        let given = [
+            ("--platform", platform.is_some()),
+            ("--quirks", quirks.is_some()),
+            ("--clock", clock.is_some()),
+            ("--rng", rng.is_some()),
+            ("--seed", seed.is_some())
+        ];
+        if let Some((name, _)) = given.iter().find(|(_, given)| *given) {
+            return Err(format!("{} can't be used with --play-movie, the movie sets it", name));
         }
     }
-}
-
 
-struct Chip8CPU {
-    ram: [u8; 4096],
-    registers: [u8; 16],
-    stack: [u16; 16],
-    pc: u16,
-    i: u16,
-    sp: i16,
-    dt: u8,
-    st: u8
+    Ok(
+        Options {
+            rom_path: rom_path.ok_or("Missing rom path")?,
+            platform,
+            clock,
+            quirks,
+            rng,
+            seed,
+            trace,
+            trace_file,
+            rewind_frames,
+            rewind_budget,
+            debug,
+            gdb_port,
+            screenshot_format,
+            record,
+            record_format,
+            headless,
+            record_movie,
+            play_movie,
+            config,
+            keymap,
+            palette,
+            colors,
+            beep_indicator,
+            tones,
+            window_scale,
+            window_size,
+            scaling,
+            fullscreen,
+            phosphor
+        }
+    )
 }
 
-impl Chip8CPU {
-    pub fn new() -> Self {
-        Chip8CPU {
-            ram: [0; 4096],
-            registers: [0; 16],
-            stack: [0; 16],
-            pc: 0x200,
-            i: 0x0,
-            sp: -1,
-            dt: 0,
-            st: 0
-        }
-    }
+fn main() {
 
-    pub fn load_rom(&mut self, rom: &Vec<u8>) -> Result<(), &'static str> {
-        if rom.len() + self.pc as usize > self.ram.len() {
-            return Err("Out of memory");
-        }
+    let args: Vec<String> = env::args().collect();
 
-        for (i, byte) in rom.into_iter().enumerate() {
-            self.ram[self.pc as usize + i] = *byte;
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            return eprintln!("{}\n\n{}", error, usage(&args[0]));
         }
+    };
 
-        Ok(())
-    }
+    let config_path = options.config.as_ref().map(PathBuf::from).or_else(Config::default_path);
+    let config = match &config_path {
+        Some(path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(error) => return eprintln!("{}", error)
+        },
+        None => Config::default()
+    };
 
-    pub fn load_font(&mut self, font: &Vec<u8>) -> Result<(),  &'static str> {
+    // Per-ROM sections go by the ROM's file name without the extension
+    let rom_name = Path::new(&options.rom_path).file_stem().and_then(|name| name.to_str());
 
-        if 0x50 + font.len() >= 0x200 {
-            return Err( "Overran program memory");
+    let keymap = match options.keymap {
+        Some(keymap) => keymap,
+        None => match Keymap::from_config(Keymap::default(), &config, rom_name) {
+            Ok(keymap) => keymap,
+            Err(error) => {
+                let path = config_path.as_deref().unwrap_or(Path::new("config"));
+                return eprintln!("Invalid keymap in {}: {}", path.display(), error);
+            }
         }
+    };
 
-        for (i, byte) in font.into_iter().enumerate() {
-            self.ram[0x50 + i] = *byte;
+    let mut palette = match options.palette {
+        Some(palette) => palette,
+        None => match Palette::from_config(Palette::default(), &config, rom_name) {
+            Ok(palette) => palette,
+            Err(error) => {
+                let path = config_path.as_deref().unwrap_or(Path::new("config"));
+                return eprintln!("Invalid palette in {}: {}", path.display(), error);
+            }
         }
+    };
+    for (name, color) in &options.colors {
+        let _ = palette.set(name, color);
+    }
 
-        Ok(())
+    let mut tone = match Tone::from_config(Tone::default(), &config, rom_name) {
+        Ok(tone) => tone,
+        Err(error) => {
+            let path = config_path.as_deref().unwrap_or(Path::new("config"));
+            return eprintln!("Invalid audio settings in {}: {}", path.display(), error);
+        }
+    };
+    for (name, setting) in &options.tones {
+        let _ = tone.set(name, setting);
     }
 
-    pub fn tick(&mut self, display: &mut Display, keyboard: &mut Rc<RefCell<[bool; 16]>>) {
-        // Fetch instruction
-        let mut instruction = (self.ram[self.pc as usize] as u16) << 8;
-        instruction = instruction | (self.ram[(self.pc + 1) as usize] as u16);
+    let mut window_settings = match WindowSettings::from_config(WindowSettings::default(), &config, rom_name) {
+        Ok(settings) => settings,
+        Err(error) => {
+            let path = config_path.as_deref().unwrap_or(Path::new("config"));
+            return eprintln!("Invalid window settings in {}: {}", path.display(), error);
+        }
+    };
+    if let Some(scale) = options.window_scale {
+        window_settings.set_scale(scale);
+    }
+    if let Some((width, height)) = options.window_size {
+        window_settings.width = width;
+        window_settings.height = height;
+    }
+    if let Some(scaling) = options.scaling {
+        window_settings.scaling = scaling;
+    }
+    window_settings.fullscreen |= options.fullscreen;
+    if let Some(phosphor) = options.phosphor {
+        window_settings.phosphor = phosphor;
+    }
 
-        // println!("INSTRUCTION: {:#X}", instruction);
-            
-        // Move to next instruction
-        self.pc += 2;
+    let rom = match fs::read(&options.rom_path) {
+        Err(error) => {
+            return eprintln!("Could not open file: {}", error);
+        },
+        Ok(file) => file
+    };
 
-        if self.dt > 0 {
-            self.dt -= 1;
-        }
+    let movie = match &options.play_movie {
+        Some(path) => match Movie::load(Path::new(path)) {
+            Ok(movie) => Some(movie),
+            Err(error) => return eprintln!("Could not load movie {}: {}", path, error)
+        },
+        None => None
+    };
 
-        if self.st > 0 {
-            self.st -= 1;
-        }
+    let mut machine = Chip8Machine::new();
+    machine.set_tone(tone);
 
-        // Decode and run instruction
-        match instruction {
-            0x00E0 => {             // CLS
-                println!("CLS");
-                display.clear();
-            },
-            0x00EE => {             // RET
-                println!("RET");
-                self.pc = self.stack[self.sp as usize];
-                self.sp -= 1;
-            },
-            0x1000..=0x1FFF => {    // JP addr
-                let addr = instruction & 0x0FFF;
-                // println!("JP {:#X}", addr);
-                self.pc = addr;
-            },
-            0x2000..=0x2FFF => {    // CALL addr
-                println!("CALL {:#X}", (instruction & 0x0FFF));
-                self.sp += 1;
-                self.stack[self.sp as usize] = self.pc;
-                self.pc = instruction & 0x0FFF;
-            },
-            0x3000..=0x3FFF => {    // SE Vx, byte
-                let register = ((instruction & 0x0F00) >> 8) as usize;
-                let val = (instruction & 0x00FF) as u8;
-                println!("SE V{:X}, {:#X}", register, val);
-                if self.registers[register] == val {
-                    self.pc += 2;
-                }
-            },
-            0x4000..=0x4FFF => {    // SNE Vx, byte
-                let register = ((instruction & 0x0F00) >> 8) as usize;
-                let val = (instruction & 0x00FF) as u8;
-                println!("SNE V{:X}, {:#X}", register, val);
-                if self.registers[register] != val {
-                    self.pc += 2;
-                }
-            },
-            0x5000..=0x5FF0 => {    // SE Vx, Vy
-                let register_x = ((instruction & 0x0F00) >> 8) as usize;
-                let register_y = ((instruction & 0x00F0) >> 4) as usize;
-                println!("SE V{:X} V{:X}", register_x, register_y);
-                if self.registers[register_x] == self.registers[register_y] {
-                    self.pc += 2;
-                }
-            },
-            0x6000..=0x6FFF => {    // LD Vx, byte
-                // Get register from 0x0F00
-                let register = (instruction & 0x0F00) >> 8;
+    // A movie sets everything up the way it was recorded. parse_args made
+    // sure no option changes it again.
+    if let Some(movie) = &movie {
+        movie.configure(&mut machine);
+    }
 
-                // Get value from 0x00FF
-                let val = (instruction & 0x00FF) as u8;
-                // println!("Loading {:#X} into register {:#X}", val, register);
-                println!("LD V{:X}, {:#X}", register, val);
+    // The platform decides how much memory there is for the rom
+    if let Some(platform) = options.platform {
+        machine.set_platform(platform);
+    }
 
-                // Place val into register
-                self.registers[register as usize] = val;
-            },
-            0x7000..=0x7FFF => {    // ADD Vx, byte
-                // Get register from 0x0F00
-                let register = ((instruction & 0x0F00) >> 8) as usize;
+    if let Err(error) = machine.load_rom(&rom) {
+        return eprintln!("Could not load rom: {}", error);
+    }
 
-                // Get value from 0x00FF
-                let value = (instruction & 0x00FF) as u8;
-                println!("ADD V{:X}, {:#X}", register, value);
+    if let Some(quirks) = options.quirks {
+        machine.set_quirks(quirks);
+    }
 
-                // Set register = register + value
-                self.registers[register] = self.registers[register].wrapping_add(value);
-            },
-            0x8000..=0x8FFF => {
-                let byte = (instruction & 0x0F) as u8;
-                let register_x = ((instruction & 0x0F00) >> 8) as usize;
-                let register_y = ((instruction & 0x00F0) >> 4) as usize;
-
-                match byte {
-                    0x0 => {    // LD Vx, Vy
-                        println!("LD V{:X}, V{:X}", register_x, register_y);
-                        self.registers[register_x] = self.registers[register_y];
-                    },
-                    0x1 => {    // OR Vx, Vy
-                        println!("OR V{:X}, V{:X}", register_x ,register_y);
-                        self.registers[register_x] = self.registers[register_x] | self.registers[register_y];
-                    },
-                    0x2 => {    // AND Vx, Vy
-                        println!("AND V{:X}, V{:X}", register_x, register_y);
-                        self.registers[register_x] = self.registers[register_x] & self.registers[register_y];
-                    },
-                    0x3 => {    // XOR Vx, Vy
-                        println!("XOR V{:X}, V{:X}", register_x, register_y);
-                        self.registers[register_x] = self.registers[register_x] ^ self.registers[register_y];
-                    },
-                    0x4 => {    // ADD Vx, Vy
-                        println!("ADD V{:X}, V{:X}", register_x, register_y);
-                        let (val, overflow) = self.registers[register_x].overflowing_add(self.registers[register_y]);
-                        self.registers[register_x] = val;
-                        if overflow {
-                            self.registers[0xF] = 1;
-                        } else {
-                            self.registers[0xF] = 0;
-                        }
-                    },
-                    0x5 => {    // SUB Vx, Vy
-                        let x = self.registers[register_x];
-                        let y = self.registers[register_y];
-                        println!("SUB V{:X}, V{:X}", register_x, register_y);
-                        let (val, overflow) = x.overflowing_sub(y);
-                        self.registers[register_x] = val;
-                        if overflow {
-                            self.registers[0xF] = 0;
-                        } else {
-                            self.registers[0xF] = 1;
-                        }
-                    },
-                    0x6 => {    // SHR Vx
-                        println!("SHR V{:X}", register_x);
-
-                        let low_bits = self.registers[register_x] & 0x1;
-                        self.registers[register_x] >>= 1;
-
-                        if low_bits == 1 {
-                            self.registers[0xF] = 1;
-                        } else {
-                            self.registers[0xF] = 0;
-                        }
-                    },
-                    0x7 => {    // SUB Vx, Vy
-                        let x = self.registers[register_x];
-                        let y = self.registers[register_y];
-                        println!("SUB V{:X}, V{:X}", register_x, register_y);
-                        let (val, overflow) = y.overflowing_sub(x);
-                        self.registers[register_x] = val;
-
-                        if overflow {
-                            self.registers[0xF] = 0;
-                        } else {
-                            self.registers[0xF] = 1;
-                        }
-                    },
-                    0xE => {    // SHL Vx
-                        println!("SHL V{:X}", register_x);
-
-                        let high_bits = (self.registers[register_x] & 0x80) >> 7;
-                        self.registers[register_x] <<= 1;
-
-                        if high_bits == 1 {
-                            self.registers[0xF] = 1;
-                        } else {
-                            self.registers[0xF] = 0;
-                        }
-                    }
-                    _ => ()
-                }
-            },
-            0x9000..=0x9FF0 => {    // SNE Vx, Vy
-                let register_x = ((instruction & 0x0F00) >> 8) as usize;
-                let register_y = ((instruction & 0x00F0) >> 4) as usize;
-                println!("SNE V{:X}, V{:X}", register_x, register_y);
-                if self.registers[register_x] != self.registers[register_y] {
-                    self.pc += 2;
-                }
-            },
-            0xA000..=0xAFFF => {    // LD I, addr
-                let addr = instruction & 0x0FFF;
-                // println!("Loading {:#X} into I", value);
-                println!("LD I, {:#X}", addr);
-                self.i = addr;
-            },
-            0xB000..=0xBFFF => {    // JP V0, addr
-                let addr = instruction & 0x0FFF;
-                println!("JP V0, {:#X}", addr);
-                self.pc = addr + self.registers[0x0] as u16;
-            },
-            0xD000..=0xDFFF => {    // DRW Vx, Vy, bytes
-                let register_x = ((instruction & 0x0F00) >> 8) as usize;
-                let register_y = ((instruction & 0x00F0) >> 4) as usize;
-                let x = self.registers[register_x] as usize;
-                let y = self.registers[register_y] as usize;
-                let rows = (instruction & 0x000F) as usize;
-
-                println!("DRW V{:X}, V{:X}, {:#X}", register_x, register_y, rows);
-
-                self.registers[0xF] = 0;
-
-                // Draw pixels (each byte is a row starting at x, y). Each bit in the byte is a pixel (i.e. 0x00111100 would be __####__)
-                for row in 0..rows {
-                    // Get row data (byte)
-                    let row_byte = self.ram[self.i as usize + (row as usize)];
-
-                    // Each bit in row is a pixel starting at x, y and moving to the right (xor bit with pixel)
-                    for column in 0..8 {
-
-                        // If the current pixel we are looking at in the row is 1 XOR it onto the screen
-                        if ((row_byte >> (7 - column)) & 0x1) == 1 {
-
-                            // If color is clear draw pixel, else turn pixel off and set VF to 1
-                            if display.is_pixel_clear(x + column, y + row) {
-                                display.draw_pixel(x + column, y + row, 0x00FF0000);
-                            } else {
-                                display.clear_pixel(x + column, y + row);
-                                self.registers[0xF] = 1;
-                            }
-                        }
-                    }
-                }
-            },
-            0xE000..=0xEFFF => {
-                let byte = (instruction & 0xFF) as u8;
-                let register = ((instruction & 0x0F00) >> 8) as usize;
-                let key = self.registers[register] as usize;
-
-                match byte {
-                    0x9E => {   // SKP Vx
-                        println!("SKP V{:X}", register);
-                        if keyboard.borrow()[key] == true {
-                            self.pc += 2;
-                        }
-                    },
-                    0xA1 => {   // SKNP Vx
-                        println!("SKNP V{:X}", register);
-                        if keyboard.borrow()[key] == false {
-                            self.pc += 2;
-                        }
-                    }
-                    _ => ()
-                }
-            }
-            0xF000..=0xFFFF => {
-                let byte = (instruction & 0xFF) as u8;
-                let register = ((instruction & 0x0F00) >> 8) as usize;
-
-                match byte {
-                    0x07 => {   // LD Vx, DT
-                        println!("LD V{:X}, DT", register);
-                        self.registers[register] = self.dt;
-                    },
-                    0x0A => {   // LD Vx, K
-                        println!("LD V{:X}, K", register);
-                        // Check if a key is pressed
-                        let mut pressed = false;
-                        for (key, state) in keyboard.borrow().iter().enumerate() {
-                            if *state {
-                                pressed = true;
-                                self.registers[register] = key as u8;
-                            }
-                        }
-
-                        // If no keys are pressed, decrement pc
-                        if !pressed {
-                            // Decrement pc, essentially causing the program to loop
-                            self.pc -= 2;
-                        }
-                    }
-                    0x15 => {   // LD DT, Vx
-                        println!("LD DT, V{:X}", register);
-                        self.dt = self.registers[register];
-                    },
-                    0x18 => {   // LD ST, Vx
-                        println!("LD ST, V{:X}", register);
-                        self.st = self.registers[register];
-                    },
-                    0x1E => {   // ADD I, Vx
-                        println!("ADD I, V{:X}", register);
-                        self.i += self.registers[register] as u16;
-                    },
-                    0x29 => {   // LD F, Vx
-                        println!("LD F, V{:X}", register);
-                        let key = self.registers[register];
-                        self.i = 0x0050 + ((key * 5) as u16);
-                    }
-                    0x33 => {   // LD B, Vx
-                        let val = self.registers[register];
-                        let ones = val % 10;
-                        let tens = (val / 10) % 10;
-                        let hundreds = (val / 100) % 10;
-
-                        println!("LD B, V{:X}", register);
-
-                        println!("{}, {} : {} : {}", val, hundreds, tens, ones);
-                        self.ram[self.i as usize] = hundreds;
-                        self.ram[self.i as usize + 1] = tens;
-                        self.ram[self.i as usize + 2] = ones;
-                    },
-                    0x55 => {   // LD [I], Vx
-                        // Load all registers from 0..=register into memory starting at i
-                        println!("LD [I], V{:X}", register);
-                        for index in 0..=register {
-                            self.ram[self.i as usize + index] = self.registers[index];
-                        }
-                    },
-                    0x65 => {   // LD Vx, [I]
-                        println!("LD V{:X}, [I]", register);
-                        for index in 0..=register {
-                            self.registers[index] = self.ram[self.i as usize + index];
-                        }
-                    },
-                    _ => {
-                        println!("Unimplemented Instruction: {:#X}", instruction);
-                    }
+    if let Some(hz) = options.clock {
+        machine.set_clock(hz);
+    }
 
+    // Print the seed we picked so the run can be reproduced with --seed
+    let rng = options.rng.or(movie.as_ref().map(|movie| movie.rng)).unwrap_or_default();
+    let seed = options.seed.or(movie.as_ref().map(|movie| movie.seed)).unwrap_or_else(|| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seed = now.as_nanos() as u64;
+        eprintln!("Random seed: {}", seed);
+        seed
+    });
+    machine.seed_rng(rng, seed);
+
+    if options.trace.any() {
+        let tracer = match &options.trace_file {
+            Some(path) => match fs::File::create(path) {
+                Ok(file) => Tracer::new(options.trace, Box::new(BufWriter::new(file))),
+                Err(error) => {
+                    return eprintln!("Could not create trace file: {}", error);
                 }
             },
-            _ => {
-                println!("Unimplemented Instruction: {:#X}", instruction);
-            }
-        }
+            None => Tracer::stdout(options.trace)
+        };
+        machine.set_tracer(Some(tracer));
     }
-}
-
-struct Chip8KeyboardCallback {
-    keys: Rc<RefCell<[bool; 16]>>
-}
 
-impl Chip8KeyboardCallback {
-    pub fn new(keys: Rc<RefCell<[bool; 16]>>) -> Self {
-        Chip8KeyboardCallback { 
-            keys: keys
+    if let Some(movie) = &movie {
+        for mismatch in movie.mismatches(&rom, &machine, rng, seed) {
+            eprintln!("Movie will desync: {}", mismatch);
         }
     }
-}
+    let movie_recording = options.record_movie.as_ref().map(|path| (Movie::new(&rom, &machine, rng, seed), PathBuf::from(path)));
 
+    let recorder = match &options.record {
+        Some(path) => match open_recorder(path, options.record_format, palette) {
+            Ok(recorder) => Some(recorder),
+            Err(error) => return eprintln!("{}", error)
+        },
+        None => None
+    };
 
-impl minifb::InputCallback for Chip8KeyboardCallback {
-    fn add_char(&mut self, uni_char: u32) {
-
+    if let Some(frames) = options.headless {
+        return run_headless(&mut machine, frames, recorder, movie.as_ref(), movie_recording);
     }
 
-    fn set_key_state(&mut self, _key: minifb::Key, _state: bool) {
-        match _key {
-            minifb::Key::Key1 => { self.keys.borrow_mut()[0x1] = _state; },
-            minifb::Key::Key2 => { self.keys.borrow_mut()[0x2] = _state; },
-            minifb::Key::Key3 => { self.keys.borrow_mut()[0x3] = _state; },
-            minifb::Key::Key4 => { self.keys.borrow_mut()[0xC] = _state; },
-            minifb::Key::Q => { self.keys.borrow_mut()[0x4] = _state; },
-            minifb::Key::W => { self.keys.borrow_mut()[0x5] = _state; },
-            minifb::Key::E => { self.keys.borrow_mut()[0x6] = _state; },
-            minifb::Key::R => { self.keys.borrow_mut()[0xD] = _state; },
-            minifb::Key::A => { self.keys.borrow_mut()[0x7] = _state; },
-            minifb::Key::S => { self.keys.borrow_mut()[0x8] = _state; },
-            minifb::Key::D => { self.keys.borrow_mut()[0x9] = _state; },
-            minifb::Key::F => { self.keys.borrow_mut()[0xE] = _state; },
-            minifb::Key::Z => { self.keys.borrow_mut()[0xA] = _state; },
-            minifb::Key::X => { self.keys.borrow_mut()[0x0] = _state; },
-            minifb::Key::C => { self.keys.borrow_mut()[0xB] = _state; },
-            minifb::Key::V => { self.keys.borrow_mut()[0xF] = _state; },
-            _ => ()
+    let mut emulator = match Chip8Emulator::with_settings(machine, &window_settings) {
+        Ok(emulator) => emulator,
+        Err(_) => {
+            return eprintln!("Failed to load emulator")
         }
-    }
-}
+    };
 
-struct Chip8Emulator {
-    cpu: Chip8CPU,
-    display: Display,
-    keyboard: Rc<RefCell<[bool; 16]>>,
-    clock_speed: f32    // speed in hz
-}
+    emulator.set_keymap(&keymap);
+    emulator.set_palette(palette);
+    emulator.set_beep_indicator(options.beep_indicator);
+    emulator.set_state_path(PathBuf::from(&options.rom_path));
+    emulator.set_rewind(options.rewind_frames, options.rewind_budget);
+    emulator.set_screenshot_format(options.screenshot_format);
 
-impl Chip8Emulator {
-    pub fn new() -> Result<Self, EmulatorError> {
-
-        let mut display = Display::new("CHIP-8 Emulator", WIDTH, HEIGHT)
-                                        .map_err(|_e| EmulatorError::DisplayCreationError)?;
-
-        let keyboard = Rc::new(RefCell::new([false; 16]));
-        display.window.set_input_callback(Box::new(Chip8KeyboardCallback::new(keyboard.clone())));
-
-        let mut cpu = Chip8CPU::new();
-
-        let font: Vec<u8> = vec![
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-        ];
-        cpu.load_font(&font).unwrap();
-
-        Ok(
-            Chip8Emulator { 
-                cpu: cpu, 
-                display: display,
-                keyboard: keyboard,
-                clock_speed: 60.0 
-            }
-        )
+    if let Some(recorder) = recorder {
+        emulator.start_recording(recorder);
     }
-
-    pub fn set_clock(&mut self, hz: f32) {
-        self.clock_speed = hz;
+    if let Some(movie) = movie {
+        emulator.play_movie(movie);
+    }
+    if let Some((movie, path)) = movie_recording {
+        emulator.record_movie(movie, path);
     }
 
-    pub fn load_rom(&mut self, rom: &Vec<u8>) {
-        self.cpu.load_rom(rom).unwrap();
+    if options.debug {
+        emulator.enable_debugger();
     }
 
-    pub fn run(&mut self) {
-        let interval = Duration::from_secs_f32(1.0 / self.clock_speed);
-        let mut next_time = Instant::now() + interval;
-        while self.display.window.is_open() {
-            let sleep_time = next_time - Instant::now();
-            thread::sleep(sleep_time);
-            next_time += interval;
-            self.cpu.tick(&mut self.display, &mut self.keyboard);
-            self.display.window.update_with_buffer(&*self.display.framebuffer, WIDTH, HEIGHT).unwrap();
+    if let Some(port) = options.gdb_port {
+        match emulator.enable_gdb(port) {
+            Ok(addr) => eprintln!("Waiting for GDB on {}", addr),
+            Err(error) => return eprintln!("Could not listen for GDB: {}", error)
         }
     }
+
+    emulator.run();
+
 }
 
-fn main() {
+/// Record to `path`, or to stdout for `-`, in `format` or the one its
+/// extension names
+fn open_recorder(path: &str, format: Option<VideoFormat>, palette: Palette) -> Result<VideoRecorder, String> {
+    let format = format.or_else(|| VideoFormat::from_path(Path::new(path)))
+                    .ok_or(format!("Can't tell the video format of {}, use --record-format", path))?;
+
+    let writer: Box<dyn Write> = if path == "-" {
+        Box::new(BufWriter::new(io::stdout()))
+    } else {
+        let file = fs::File::create(path).map_err(|error| format!("Could not create {}: {}", path, error))?;
+        Box::new(BufWriter::new(file))
+    };
 
-    let args: Vec<String> = env::args().collect();
+    Ok(VideoRecorder::new(writer, format, RECORD_SCALE, palette))
+}
 
-    // Load rom here
-    if args.len() != 2 {
-        return eprintln!("Usage: {} <rom-path>", args[0]);
-    }
+/// Run `frames` frames as fast as possible with no window. Keys come from
+/// the movie being played, if any, and stay up otherwise. Emulated time is
+/// all that counts, so the same options give the same run.
+fn run_headless(
+    machine: &mut Chip8Machine,
+    frames: u64,
+    mut recorder: Option<VideoRecorder>,
+    playback: Option<&Movie>,
+    mut movie_recording: Option<(Movie, PathBuf)>
+) {
+    for _ in 0..frames {
+        let frame = machine.frame();
+        if machine.has_exited() || playback.is_some_and(|movie| frame >= movie.length) {
+            break;
+        }
 
-    let rom = match fs::read(String::from(&args[1])) {
-        Err(error) => {
-            return eprintln!("Could not open file: {}", error.to_string());
-        },
-        Ok(file) => file
-    };
+        machine.keys = playback.map(|movie| movie.keys_at(frame)).unwrap_or_default();
+        if let Some((movie, _)) = movie_recording.as_mut() {
+            movie.record(frame, machine.keys);
+        }
 
-    let mut emulator = match Chip8Emulator::new() {
-        Ok(emulator) => emulator,
-        Err(_) => {
-            return eprintln!("Failed to load emulator")
+        if let Err(fault) = machine.run_frame() {
+            eprintln!("CPU halted: {}", fault);
+            break;
         }
-    };
 
-    emulator.load_rom(&rom);
-    emulator.set_clock(256.0);
-    emulator.run();
+        if let Some(recorder) = recorder.as_mut()
+            && let Err(error) = recorder.record_frame(&machine.display) {
+            return eprintln!("Could not record: {}", error);
+        }
+    }
 
-}
\ No newline at end of file
+    if let Some(recorder) = recorder
+        && let Err(error) = recorder.finish() {
+        eprintln!("Could not finish recording: {}", error);
+    }
+
+    if let Some(movie) = playback {
+        if machine.frame() < movie.length {
+            eprintln!("Stopped at frame {}, before the movie's end at frame {}", machine.frame(), movie.length);
+        } else {
+            match movie.check_end(machine) {
+                Ok(()) => eprintln!("Movie finished after {} frames, in sync", movie.length),
+                Err(error) => eprintln!("{}", error)
+            }
+        }
+    }
+
+    if let Some((mut movie, path)) = movie_recording {
+        movie.finish(machine);
+        if let Err(error) = movie.save(&path) {
+            eprintln!("Could not save movie to {}: {}", path.display(), error);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Input movies: every keypad change of a run, for replaying it exactly.
+//!
+//! The machine is deterministic, so a run is fully described by the ROM, the
+//! settings it was started with and the keys held on every frame. A movie
+//! stores those as text:
+//!
+//! ```text
+//! CHIP-8 movie 1
+//! rom 8B2A6A45
+//! platform chip8
+//! quirks shift_uses_vy=0 load_store_increments_i=0 jump_uses_vx=0 logic_resets_vf=0 clip_sprites=1 wait_for_vblank=0
+//! clock 256
+//! rng xorshift 12345
+//! length 600
+//! end 1C0FFEE5
+//! 0 0000
+//! 42 0020
+//! 45 0000
+//! ```
+//!
+//! `rom` is the CRC-32 of the ROM. The lines after the header are a frame
+//! number and the keys held from that frame on, as a mask with bit n for key
+//! n. `end` is a hash of the machine's state after `length` frames, so
+//! playback can tell whether it ended up in the same place.
+
+use std::{fs, io, path::Path};
+
+use crate::{
+    machine::Chip8Machine,
+    platform::Platform,
+    quirks::Quirks,
+    rng::RngKind,
+    savestate::crc32
+};
+
+pub const MOVIE_VERSION: u32 = 1;
+const MOVIE_MAGIC: &str = "CHIP-8 movie";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    /// CRC-32 of the ROM
+    pub rom_hash: u32,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub clock_hz: u32,
+    pub rng: RngKind,
+    pub seed: u64,
+    /// Frames recorded
+    pub length: u64,
+    /// Hash of the machine state after `length` frames
+    pub end_hash: Option<u32>,
+    /// Keys held from each frame on, in frame order
+    pub inputs: Vec<(u64, [bool; 16])>
+}
+
+impl Movie {
+    /// Start a movie of `rom` running on `machine`, which must not have run
+    /// yet. `rng` and `seed` are what the machine was seeded with.
+    pub fn new(rom: &[u8], machine: &Chip8Machine, rng: RngKind, seed: u64) -> Self {
+        Movie {
+            rom_hash: crc32(rom),
+            platform: machine.platform(),
+            quirks: machine.quirks(),
+            clock_hz: machine.clock_hz(),
+            rng,
+            seed,
+            length: 0,
+            end_hash: None,
+            inputs: Vec::new()
+        }
+    }
+
+    /// Note the keys held while running `frame`. Going back to an earlier
+    /// frame, after rewinding or loading a state, records over what came
+    /// after it.
+    pub fn record(&mut self, frame: u64, keys: [bool; 16]) {
+        let kept = self.inputs.partition_point(|(start, _)| *start < frame);
+        self.inputs.truncate(kept);
+
+        if self.keys_at(frame) != keys {
+            self.inputs.push((frame, keys));
+        }
+        self.end_hash = None;
+    }
+
+    /// Mark the end of the recording at the machine's current frame
+    pub fn finish(&mut self, machine: &Chip8Machine) {
+        self.length = machine.frame();
+        self.end_hash = Some(state_hash(machine));
+    }
+
+    /// Keys held during `frame`
+    pub fn keys_at(&self, frame: u64) -> [bool; 16] {
+        let index = self.inputs.partition_point(|(start, _)| *start <= frame);
+        if index == 0 {
+            [false; 16]
+        } else {
+            self.inputs[index - 1].1
+        }
+    }
+
+    /// Set up a fresh machine the way the movie was recorded
+    pub fn configure(&self, machine: &mut Chip8Machine) {
+        machine.set_platform(self.platform);
+        machine.set_quirks(self.quirks);
+        machine.set_clock(self.clock_hz);
+        machine.seed_rng(self.rng, self.seed);
+    }
+
+    /// Everything about `rom` and `machine`'s setup that doesn't match the
+    /// movie and will make playback desync. Call it before running.
+    pub fn mismatches(&self, rom: &[u8], machine: &Chip8Machine, rng: RngKind, seed: u64) -> Vec<String> {
+        let mut mismatches = Vec::new();
+
+        if crc32(rom) != self.rom_hash {
+            mismatches.push(format!("ROM hash is {:08X}, the movie was recorded with {:08X}", crc32(rom), self.rom_hash));
+        }
+        if machine.platform() != self.platform {
+            mismatches.push(format!("platform is {}, the movie was recorded on {}", machine.platform().name(), self.platform.name()));
+        }
+        if machine.quirks() != self.quirks {
+            mismatches.push(format!("quirks are {}, the movie was recorded with {}", quirks_text(&machine.quirks()), quirks_text(&self.quirks)));
+        }
+        if machine.clock_hz() != self.clock_hz {
+            mismatches.push(format!("clock is {} Hz, the movie was recorded at {} Hz", machine.clock_hz(), self.clock_hz));
+        }
+        if rng != self.rng || seed != self.seed {
+            mismatches.push(format!("rng is {} {}, the movie was recorded with {} {}", rng.name(), seed, self.rng.name(), self.seed));
+        }
+
+        mismatches
+    }
+
+    /// Check the machine ended up where the recording did. Call it once the
+    /// machine has run `length` frames.
+    pub fn check_end(&self, machine: &Chip8Machine) -> Result<(), String> {
+        match self.end_hash {
+            Some(hash) if hash != state_hash(machine) => {
+                Err(format!("Desync: the machine state after frame {} doesn't match the recording", self.length))
+            },
+            _ => Ok(())
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{} {}\n", MOVIE_MAGIC, MOVIE_VERSION);
+        text.push_str(&format!("rom {:08X}\n", self.rom_hash));
+        text.push_str(&format!("platform {}\n", self.platform.name()));
+        text.push_str(&format!("quirks {}\n", quirks_text(&self.quirks)));
+        text.push_str(&format!("clock {}\n", self.clock_hz));
+        text.push_str(&format!("rng {} {}\n", self.rng.name(), self.seed));
+        text.push_str(&format!("length {}\n", self.length));
+        if let Some(hash) = self.end_hash {
+            text.push_str(&format!("end {:08X}\n", hash));
+        }
+
+        for (frame, keys) in &self.inputs {
+            let mask = keys.iter().enumerate().fold(0u16, |mask, (key, held)| mask | ((*held as u16) << key));
+            text.push_str(&format!("{} {:04X}\n", frame, mask));
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+
+        match lines.next() {
+            Some((_, line)) if line.trim() == format!("{} {}", MOVIE_MAGIC, MOVIE_VERSION) => (),
+            Some((_, line)) if line.starts_with(MOVIE_MAGIC) => return Err(format!("Unsupported movie version '{}'", line)),
+            _ => return Err(String::from("Not a CHIP-8 movie"))
+        }
+
+        let mut movie = Movie {
+            rom_hash: 0,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            clock_hz: 0,
+            rng: RngKind::default(),
+            seed: 0,
+            length: 0,
+            end_hash: None,
+            inputs: Vec::new()
+        };
+        let mut seen = Vec::new();
+
+        for (index, line) in lines {
+            let error = |message: &str| format!("line {}: {}", index + 1, message);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let value = |n: usize| words.get(n).copied().ok_or_else(|| error("missing value"));
+
+            match words[0] {
+                "rom" => movie.rom_hash = u32::from_str_radix(value(1)?, 16).map_err(|_| error("invalid ROM hash"))?,
+                "platform" => movie.platform = Platform::from_name(value(1)?).ok_or_else(|| error("unknown platform"))?,
+                "quirks" => movie.quirks = parse_quirks(&words[1..]).ok_or_else(|| error("invalid quirks"))?,
+                "clock" => movie.clock_hz = value(1)?.parse().map_err(|_| error("invalid clock"))?,
+                "rng" => {
+                    movie.rng = RngKind::from_name(value(1)?).ok_or_else(|| error("unknown rng"))?;
+                    movie.seed = value(2)?.parse().map_err(|_| error("invalid seed"))?;
+                },
+                "length" => movie.length = value(1)?.parse().map_err(|_| error("invalid length"))?,
+                "end" => movie.end_hash = Some(u32::from_str_radix(value(1)?, 16).map_err(|_| error("invalid end hash"))?),
+                frame => {
+                    let frame: u64 = frame.parse().map_err(|_| error("expected a frame number"))?;
+                    let mask = u16::from_str_radix(value(1)?, 16).map_err(|_| error("invalid key mask"))?;
+                    if movie.inputs.last().is_some_and(|(last, _)| *last >= frame) {
+                        return Err(error("frames are out of order"));
+                    }
+
+                    let keys = std::array::from_fn(|key| mask & (1 << key) != 0);
+                    movie.inputs.push((frame, keys));
+                    continue;
+                }
+            }
+            seen.push(words[0]);
+        }
+
+        for required in ["rom", "platform", "quirks", "clock", "rng", "length"] {
+            if !seen.contains(&required) {
+                return Err(format!("Movie has no {} line", required));
+            }
+        }
+        Ok(movie)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::from_text(&text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// Hash of everything a save state holds
+pub fn state_hash(machine: &Chip8Machine) -> u32 {
+    // Leave out the state's own CRC at the end, with it every CRC comes out
+    // the same
+    let bytes = machine.save_state().to_bytes();
+    crc32(&bytes[..bytes.len() - 4])
+}
+
+fn quirk_flags(quirks: &Quirks) -> [(&'static str, bool); 6] {
+    [
+        ("shift_uses_vy", quirks.shift_uses_vy),
+        ("load_store_increments_i", quirks.load_store_increments_i),
+        ("jump_uses_vx", quirks.jump_uses_vx),
+        ("logic_resets_vf", quirks.logic_resets_vf),
+        ("clip_sprites", quirks.clip_sprites),
+        ("wait_for_vblank", quirks.wait_for_vblank)
+    ]
+}
+
+fn quirks_text(quirks: &Quirks) -> String {
+    let flags: Vec<String> = quirk_flags(quirks).iter().map(|(name, on)| format!("{}={}", name, *on as u8)).collect();
+    flags.join(" ")
+}
+
+/// Every quirk has to be given, so a movie never depends on defaults
+fn parse_quirks(words: &[&str]) -> Option<Quirks> {
+    let mut quirks = Quirks::default();
+    let mut given = 0;
+
+    for word in words {
+        let (name, value) = word.split_once('=')?;
+        let on = match value {
+            "0" => false,
+            "1" => true,
+            _ => return None
+        };
+        let flag = match name {
+            "shift_uses_vy" => &mut quirks.shift_uses_vy,
+            "load_store_increments_i" => &mut quirks.load_store_increments_i,
+            "jump_uses_vx" => &mut quirks.jump_uses_vx,
+            "logic_resets_vf" => &mut quirks.logic_resets_vf,
+            "clip_sprites" => &mut quirks.clip_sprites,
+            "wait_for_vblank" => &mut quirks.wait_for_vblank,
+            _ => return None
+        };
+        *flag = on;
+        given += 1;
+    }
+
+    (given == quirk_flags(&quirks).len()).then_some(quirks)
+}
@@ -0,0 +1,170 @@
+//! Flicker filters for the presentation layer.
+//!
+//! CHIP-8 games move sprites by XORing them off and back on, so a sprite is
+//! often missing from the frame that gets shown. These filters smooth that
+//! over like the slow phosphor of an old CRT:
+//!
+//! - Fade: a pixel that goes dark fades out to the background over a number
+//!   of frames instead of vanishing at once.
+//! - Blend: every pixel is the average of its colors over the last frames.
+//!
+//! They only change the colors a [`Renderer`](crate::render::Renderer) puts
+//! on screen. The display itself, and with it collisions, save states,
+//! screenshots and recordings, never sees them.
+
+use std::collections::VecDeque;
+
+use crate::{display::Display, render::Palette};
+
+/// Frames a dark pixel takes to fade out when none are given
+pub const DEFAULT_FADE_FRAMES: u32 = 4;
+
+/// Frames averaged together when none are given
+pub const DEFAULT_BLEND_FRAMES: u32 = 2;
+
+/// Most frames either filter can be given, a second of them
+pub const MAX_PHOSPHOR_FRAMES: u32 = 60;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PhosphorMode {
+    #[default]
+    Off,
+    /// Pixels fade out over this many frames
+    Fade(u32),
+    /// Colors are averaged over this many frames
+    Blend(u32)
+}
+
+impl PhosphorMode {
+    /// `off`, `fade` or `blend`, optionally with a frame count from 1 to
+    /// [`MAX_PHOSPHOR_FRAMES`] like `fade:8`
+    pub fn parse(text: &str) -> Option<Self> {
+        let (name, frames) = match text.split_once(':') {
+            Some((name, frames)) => {
+                let frames = frames.parse::<u32>().ok().filter(|frames| (1..=MAX_PHOSPHOR_FRAMES).contains(frames))?;
+                (name, Some(frames))
+            },
+            None => (text, None)
+        };
+
+        match name.to_ascii_lowercase().as_str() {
+            "off" if frames.is_none() => Some(PhosphorMode::Off),
+            "fade" => Some(PhosphorMode::Fade(frames.unwrap_or(DEFAULT_FADE_FRAMES))),
+            "blend" => Some(PhosphorMode::Blend(frames.unwrap_or(DEFAULT_BLEND_FRAMES))),
+            _ => None
+        }
+    }
+}
+
+/// Works out the color of every CHIP-8 pixel for one shown frame, keeping
+/// whatever the mode needs from the frames before it
+#[derive(Clone, Debug, Default)]
+pub struct Phosphor {
+    mode: PhosphorMode,
+    width: usize,
+    height: usize,
+    colors: Vec<u32>,
+    /// Fade: the last lit color of every pixel and frames since it was lit
+    lit: Vec<(u32, u32)>,
+    /// Blend: colors of the frames being averaged, newest last
+    history: VecDeque<Vec<u32>>
+}
+
+impl Phosphor {
+    pub fn new(mode: PhosphorMode) -> Self {
+        Phosphor {
+            mode,
+            ..Phosphor::default()
+        }
+    }
+
+    pub fn mode(&self) -> PhosphorMode {
+        self.mode
+    }
+
+    /// Change the mode, starting from a clean screen
+    pub fn set_mode(&mut self, mode: PhosphorMode) {
+        self.mode = mode;
+        self.reset();
+    }
+
+    /// Forget the frames seen so far
+    pub fn reset(&mut self) {
+        self.lit.clear();
+        self.history.clear();
+    }
+
+    /// Colors of every pixel of `display`, row by row, for the next shown
+    /// frame. Call it once per frame.
+    pub fn apply(&mut self, display: &Display, palette: &Palette) -> &[u32] {
+        // Switching between lores and hires starts over
+        if display.width() != self.width || display.height() != self.height {
+            self.width = display.width();
+            self.height = display.height();
+            self.reset();
+        }
+
+        self.colors.clear();
+        self.colors.extend(display.pixels().iter().map(|planes| palette.color(*planes)));
+
+        match self.mode {
+            PhosphorMode::Off => (),
+            // Modes built without parse could ask for anything
+            PhosphorMode::Fade(frames) => self.fade(display, palette, frames.min(MAX_PHOSPHOR_FRAMES)),
+            PhosphorMode::Blend(frames) => self.blend(frames.clamp(1, MAX_PHOSPHOR_FRAMES))
+        }
+
+        &self.colors
+    }
+
+    fn fade(&mut self, display: &Display, palette: &Palette, frames: u32) {
+        if self.lit.len() != self.colors.len() {
+            self.lit = vec![(palette.background, frames.saturating_add(1)); self.colors.len()];
+        }
+
+        for ((color, (lit_color, age)), planes) in self.colors.iter_mut().zip(self.lit.iter_mut()).zip(display.pixels()) {
+            if *planes != 0 {
+                *lit_color = *color;
+                *age = 0;
+                continue;
+            }
+
+            *age = age.saturating_add(1);
+            if *age <= frames {
+                // Most of the glow is left on the first dark frame, none
+                // after the last one
+                let left = frames.saturating_add(1) - *age;
+                *color = mix(palette.background, *lit_color, left, frames.saturating_add(1));
+            }
+        }
+    }
+
+    fn blend(&mut self, frames: u32) {
+        self.history.push_back(self.colors.clone());
+        while self.history.len() > frames as usize {
+            self.history.pop_front();
+        }
+
+        let count = self.history.len() as u32;
+        for (index, color) in self.colors.iter_mut().enumerate() {
+            let mut sums = [0u32; 3];
+            for frame in &self.history {
+                let [_, red, green, blue] = frame[index].to_be_bytes();
+                sums[0] += red as u32;
+                sums[1] += green as u32;
+                sums[2] += blue as u32;
+            }
+            *color = (sums[0] / count) << 16 | (sums[1] / count) << 8 | (sums[2] / count);
+        }
+    }
+}
+
+/// `from` moved `amount`/`total` of the way to `to`, per channel
+fn mix(from: u32, to: u32, amount: u32, total: u32) -> u32 {
+    let channel = |shift: u32| {
+        let from = (from >> shift) & 0xFF;
+        let to = (to >> shift) & 0xFF;
+        (from * (total - amount) + to * amount) / total
+    };
+    channel(16) << 16 | channel(8) << 8 | channel(0)
+}
@@ -0,0 +1,90 @@
+//! The CHIP-8 variants the emulator can run as.
+
+use crate::{
+    cpu::RAM_SIZE,
+    instruction::Instruction,
+    quirks::Quirks
+};
+
+pub const XO_RAM_SIZE: usize = 65536;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    /// The original CHIP-8 instruction set
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: hires mode, scrolling, big font and RPL flags
+    SuperChip,
+    /// XO-CHIP: SUPER-CHIP plus 64K memory, two bit-planes and audio patterns
+    XoChip
+}
+
+impl Platform {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Some(Platform::SuperChip),
+            "xochip" | "xo-chip" => Some(Platform::XoChip),
+            _ => None
+        }
+    }
+
+    /// The name [`Platform::from_name`] takes
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip"
+        }
+    }
+
+    /// Quirks ROMs written for this platform usually expect
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::modern(),
+            Platform::SuperChip => Quirks::schip(),
+            Platform::XoChip => Quirks::xochip()
+        }
+    }
+
+    /// Size of addressable memory in bytes
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => RAM_SIZE,
+            Platform::XoChip => XO_RAM_SIZE
+        }
+    }
+
+    /// Number of RPL user flags FX75/FX85 can reach
+    pub fn rpl_flags(&self) -> usize {
+        match self {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 8,
+            Platform::XoChip => 16
+        }
+    }
+
+    /// Whether this platform has the instruction. Decoding always knows every
+    /// instruction, so the CPU checks this before executing one.
+    pub fn supports(&self, instruction: &Instruction) -> bool {
+        match instruction {
+            Instruction::ScrollDown { .. }
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::Lores
+            | Instruction::Hires
+            | Instruction::LoadBigFont { .. } => *self != Platform::Chip8,
+            Instruction::ScrollUp { .. }
+            | Instruction::SaveRange { .. }
+            | Instruction::LoadRange { .. }
+            | Instruction::LoadLongI { .. }
+            | Instruction::SelectPlanes { .. }
+            | Instruction::LoadAudio
+            | Instruction::SetPitch { .. } => *self == Platform::XoChip,
+            Instruction::StoreFlags { x } | Instruction::LoadFlags { x } => (*x as usize) < self.rpl_flags(),
+            Instruction::Invalid { .. } => false,
+            _ => true
+        }
+    }
+}
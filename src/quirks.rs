@@ -0,0 +1,91 @@
+//! Behaviors that differ between CHIP-8 interpreters.
+//!
+//! ROMs are written against one interpreter and often depend on how it
+//! handled the ambiguous instructions, so the CPU takes these as settings
+//! instead of hardcoding one interpretation. Presets follow the quirks table
+//! of Timendus' chip8-test-suite.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift Vy into Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    /// FX55/FX65 leave I pointing just past the last register
+    pub load_store_increments_i: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+    /// DXYN clips sprites at the screen edges instead of wrapping them
+    pub clip_sprites: bool,
+    /// DXYN waits for the next 60 Hz frame before the program carries on
+    pub wait_for_vblank: bool
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::modern()
+    }
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub fn vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            wait_for_vblank: true
+        }
+    }
+
+    /// SUPER-CHIP 1.1 on the HP48
+    pub fn schip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            wait_for_vblank: false
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            wait_for_vblank: false
+        }
+    }
+
+    /// What most modern interpreters (and this one, originally) do
+    pub fn modern() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            wait_for_vblank: false
+        }
+    }
+
+    /// Look up a preset by name. There's no `chip8` preset: the CHIP-8
+    /// platform runs with [`Quirks::modern`] by default, the original
+    /// interpreter's quirks are `vip`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" => Some(Self::vip()),
+            "schip" | "superchip" | "super-chip" => Some(Self::schip()),
+            "xochip" | "xo-chip" => Some(Self::xochip()),
+            "modern" => Some(Self::modern()),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! Turns the machine's logical display into host pixels.
+//!
+//! Colors come from a [`Palette`], either one of the presets or set in the
+//! `[palette]` section of the config file (`[palette rom]` for a single
+//! ROM). Colors are written `#RRGGBB`:
+//!
+//! ```text
+//! [palette]
+//! preset = lcd
+//! buzzer = #FF0000
+//! ```
+
+use crate::{
+    config::Config,
+    display::Display,
+    phosphor::{Phosphor, PhosphorMode}
+};
+
+pub const DEFAULT_BACKGROUND: u32 = 0x004D4D4D;
+pub const DEFAULT_FOREGROUND: u32 = 0x00FF0000;
+pub const DEFAULT_PLANE2: u32 = 0x003399FF;
+pub const DEFAULT_OVERLAP: u32 = 0x00FFFFFF;
+pub const DEFAULT_BUZZER: u32 = 0x00990000;
+pub const DEFAULT_SILENCE: u32 = 0x00000000;
+
+/// Preset names for [`Palette::preset`]. All but the first are Octo's.
+pub const PALETTE_PRESETS: [&str; 5] = ["default", "octo", "lcd", "hotdog", "gray"];
+
+/// Names of the colors in a palette, as used in the config file and by
+/// [`Palette::set`]
+pub const PALETTE_COLORS: [&str; 6] = ["background", "foreground", "plane2", "overlap", "buzzer", "silence"];
+
+/// Colors for each pixel value, as 0RGB.
+///
+/// Only XO-CHIP uses the second plane, everything else just needs
+/// `background` and `foreground`. `buzzer` and `silence` are for the beep
+/// indicator around the display, shown while the sound timer runs or not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub background: u32,    // no plane set
+    pub foreground: u32,    // plane 1 only
+    pub plane2: u32,        // plane 2 only
+    pub overlap: u32,       // both planes
+    pub buzzer: u32,        // around the display while beeping
+    pub silence: u32        // around the display otherwise
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            background: DEFAULT_BACKGROUND,
+            foreground: DEFAULT_FOREGROUND,
+            plane2: DEFAULT_PLANE2,
+            overlap: DEFAULT_OVERLAP,
+            buzzer: DEFAULT_BUZZER,
+            silence: DEFAULT_SILENCE
+        }
+    }
+}
+
+impl Palette {
+    /// Octo's own colors, yellow and orange on brown
+    pub fn octo() -> Self {
+        Palette {
+            background: 0x00996600,
+            foreground: 0x00FFCC00,
+            plane2: 0x00FF6600,
+            overlap: 0x00662200,
+            buzzer: 0x00FFAA00,
+            silence: 0x00000000
+        }
+    }
+
+    /// Dark green on pale green, like a Game Boy screen
+    pub fn lcd() -> Self {
+        Palette {
+            background: 0x00F9FFB3,
+            foreground: 0x003D8026,
+            plane2: 0x00ABCC47,
+            overlap: 0x0000131A,
+            buzzer: 0x00F9FFB3,
+            silence: 0x00000000
+        }
+    }
+
+    /// Red and yellow on black
+    pub fn hotdog() -> Self {
+        Palette {
+            background: 0x00000000,
+            foreground: 0x00FF0000,
+            plane2: 0x00FFFF00,
+            overlap: 0x00FFFFFF,
+            buzzer: 0x00330000,
+            silence: 0x00990000
+        }
+    }
+
+    /// Black and white on gray
+    pub fn gray() -> Self {
+        Palette {
+            background: 0x00AAAAAA,
+            foreground: 0x00000000,
+            plane2: 0x00FFFFFF,
+            overlap: 0x00666666,
+            buzzer: 0x00AAAAAA,
+            silence: 0x00666666
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "default" => Some(Self::default()),
+            "octo" => Some(Self::octo()),
+            "lcd" => Some(Self::lcd()),
+            "hotdog" => Some(Self::hotdog()),
+            "gray" | "grey" => Some(Self::gray()),
+            _ => None
+        }
+    }
+
+    /// Set the color called `name` (see [`PALETTE_COLORS`]) from a color
+    /// written `#RRGGBB`
+    pub fn set(&mut self, name: &str, color: &str) -> Result<(), String> {
+        let color = parse_color(color).ok_or(format!("'{}' isn't a color, expected #RRGGBB", color))?;
+        let slot = match name.to_ascii_lowercase().as_str() {
+            "background" => &mut self.background,
+            "foreground" => &mut self.foreground,
+            "plane2" => &mut self.plane2,
+            "overlap" => &mut self.overlap,
+            "buzzer" => &mut self.buzzer,
+            "silence" => &mut self.silence,
+            _ => return Err(format!("unknown color '{}', expected one of {}", name, PALETTE_COLORS.join(", ")))
+        };
+        *slot = color;
+        Ok(())
+    }
+
+    /// Start from `base` and apply the `[palette]` sections of `config` that
+    /// apply to `rom`. Errors name the line they're on.
+    pub fn from_config(base: Palette, config: &Config, rom: Option<&str>) -> Result<Self, String> {
+        let mut palette = base;
+
+        for section in config.sections("palette", rom) {
+            for entry in &section.entries {
+                let error = |message: String| format!("line {}: {}", entry.line, message);
+
+                if entry.name == "preset" {
+                    palette = Self::preset(&entry.value).ok_or_else(|| {
+                        error(format!("unknown palette '{}', expected one of {}", entry.value, PALETTE_PRESETS.join(", ")))
+                    })?;
+                } else {
+                    palette.set(&entry.name, &entry.value).map_err(error)?;
+                }
+            }
+        }
+
+        Ok(palette)
+    }
+
+    /// Color shown around the display by the beep indicator
+    pub fn indicator(&self, beeping: bool) -> u32 {
+        if beeping { self.buzzer } else { self.silence }
+    }
+
+    /// Color of a pixel with the given plane bits
+    pub fn color(&self, planes: u8) -> u32 {
+        match planes & 0b11 {
+            0b00 => self.background,
+            0b01 => self.foreground,
+            0b10 => self.plane2,
+            _ => self.overlap
+        }
+    }
+}
+
+/// `#RRGGBB`, also taken without the `#` or as `0xRRGGBB`, to 0RGB
+pub fn parse_color(text: &str) -> Option<u32> {
+    let hex = text.strip_prefix('#').or_else(|| text.strip_prefix("0x")).unwrap_or(text);
+    // from_str_radix would take a sign too
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+/// How the display fills a frame bigger than itself
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scaling {
+    /// The biggest whole number of host pixels per CHIP-8 pixel, so every
+    /// pixel is the same size
+    #[default]
+    Integer,
+    /// As big as fits, pixels may differ by one host pixel
+    Fit
+}
+
+impl Scaling {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "integer" => Some(Scaling::Integer),
+            "fit" => Some(Scaling::Fit),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scaling::Integer => "integer",
+            Scaling::Fit => "fit"
+        }
+    }
+}
+
+/// Scales a [`Display`] up into an 0RGB buffer, either by a fixed factor or
+/// to fill a frame like a window.
+///
+/// In a frame the display keeps its 2:1 shape, centered with the palette's
+/// `silence` color around it (`buzzer` while beeping).
+///
+/// Rendering only reads the display, so palette and scale can change at any
+/// time without affecting emulation.
+pub struct Renderer {
+    pub palette: Palette,
+    scale: usize,
+    scaling: Scaling,
+    /// Size to center the display in, if it's not just the display
+    frame: Option<(usize, usize)>,
+    /// Host pixels always left around the display in a frame
+    margin: usize,
+    beeping: bool,
+    phosphor: Phosphor,
+    width: usize,
+    height: usize,
+    buffer: Vec<u32>
+}
+
+impl Renderer {
+    pub fn new(scale: usize, palette: Palette) -> Self {
+        Renderer {
+            palette,
+            scale: scale.max(1),
+            scaling: Scaling::default(),
+            frame: None,
+            margin: 0,
+            beeping: false,
+            phosphor: Phosphor::default(),
+            width: 0,
+            height: 0,
+            buffer: Vec::new()
+        }
+    }
+
+    /// Host pixels per CHIP-8 pixel. In a frame it's worked out on every
+    /// render, rounded down for [`Scaling::Fit`].
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: usize) {
+        self.scale = scale.max(1);
+    }
+
+    pub fn scaling(&self) -> Scaling {
+        self.scaling
+    }
+
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+    }
+
+    /// Render into a `width`x`height` buffer with the display scaled to fit
+    /// in the middle, or just the display at the set scale for `None`
+    pub fn set_frame(&mut self, frame: Option<(usize, usize)>) {
+        self.frame = frame;
+    }
+
+    /// Keep at least `margin` host pixels between the display and the edge
+    /// of the frame
+    pub fn set_margin(&mut self, margin: usize) {
+        self.margin = margin;
+    }
+
+    /// Show the `buzzer` color around the display instead of `silence`
+    pub fn set_beeping(&mut self, beeping: bool) {
+        self.beeping = beeping;
+    }
+
+    pub fn phosphor(&self) -> PhosphorMode {
+        self.phosphor.mode()
+    }
+
+    /// Smooth over flicker with a [`PhosphorMode`]. It works from the frames
+    /// rendered before, so only use it for a renderer that's given every
+    /// shown frame in turn.
+    pub fn set_phosphor(&mut self, mode: PhosphorMode) {
+        self.phosphor.set_mode(mode);
+    }
+
+    /// Width of the last rendered frame in host pixels
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the last rendered frame in host pixels
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn buffer(&self) -> &[u32] {
+        &self.buffer
+    }
+
+    /// Size of the display in host pixels inside a `width`x`height` frame
+    fn fit(&mut self, display: &Display, width: usize, height: usize) -> (usize, usize) {
+        let space_width = width.saturating_sub(2 * self.margin);
+        let space_height = height.saturating_sub(2 * self.margin);
+
+        match self.scaling {
+            Scaling::Integer => {
+                self.scale = (space_width / display.width()).min(space_height / display.height()).max(1);
+                (display.width() * self.scale, display.height() * self.scale)
+            },
+            Scaling::Fit => {
+                let fit_width = space_width.min(space_height * display.width() / display.height()).max(display.width());
+                self.scale = fit_width / display.width();
+                (fit_width, fit_width * display.height() / display.width())
+            }
+        }
+    }
+
+    /// Render the display into the internal buffer and return it
+    pub fn render(&mut self, display: &Display) -> &[u32] {
+        let (display_width, display_height) = match self.frame {
+            Some((width, height)) => self.fit(display, width, height),
+            None => (display.width() * self.scale, display.height() * self.scale)
+        };
+        (self.width, self.height) = self.frame.unwrap_or((display_width, display_height));
+        self.buffer.resize(self.width * self.height, 0);
+
+        // A display bigger than the frame is cut off on the right and bottom
+        let left = self.width.saturating_sub(display_width) / 2;
+        let top = self.height.saturating_sub(display_height) / 2;
+        if self.frame.is_some() {
+            self.buffer.fill(self.palette.indicator(self.beeping));
+        }
+
+        let colors = self.phosphor.apply(display, &self.palette);
+
+        // Nearest neighbour: each host pixel takes the CHIP-8 pixel under it.
+        // Rows are worked out once and copied to every host row they cover.
+        let columns = display_width.min(self.width - left);
+        let rows = display_height.min(self.height - top);
+        let mut line = vec![0; columns];
+        let mut line_y = None;
+        for host_y in 0..rows {
+            let y = host_y * display.height() / display_height;
+            if line_y != Some(y) {
+                for (host_x, color) in line.iter_mut().enumerate() {
+                    *color = colors[y * display.width() + host_x * display.width() / display_width];
+                }
+                line_y = Some(y);
+            }
+
+            let start = (host_y + top) * self.width + left;
+            self.buffer[start..start + columns].copy_from_slice(&line);
+        }
+
+        &self.buffer
+    }
+}
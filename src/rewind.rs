@@ -0,0 +1,339 @@
+//! Recent history of the machine, for stepping backwards in time.
+//!
+//! Every frame the frontend pushes a [`SaveState`] and rewinding pops them
+//! off again, newest first. Consecutive states barely differ, so only every
+//! `keyframe_interval`th one is stored whole and the ones in between are
+//! stored as the bytes that changed since that keyframe.
+//!
+//! History is dropped oldest first, a keyframe together with its deltas,
+//! whenever it holds more than `depth` frames or more than `memory_budget`
+//! bytes. The budget is a hard limit: when even the newest keyframe and its
+//! deltas don't fit, they're dropped too.
+
+use std::collections::VecDeque;
+
+use crate::savestate::SaveState;
+
+/// One minute of frames
+pub const DEFAULT_REWIND_DEPTH: usize = 60 * 60;
+pub const DEFAULT_REWIND_BUDGET: usize = 16 * 1024 * 1024;
+pub const DEFAULT_KEYFRAME_INTERVAL: usize = 60;
+
+/// A keyframe and the frames after it, stored as deltas against it
+struct Group {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>
+}
+
+impl Group {
+    fn frames(&self) -> usize {
+        1 + self.deltas.len()
+    }
+
+    fn bytes(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+pub struct RewindBuffer {
+    depth: usize,
+    memory_budget: usize,
+    keyframe_interval: usize,
+    groups: VecDeque<Group>,
+    frames: usize,
+    bytes: usize
+}
+
+impl Default for RewindBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_REWIND_DEPTH, DEFAULT_REWIND_BUDGET)
+    }
+}
+
+impl RewindBuffer {
+    /// Keep at most `depth` frames in at most `memory_budget` bytes. A depth
+    /// or budget of 0 turns rewinding off.
+    pub fn new(depth: usize, memory_budget: usize) -> Self {
+        RewindBuffer {
+            depth,
+            memory_budget,
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            groups: VecDeque::new(),
+            frames: 0,
+            bytes: 0
+        }
+    }
+
+    /// Store every `interval`th frame whole. Longer intervals use less memory
+    /// but history is dropped in bigger chunks.
+    pub fn set_keyframe_interval(&mut self, interval: usize) {
+        self.keyframe_interval = interval.max(1);
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget
+    }
+
+    /// Number of frames that can be rewound
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    /// Memory used by the stored history
+    pub fn memory_used(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.frames = 0;
+        self.bytes = 0;
+    }
+
+    /// Remember `state` as the newest frame
+    pub fn push(&mut self, state: &SaveState) {
+        if self.depth == 0 || self.memory_budget == 0 {
+            return;
+        }
+
+        let bytes = state.to_bytes();
+        let added = match self.groups.back_mut() {
+            Some(group) if group.frames() < self.keyframe_interval && group.keyframe.len() == bytes.len() => {
+                let delta = encode_delta(&group.keyframe, &bytes);
+                let size = delta.len();
+                group.deltas.push(delta);
+                size
+            },
+            _ => {
+                let size = bytes.len();
+                self.groups.push_back(Group { keyframe: bytes, deltas: Vec::new() });
+                size
+            }
+        };
+
+        self.frames += 1;
+        self.bytes += added;
+        self.trim();
+    }
+
+    /// Take the newest frame back out
+    pub fn pop(&mut self) -> Option<SaveState> {
+        let group = self.groups.back_mut()?;
+        let bytes = match group.deltas.pop() {
+            Some(delta) => {
+                self.bytes -= delta.len();
+                apply_delta(&group.keyframe, &delta)
+            },
+            None => {
+                let group = self.groups.pop_back()?;
+                self.bytes -= group.keyframe.len();
+                group.keyframe
+            }
+        };
+        self.frames -= 1;
+
+        // Only ever decoding what push encoded, so this can't fail
+        SaveState::from_bytes(&bytes).ok()
+    }
+
+    /// Drop the oldest groups until the history fits. The newest group is
+    /// kept when it's only over the depth, so there's always something to
+    /// rewind to, but not when it's over the memory budget.
+    fn trim(&mut self) {
+        while self.groups.len() > 1 && (self.frames > self.depth || self.bytes > self.memory_budget) {
+            if let Some(group) = self.groups.pop_front() {
+                self.frames -= group.frames();
+                self.bytes -= group.bytes();
+            }
+        }
+
+        if self.bytes > self.memory_budget {
+            self.clear();
+        }
+    }
+}
+
+/// Encode the runs where `target` differs from `base` (both the same length)
+/// as: unchanged count, changed count, changed bytes, repeated. Counts are
+/// LEB128 so short runs take a single byte.
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut position = 0;
+
+    while position < target.len() {
+        let unchanged = base[position..].iter().zip(&target[position..]).take_while(|(a, b)| a == b).count();
+        let start = position + unchanged;
+        if start == target.len() {
+            break;
+        }
+
+        let changed = base[start..].iter().zip(&target[start..]).take_while(|(a, b)| a != b).count();
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, changed);
+        delta.extend_from_slice(&target[start..start + changed]);
+        position = start + changed;
+    }
+
+    delta
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut bytes = base.to_vec();
+    let mut position = 0;
+    let mut cursor = 0;
+
+    while cursor < delta.len() {
+        position += read_varint(delta, &mut cursor);
+        let changed = read_varint(delta, &mut cursor);
+        bytes[position..position + changed].copy_from_slice(&delta[cursor..cursor + changed]);
+        cursor += changed;
+        position += changed;
+    }
+
+    bytes
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], cursor: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*cursor];
+        *cursor += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Chip8Machine;
+
+    /// A state that differs from its neighbours in a few registers and bytes
+    fn state(frame: usize) -> SaveState {
+        let mut state = Chip8Machine::new().save_state();
+        state.cpu.registers[0] = frame as u8;
+        state.cpu.registers[1] = (frame >> 8) as u8;
+        state.cpu.ram[0x300 + frame % 0x100] = 0xFF;
+        state.cpu.pc = 0x200 + 2 * (frame % 0x100) as u16;
+        state
+    }
+
+    #[test]
+    fn deltas_round_trip() {
+        let base: Vec<u8> = (0..1000).map(|byte| byte as u8).collect();
+
+        let mut target = base.clone();
+        assert!(encode_delta(&base, &target).is_empty());
+        assert_eq!(apply_delta(&base, &encode_delta(&base, &target)), target);
+
+        // Changes at both ends and runs too long for a one byte count
+        target[0] ^= 1;
+        for byte in &mut target[200..500] {
+            *byte = !*byte;
+        }
+        target[999] ^= 0x80;
+        assert_eq!(apply_delta(&base, &encode_delta(&base, &target)), target);
+    }
+
+    #[test]
+    fn pops_what_was_pushed() {
+        let mut buffer = RewindBuffer::new(1000, usize::MAX);
+        buffer.set_keyframe_interval(8);
+        for frame in 0..30 {
+            buffer.push(&state(frame));
+        }
+        assert_eq!(buffer.len(), 30);
+        // 4 groups of 8, 8, 8 and 6 frames, each with one keyframe
+        assert_eq!(buffer.groups.len(), 4);
+
+        for frame in (0..30).rev() {
+            assert_eq!(buffer.pop(), Some(state(frame)));
+        }
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.memory_used(), 0);
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn trim_keeps_a_keyframe_for_every_delta() {
+        let mut buffer = RewindBuffer::new(20, usize::MAX);
+        buffer.set_keyframe_interval(8);
+        for frame in 0..100 {
+            buffer.push(&state(frame));
+
+            assert!(buffer.len() <= 20);
+            assert_eq!(buffer.len(), buffer.groups.iter().map(Group::frames).sum::<usize>());
+            assert_eq!(buffer.memory_used(), buffer.groups.iter().map(Group::bytes).sum::<usize>());
+        }
+
+        // Whatever is left decodes, newest first, all the way back
+        let frames = buffer.len();
+        for frame in (100 - frames..100).rev() {
+            assert_eq!(buffer.pop(), Some(state(frame)));
+        }
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn memory_used_never_goes_over_the_budget() {
+        let keyframe = state(0).to_bytes().len();
+        for budget in [1, keyframe - 1, keyframe, keyframe + 10, 3 * keyframe, 10 * keyframe] {
+            let mut buffer = RewindBuffer::new(1000, budget);
+            buffer.set_keyframe_interval(4);
+            for frame in 0..100 {
+                buffer.push(&state(frame));
+                assert!(buffer.memory_used() <= budget, "{} bytes with a budget of {}", buffer.memory_used(), budget);
+            }
+
+            // Whatever fit still decodes
+            let frames = buffer.len();
+            for frame in (100 - frames..100).rev() {
+                assert_eq!(buffer.pop(), Some(state(frame)));
+            }
+        }
+
+        // A budget too small for a single keyframe keeps nothing
+        let mut buffer = RewindBuffer::new(1000, keyframe - 1);
+        buffer.push(&state(0));
+        assert!(buffer.is_empty());
+
+        // One that fits exactly keeps the newest keyframe
+        let mut buffer = RewindBuffer::new(1000, keyframe);
+        buffer.set_keyframe_interval(1);
+        for frame in 0..10 {
+            buffer.push(&state(frame));
+        }
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.pop(), Some(state(9)));
+    }
+
+    #[test]
+    fn zero_turns_rewinding_off() {
+        for (depth, budget) in [(0, usize::MAX), (1000, 0)] {
+            let mut buffer = RewindBuffer::new(depth, budget);
+            buffer.push(&state(0));
+            assert!(buffer.is_empty());
+            assert_eq!(buffer.memory_used(), 0);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Random numbers for CXNN.
+//!
+//! The generator is part of the machine state and fully determined by its
+//! algorithm and seed, so a run can be reproduced exactly.
+
+pub const DEFAULT_SEED: u64 = 0x2BAD_C0DE_CAFE_F00D;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RngKind {
+    /// xorshift64*
+    #[default]
+    XorShift,
+    /// 64-bit linear congruential generator (Knuth's MMIX constants)
+    Lcg
+}
+
+impl RngKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "xorshift" => Some(RngKind::XorShift),
+            "lcg" => Some(RngKind::Lcg),
+            _ => None
+        }
+    }
+
+    /// The name [`RngKind::from_name`] takes
+    pub fn name(&self) -> &'static str {
+        match self {
+            RngKind::XorShift => "xorshift",
+            RngKind::Lcg => "lcg"
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rng {
+    pub kind: RngKind,
+    pub state: u64
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(RngKind::default(), DEFAULT_SEED)
+    }
+}
+
+impl Rng {
+    pub fn new(kind: RngKind, seed: u64) -> Self {
+        // Spread the seed out with one round of splitmix64 so that small seeds
+        // (and 0, which xorshift can't leave) still give a good sequence
+        let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        state ^= state >> 31;
+
+        Rng {
+            kind,
+            state: if state == 0 { DEFAULT_SEED } else { state }
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        match self.kind {
+            RngKind::XorShift => {
+                self.state ^= self.state >> 12;
+                self.state ^= self.state << 25;
+                self.state ^= self.state >> 27;
+                self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+            },
+            RngKind::Lcg => {
+                self.state = self.state
+                                .wrapping_mul(6364136223846793005)
+                                .wrapping_add(1442695040888963407);
+                self.state
+            }
+        }
+    }
+
+    /// A random byte, taken from the high bits which are the best ones for both generators
+    pub fn next_byte(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
@@ -0,0 +1,405 @@
+//! Snapshots of the complete machine and the file format they are saved in.
+//!
+//! A [`SaveState`] holds everything that affects execution: the CPU (memory,
+//! registers, stack, timers, quirks, RNG), the display, the keypad and the
+//! frame clock. Restoring one and running the same input again gives exactly
+//! the same result as the original run.
+//!
+//! On disk a state is:
+//!
+//! ```text
+//! "CH8S"          magic
+//! u16             format version
+//! u32             payload length
+//! [u8]            payload
+//! u32             CRC-32 of the payload
+//! ```
+//!
+//! All numbers are little endian.
+
+use std::{fs, io, path::Path};
+
+use crate::{
+    cpu::Chip8CPU,
+    display::Display,
+    fault::{Chip8Fault, FaultPolicy},
+    platform::Platform,
+    quirks::Quirks,
+    rng::{Rng, RngKind},
+    timer::FrameClock
+};
+
+pub const SAVE_STATE_MAGIC: [u8; 4] = *b"CH8S";
+pub const SAVE_STATE_VERSION: u16 = 1;
+
+/// Everything needed to put a machine back exactly where it was
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SaveState {
+    pub cpu: Chip8CPU,
+    pub display: Display,
+    pub keys: [bool; 16],
+    pub clock: FrameClock
+}
+
+impl SaveState {
+    /// Encode the state in the versioned, checksummed file format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = Writer::default();
+        payload.cpu(&self.cpu);
+        payload.display(&self.display);
+        for key in self.keys {
+            payload.bool(key);
+        }
+        payload.u32(self.clock.clock_hz());
+        payload.u32(self.clock.phase());
+        payload.u64(self.clock.frame());
+
+        let mut bytes = Writer::default();
+        bytes.bytes(&SAVE_STATE_MAGIC);
+        bytes.u16(SAVE_STATE_VERSION);
+        bytes.u32(payload.0.len() as u32);
+        bytes.bytes(&payload.0);
+        bytes.u32(crc32(&payload.0));
+        bytes.0
+    }
+
+    /// Decode a state written by [`SaveState::to_bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut header = Reader::new(bytes);
+        if header.bytes(4)? != SAVE_STATE_MAGIC {
+            return Err("Not a save state");
+        }
+        if header.u16()? != SAVE_STATE_VERSION {
+            return Err("Unsupported save state version");
+        }
+
+        let length = header.u32()? as usize;
+        let payload = header.bytes(length)?;
+        if header.u32()? != crc32(payload) {
+            return Err("Save state checksum mismatch");
+        }
+        if !header.is_empty() {
+            return Err("Trailing data after save state");
+        }
+
+        let mut reader = Reader::new(payload);
+        let cpu = reader.cpu()?;
+        let display = reader.display()?;
+        let mut keys = [false; 16];
+        for key in keys.iter_mut() {
+            *key = reader.bool()?;
+        }
+        let clock_hz = reader.u32()?;
+        let phase = reader.u32()?;
+        let frame = reader.u64()?;
+        if clock_hz == 0 || phase >= clock_hz {
+            return Err("Invalid clock in save state");
+        }
+
+        if !reader.is_empty() {
+            return Err("Trailing data in save state");
+        }
+
+        Ok(
+            SaveState {
+                cpu,
+                display,
+                keys,
+                clock: FrameClock::from_parts(clock_hz, phase, frame)
+            }
+        )
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        Self::from_bytes(&bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// CRC-32 (the zlib/PNG one)
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn cpu(&mut self, cpu: &Chip8CPU) {
+        self.u8(match cpu.platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2
+        });
+        self.quirks(&cpu.quirks);
+        self.u8(match cpu.fault_policy {
+            FaultPolicy::Halt => 0,
+            FaultPolicy::Wrap => 1,
+            FaultPolicy::Ignore => 2
+        });
+        self.fault(cpu.halted);
+
+        self.u32(cpu.ram.len() as u32);
+        self.bytes(&cpu.ram);
+        self.bytes(&cpu.registers);
+        for address in cpu.stack {
+            self.u16(address);
+        }
+        self.u16(cpu.pc);
+        self.u16(cpu.i);
+        self.u8(cpu.sp);
+        self.u8(cpu.timers.dt);
+        self.u8(cpu.timers.st);
+        self.bytes(&cpu.rpl);
+
+        match cpu.audio_pattern {
+            Some(pattern) => {
+                self.bool(true);
+                self.bytes(&pattern);
+            },
+            None => self.bool(false)
+        }
+        self.u8(cpu.pitch);
+        self.bool(cpu.exited);
+        self.bool(cpu.waiting_for_vblank);
+
+        self.u8(match cpu.rng.kind {
+            RngKind::XorShift => 0,
+            RngKind::Lcg => 1
+        });
+        self.u64(cpu.rng.state);
+    }
+
+    fn quirks(&mut self, quirks: &Quirks) {
+        let flags = [
+            quirks.shift_uses_vy,
+            quirks.load_store_increments_i,
+            quirks.jump_uses_vx,
+            quirks.logic_resets_vf,
+            quirks.clip_sprites,
+            quirks.wait_for_vblank
+        ];
+
+        let mut bits = 0;
+        for (bit, flag) in flags.into_iter().enumerate() {
+            bits |= (flag as u8) << bit;
+        }
+        self.u8(bits);
+    }
+
+    /// Tag, pc, opcode and the address for the faults that have one
+    fn fault(&mut self, fault: Option<Chip8Fault>) {
+        let (tag, address) = match fault {
+            None => (0, 0),
+            Some(Chip8Fault::StackOverflow { .. }) => (1, 0),
+            Some(Chip8Fault::StackUnderflow { .. }) => (2, 0),
+            Some(Chip8Fault::PcOutOfRange { target, .. }) => (3, target),
+            Some(Chip8Fault::MemoryOutOfBounds { addr, .. }) => (4, addr),
+            Some(Chip8Fault::InvalidOpcode { .. }) => (5, 0)
+        };
+
+        self.u8(tag);
+        if let Some(fault) = fault {
+            self.u16(fault.pc());
+            self.u16(fault.opcode());
+            self.u64(address as u64);
+        }
+    }
+
+    fn display(&mut self, display: &Display) {
+        self.u16(display.width() as u16);
+        self.u16(display.height() as u16);
+        self.bool(display.is_hires());
+        self.u8(display.selected_planes());
+        self.bytes(display.pixels());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader {
+            bytes,
+            position: 0
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], &'static str> {
+        let end = self.position.checked_add(count).filter(|end| *end <= self.bytes.len()).ok_or("Save state is truncated")?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, &'static str> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err("Invalid flag in save state")
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, &'static str> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn cpu(&mut self) -> Result<Chip8CPU, &'static str> {
+        let mut cpu = Chip8CPU::new();
+
+        cpu.platform = match self.u8()? {
+            0 => Platform::Chip8,
+            1 => Platform::SuperChip,
+            2 => Platform::XoChip,
+            _ => return Err("Unknown platform in save state")
+        };
+        cpu.quirks = self.quirks()?;
+        cpu.fault_policy = match self.u8()? {
+            0 => FaultPolicy::Halt,
+            1 => FaultPolicy::Wrap,
+            2 => FaultPolicy::Ignore,
+            _ => return Err("Unknown fault policy in save state")
+        };
+        cpu.halted = self.fault()?;
+
+        let ram_size = self.u32()? as usize;
+        if ram_size != cpu.platform.memory_size() {
+            return Err("Memory size doesn't match the platform in save state");
+        }
+        cpu.ram = self.bytes(ram_size)?.to_vec();
+        cpu.registers = self.array()?;
+        for address in cpu.stack.iter_mut() {
+            *address = self.u16()?;
+        }
+        cpu.pc = self.u16()?;
+        cpu.i = self.u16()?;
+        cpu.sp = self.u8()?;
+        if cpu.sp as usize > cpu.stack.len() {
+            return Err("Invalid stack pointer in save state");
+        }
+        cpu.timers.dt = self.u8()?;
+        cpu.timers.st = self.u8()?;
+        cpu.rpl = self.array()?;
+
+        cpu.audio_pattern = if self.bool()? { Some(self.array()?) } else { None };
+        cpu.pitch = self.u8()?;
+        cpu.exited = self.bool()?;
+        cpu.waiting_for_vblank = self.bool()?;
+
+        let kind = match self.u8()? {
+            0 => RngKind::XorShift,
+            1 => RngKind::Lcg,
+            _ => return Err("Unknown rng in save state")
+        };
+        cpu.rng = Rng { kind, state: self.u64()? };
+
+        Ok(cpu)
+    }
+
+    fn quirks(&mut self) -> Result<Quirks, &'static str> {
+        let bits = self.u8()?;
+        let flag = |bit: u8| bits & (1 << bit) != 0;
+
+        Ok(
+            Quirks {
+                shift_uses_vy: flag(0),
+                load_store_increments_i: flag(1),
+                jump_uses_vx: flag(2),
+                logic_resets_vf: flag(3),
+                clip_sprites: flag(4),
+                wait_for_vblank: flag(5)
+            }
+        )
+    }
+
+    fn fault(&mut self) -> Result<Option<Chip8Fault>, &'static str> {
+        let tag = self.u8()?;
+        if tag == 0 {
+            return Ok(None);
+        }
+
+        let pc = self.u16()?;
+        let opcode = self.u16()?;
+        let address = self.u64()? as usize;
+
+        match tag {
+            1 => Ok(Some(Chip8Fault::StackOverflow { pc, opcode })),
+            2 => Ok(Some(Chip8Fault::StackUnderflow { pc, opcode })),
+            3 => Ok(Some(Chip8Fault::PcOutOfRange { pc, opcode, target: address })),
+            4 => Ok(Some(Chip8Fault::MemoryOutOfBounds { pc, opcode, addr: address })),
+            5 => Ok(Some(Chip8Fault::InvalidOpcode { pc, opcode })),
+            _ => Err("Unknown fault in save state")
+        }
+    }
+
+    fn display(&mut self) -> Result<Display, &'static str> {
+        let width = self.u16()? as usize;
+        let height = self.u16()? as usize;
+        let hires = self.bool()?;
+        let selected = self.u8()?;
+        let pixels = self.bytes(width * height)?.to_vec();
+
+        Display::from_parts(width, height, hires, selected, pixels)
+    }
+}
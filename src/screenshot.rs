@@ -0,0 +1,194 @@
+//! Writes the machine's display to an image file.
+//!
+//! PNG, PPM and PBM are all encoded here without any image library. PNG and
+//! PPM are drawn through a [`Renderer`], so they use its palette and any
+//! scale. PBM only has black and white, so every lit pixel in any plane is
+//! written as a 1. It's plain text with one line per row where rows fit,
+//! which makes it easy to diff, so the conformance tests keep their golden
+//! images in it.
+
+use std::{fs, io, path::Path};
+
+use crate::{
+    display::Display,
+    render::{Palette, Renderer},
+    savestate::crc32
+};
+
+/// Longest line allowed in a plain PBM
+const PBM_LINE_LENGTH: usize = 70;
+
+/// Most data a stored deflate block can hold
+const STORED_BLOCK_SIZE: usize = 0xFFFF;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Ppm,
+    Pbm
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "pbm" => Some(ImageFormat::Pbm),
+            _ => None
+        }
+    }
+
+    /// Format to use for a file, going by its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_name(path.extension()?.to_str()?)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Pbm => "pbm"
+        }
+    }
+}
+
+/// How to turn a display into an image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Screenshot {
+    pub format: ImageFormat,
+    /// Host pixels per CHIP-8 pixel, 1 for the display's own resolution
+    pub scale: usize,
+    pub palette: Palette
+}
+
+impl Default for Screenshot {
+    fn default() -> Self {
+        Screenshot {
+            format: ImageFormat::default(),
+            scale: 1,
+            palette: Palette::default()
+        }
+    }
+}
+
+impl Screenshot {
+    pub fn new(format: ImageFormat, scale: usize, palette: Palette) -> Self {
+        Screenshot {
+            format,
+            scale: scale.max(1),
+            palette
+        }
+    }
+
+    /// File name for frame `frame` of the ROM `rom_name`, like
+    /// `ibm-logo-000120.png`
+    pub fn file_name(&self, rom_name: &str, frame: u64) -> String {
+        format!("{}-{:06}.{}", rom_name, frame, self.format.extension())
+    }
+
+    /// Encode the display as an image file
+    pub fn encode(&self, display: &Display) -> Vec<u8> {
+        match self.format {
+            ImageFormat::Pbm => encode_pbm(display, self.scale),
+            ImageFormat::Ppm | ImageFormat::Png => {
+                let mut renderer = Renderer::new(self.scale, self.palette);
+                renderer.render(display);
+
+                let rgb: Vec<u8> = renderer.buffer()
+                                        .iter()
+                                        .flat_map(|color| {
+                                            let [_, red, green, blue] = color.to_be_bytes();
+                                            [red, green, blue]
+                                        })
+                                        .collect();
+                if self.format == ImageFormat::Ppm {
+                    encode_ppm(renderer.width(), renderer.height(), &rgb)
+                } else {
+                    encode_png(renderer.width(), renderer.height(), &rgb)
+                }
+            }
+        }
+    }
+
+    pub fn save(&self, display: &Display, path: &Path) -> io::Result<()> {
+        fs::write(path, self.encode(display))
+    }
+}
+
+fn encode_pbm(display: &Display, scale: usize) -> Vec<u8> {
+    let width = display.width() * scale;
+    let height = display.height() * scale;
+
+    let mut text = format!("P1\n{} {}\n", width, height);
+    for y in 0..height {
+        let row: Vec<u8> = (0..width).map(|x| if display.get_pixel(x / scale, y / scale) { b'1' } else { b'0' }).collect();
+        for line in row.chunks(PBM_LINE_LENGTH) {
+            text.push_str(std::str::from_utf8(line).unwrap_or_default());
+            text.push('\n');
+        }
+    }
+    text.into_bytes()
+}
+
+fn encode_ppm(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut bytes = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    bytes.extend_from_slice(rgb);
+    bytes
+}
+
+/// 8-bit RGB PNG. The pixel data isn't compressed, it goes into stored
+/// deflate blocks, which every decoder reads and keeps this short.
+fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    // Every row starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // zlib header for deflate with a 32K window and no preset dictionary
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(STORED_BLOCK_SIZE).collect::<Vec<_>>();
+    for (index, block) in blocks.iter().enumerate() {
+        let last = index + 1 == blocks.len();
+        let length = block.len() as u16;
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&length.to_le_bytes());
+        zlib.extend_from_slice(&(!length).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, deflate, standard filters, not interlaced
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Length, type, data, then the CRC of type and data
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for byte in bytes {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
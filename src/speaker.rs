@@ -0,0 +1,114 @@
+//! cpal audio backend that plays the beeper on the default output device.
+//!
+//! The audio callback runs on a real-time thread, so it never takes a lock:
+//! whether to play is an atomic flag and tone changes, XO-CHIP patterns and
+//! pitch included, come in over a channel the callback drains without
+//! blocking.
+
+use std::sync::{
+    Arc, atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, Sender}
+};
+
+use cpal::{
+    FromSample, SampleFormat, SizedSample,
+    traits::{DeviceTrait, HostTrait, StreamTrait}
+};
+
+use crate::audio::{AudioBackend, Oscillator, Tone};
+
+#[derive(Debug)]
+pub enum SpeakerError {
+    NoOutputDevice,
+    UnsupportedSampleFormat,
+    StreamError(String)
+}
+
+pub struct Speaker {
+    // Dropping the stream stops playback, so it has to live as long as we do
+    _stream: cpal::Stream,
+    tones: Sender<Tone>,
+    playing: Arc<AtomicBool>
+}
+
+impl Speaker {
+    pub fn new() -> Result<Self, SpeakerError> {
+        let host = cpal::default_host();
+        let device = host.default_output_device().ok_or(SpeakerError::NoOutputDevice)?;
+        let supported = device.default_output_config()
+                                .map_err(|e| SpeakerError::StreamError(e.to_string()))?;
+
+        let sample_format = supported.sample_format();
+        let config: cpal::StreamConfig = supported.into();
+
+        let (tones, receiver) = mpsc::channel();
+        let playing = Arc::new(AtomicBool::new(false));
+        let oscillator = Oscillator::new(Tone::default(), config.sample_rate.0);
+
+        let stream = match sample_format {
+            SampleFormat::I8 => build_stream::<i8>(&device, &config, oscillator, receiver, playing.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, oscillator, receiver, playing.clone()),
+            SampleFormat::I32 => build_stream::<i32>(&device, &config, oscillator, receiver, playing.clone()),
+            SampleFormat::U8 => build_stream::<u8>(&device, &config, oscillator, receiver, playing.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, oscillator, receiver, playing.clone()),
+            SampleFormat::U32 => build_stream::<u32>(&device, &config, oscillator, receiver, playing.clone()),
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, oscillator, receiver, playing.clone()),
+            SampleFormat::F64 => build_stream::<f64>(&device, &config, oscillator, receiver, playing.clone()),
+            _ => return Err(SpeakerError::UnsupportedSampleFormat)
+        }?;
+
+        stream.play().map_err(|e| SpeakerError::StreamError(e.to_string()))?;
+
+        Ok(
+            Speaker {
+                _stream: stream,
+                tones,
+                playing
+            }
+        )
+    }
+}
+
+/// Open an output stream that takes samples of type `T`
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut oscillator: Oscillator,
+    tones: Receiver<Tone>,
+    playing: Arc<AtomicBool>
+) -> Result<cpal::Stream, SpeakerError>
+where
+    T: SizedSample + FromSample<f32>
+{
+    let channels = config.channels as usize;
+
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            // Only the newest tone matters
+            while let Ok(tone) = tones.try_recv() {
+                oscillator.tone = tone;
+            }
+            let playing = playing.load(Ordering::Relaxed);
+
+            // Write the same sample to every channel of a frame
+            for frame in data.chunks_mut(channels) {
+                let sample = if playing { oscillator.next_sample() } else { 0.0 };
+                frame.fill(T::from_sample(sample));
+            }
+        },
+        |error| eprintln!("Audio stream error: {}", error),
+        None
+    ).map_err(|e| SpeakerError::StreamError(e.to_string()))
+}
+
+impl AudioBackend for Speaker {
+    fn set_tone(&mut self, tone: Tone) {
+        // Only fails once the stream and its callback are gone
+        let _ = self.tones.send(tone);
+    }
+
+    fn set_playing(&mut self, _frame: u64, playing: bool) {
+        self.playing.store(playing, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,149 @@
+//! Delay and sound timers, and the 60 Hz frame clock that drives them.
+
+pub const TIMER_HZ: u32 = 60;
+
+/// The delay (DT) and sound (ST) timers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timers {
+    pub dt: u8,
+    pub st: u8
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Timers { dt: 0, st: 0 }
+    }
+
+    /// Count both timers down once. Called on every 60 Hz frame boundary.
+    pub fn tick(&mut self) {
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+    }
+}
+
+/// Keeps track of emulated time in instructions and reports 60 Hz frames.
+///
+/// Each instruction advances time by `1 / clock_hz` seconds. The clock keeps
+/// the remainder between frames so timers tick exactly 60 times per emulated
+/// second, even when the clock speed is not a multiple of 60.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameClock {
+    clock_hz: u32,
+    phase: u32,
+    frame: u64
+}
+
+impl FrameClock {
+    pub fn new(clock_hz: u32) -> Self {
+        FrameClock {
+            clock_hz: clock_hz.max(1),
+            phase: 0,
+            frame: 0
+        }
+    }
+
+    /// Rebuild a clock from saved values. `phase` must be below `clock_hz`.
+    pub fn from_parts(clock_hz: u32, phase: u32, frame: u64) -> Self {
+        let clock_hz = clock_hz.max(1);
+        FrameClock {
+            clock_hz,
+            phase: phase.min(clock_hz - 1),
+            frame
+        }
+    }
+
+    pub fn clock_hz(&self) -> u32 {
+        self.clock_hz
+    }
+
+    pub fn set_clock_hz(&mut self, clock_hz: u32) {
+        self.clock_hz = clock_hz.max(1);
+        self.phase = self.phase.min(self.clock_hz - 1);
+    }
+
+    /// Progress towards the next frame, which ends when it reaches `clock_hz`
+    pub fn phase(&self) -> u32 {
+        self.phase
+    }
+
+    /// Number of frames completed so far
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Account for one executed instruction and return how many frame
+    /// boundaries it crossed. This is 0 or 1 unless the clock is below 60 Hz.
+    pub fn step(&mut self) -> u32 {
+        self.phase += TIMER_HZ;
+
+        let mut frames = 0;
+        while self.phase >= self.clock_hz {
+            self.phase -= self.clock_hz;
+            self.frame += 1;
+            frames += 1;
+        }
+
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run one emulated second of instructions, ticking `timers` on every
+    /// frame, and return the instruction count each frame ended on
+    fn run_second(clock: &mut FrameClock, timers: &mut Timers) -> Vec<u32> {
+        let mut frame_ends = Vec::new();
+        for instruction in 1..=clock.clock_hz() {
+            for _ in 0..clock.step() {
+                timers.tick();
+                frame_ends.push(instruction);
+            }
+        }
+        frame_ends
+    }
+
+    #[test]
+    fn timers_tick_60_times_a_second_at_any_clock() {
+        for clock_hz in [500, 1000, 256, 61, 60, 7] {
+            let mut clock = FrameClock::new(clock_hz);
+            let mut timers = Timers { dt: 255, st: 200 };
+
+            for second in 1..=3 {
+                let frame_ends = run_second(&mut clock, &mut timers);
+                assert_eq!(frame_ends.len(), 60, "{} Hz", clock_hz);
+                assert_eq!(clock.frame(), 60 * second, "{} Hz", clock_hz);
+                // Back in step at every whole second
+                assert_eq!(clock.phase(), 0, "{} Hz", clock_hz);
+            }
+            assert_eq!(timers, Timers { dt: 255 - 180, st: 200 - 180 });
+        }
+    }
+
+    #[test]
+    fn frames_are_spread_evenly() {
+        // 500 / 60 = 8.33 instructions a frame, so frames end on the
+        // instruction where k * 500 / 60 is reached
+        let mut clock = FrameClock::new(500);
+        let frame_ends = run_second(&mut clock, &mut Timers::new());
+        let expected: Vec<u32> = (1..=60).map(|frame: u32| (frame * 500).div_ceil(60)).collect();
+        assert_eq!(frame_ends, expected);
+
+        let mut clock = FrameClock::new(1000);
+        let frame_ends = run_second(&mut clock, &mut Timers::new());
+        assert_eq!(frame_ends[..3], [17, 34, 50]);
+        assert_eq!(frame_ends[59], 1000);
+    }
+
+    #[test]
+    fn clock_changes_keep_the_phase_valid() {
+        let mut clock = FrameClock::new(1000);
+        for _ in 0..999 {
+            clock.step();
+        }
+        clock.set_clock_hz(100);
+        assert!(clock.phase() < 100);
+        assert_eq!(FrameClock::from_parts(0, 5, 0).clock_hz(), 1);
+    }
+}
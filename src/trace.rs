@@ -0,0 +1,129 @@
+//! Optional execution trace.
+//!
+//! Tracing is off unless a [`Tracer`] is attached to the machine. Each line
+//! starts with the frame number and category and ends with the full register
+//! state, so traces from two runs (or two emulators) can be diffed line by
+//! line.
+
+use std::io::{self, Write};
+
+use crate::{
+    cpu::Chip8CPU,
+    instruction::Instruction
+};
+
+/// Which kinds of events get traced
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceCategories {
+    pub instructions: bool,
+    pub draws: bool,
+    pub input: bool,
+    pub timers: bool
+}
+
+impl TraceCategories {
+    pub fn all() -> Self {
+        TraceCategories {
+            instructions: true,
+            draws: true,
+            input: true,
+            timers: true
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.instructions || self.draws || self.input || self.timers
+    }
+
+    /// Parse a comma separated list like `instructions,draws` (or `all`)
+    pub fn parse(list: &str) -> Result<Self, String> {
+        let mut categories = TraceCategories::default();
+
+        for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "all" => categories = TraceCategories::all(),
+                "instructions" | "ins" => categories.instructions = true,
+                "draws" | "draw" => categories.draws = true,
+                "input" | "keys" => categories.input = true,
+                "timers" => categories.timers = true,
+                _ => return Err(format!("Unknown trace category '{}'", name))
+            }
+        }
+
+        Ok(categories)
+    }
+}
+
+pub struct Tracer {
+    pub categories: TraceCategories,
+    sink: Box<dyn Write>
+}
+
+impl Tracer {
+    pub fn new(categories: TraceCategories, sink: Box<dyn Write>) -> Self {
+        Tracer {
+            categories,
+            sink
+        }
+    }
+
+    pub fn stdout(categories: TraceCategories) -> Self {
+        Self::new(categories, Box::new(io::stdout()))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    // Tracing must never take the emulator down, so write errors are dropped
+    fn line(&mut self, frame: u64, category: &str, message: &str, cpu: &Chip8CPU) {
+        let _ = writeln!(self.sink, "{:>8} {:<5} {:<28} {}", frame, category, message, Registers(cpu));
+    }
+
+    /// Trace an instruction before it executes
+    pub fn instruction(&mut self, frame: u64, cpu: &Chip8CPU, opcode: u16, instruction: &Instruction) {
+        if self.categories.instructions {
+            let message = format!("{:04X} {:04X} {}", cpu.pc, opcode, instruction);
+            self.line(frame, "INS", &message, cpu);
+        }
+    }
+
+    /// Trace a sprite draw after it executed, VF holds the collision flag
+    pub fn draw(&mut self, frame: u64, cpu: &Chip8CPU, x: u8, y: u8, rows: u8) {
+        if self.categories.draws {
+            let message = format!("({}, {}) rows {} from {:04X}", x, y, rows, cpu.i);
+            self.line(frame, "DRAW", &message, cpu);
+        }
+    }
+
+    pub fn input(&mut self, frame: u64, cpu: &Chip8CPU, keys: &[bool; 16]) {
+        if self.categories.input {
+            let pressed: String = keys.iter()
+                                    .enumerate()
+                                    .map(|(key, pressed)| if *pressed { format!("{:X}", key) } else { String::from(".") })
+                                    .collect();
+            self.line(frame, "KEYS", &pressed, cpu);
+        }
+    }
+
+    pub fn timers(&mut self, frame: u64, cpu: &Chip8CPU) {
+        if self.categories.timers && (cpu.timers.dt > 0 || cpu.timers.st > 0) {
+            let message = format!("DT {:02X} ST {:02X}", cpu.timers.dt, cpu.timers.st);
+            self.line(frame, "TIMER", &message, cpu);
+        }
+    }
+}
+
+/// Formats the full register state of a CPU on one line
+struct Registers<'a>(&'a Chip8CPU);
+
+impl std::fmt::Display for Registers<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cpu = self.0;
+        for (index, value) in cpu.registers.iter().enumerate() {
+            write!(f, "V{:X}:{:02X} ", index, value)?;
+        }
+
+        write!(f, "I:{:04X} SP:{:X} DT:{:02X} ST:{:02X}", cpu.i, cpu.sp, cpu.timers.dt, cpu.timers.st)
+    }
+}
@@ -0,0 +1,307 @@
+//! Records the display, one image per emulated 60 Hz frame.
+//!
+//! Frames are counted in emulated time, never wall-clock time, so recording
+//! the same run twice gives the same file. Three formats are written without
+//! any encoder library:
+//!
+//! - GIF, animated, with a 4 color table taken from the palette. Repeated
+//!   frames are merged into one with a longer delay.
+//! - Y4M, uncompressed 4:4:4 video at 60 fps that ffmpeg reads directly.
+//! - PPM, a stream of P6 images, for `ffmpeg -f image2pipe -framerate 60`.
+//!
+//! The size of the video is fixed by the first frame. Frames at another
+//! resolution, like after switching to hires, are stretched to fit.
+
+use std::{collections::HashMap, io::{self, Write}, path::Path};
+
+use crate::{display::Display, render::Palette};
+
+/// GIF delays are in hundredths of a second
+const GIF_TICKS_PER_SECOND: u64 = 100;
+const FRAMES_PER_SECOND: u64 = 60;
+
+/// Most codes a GIF LZW table can hold
+const LZW_MAX_CODES: u16 = 4096;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VideoFormat {
+    #[default]
+    Gif,
+    Y4m,
+    Ppm
+}
+
+impl VideoFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "gif" => Some(VideoFormat::Gif),
+            "y4m" => Some(VideoFormat::Y4m),
+            "ppm" => Some(VideoFormat::Ppm),
+            _ => None
+        }
+    }
+
+    /// Format to use for a file, going by its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_name(path.extension()?.to_str()?)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Y4m => "y4m",
+            VideoFormat::Ppm => "ppm"
+        }
+    }
+}
+
+/// A GIF frame waiting to find out how long it stays on screen
+struct PendingFrame {
+    pixels: Vec<u8>,
+    start: u64
+}
+
+pub struct VideoRecorder {
+    writer: Box<dyn Write>,
+    format: VideoFormat,
+    scale: usize,
+    palette: Palette,
+    size: Option<(usize, usize)>,
+    frames: u64,
+    pending: Option<PendingFrame>
+}
+
+impl VideoRecorder {
+    /// Record to `writer` at `scale` host pixels per CHIP-8 pixel
+    pub fn new(writer: Box<dyn Write>, format: VideoFormat, scale: usize, palette: Palette) -> Self {
+        VideoRecorder {
+            writer,
+            format,
+            scale: scale.max(1),
+            palette,
+            size: None,
+            frames: 0,
+            pending: None
+        }
+    }
+
+    pub fn format(&self) -> VideoFormat {
+        self.format
+    }
+
+    /// Number of frames recorded so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Add the display as the next frame
+    pub fn record_frame(&mut self, display: &Display) -> io::Result<()> {
+        let (width, height) = match self.size {
+            Some(size) => size,
+            None => {
+                let size = display.width().checked_mul(self.scale).zip(display.height().checked_mul(self.scale))
+                                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Scale {} is too big to record", self.scale)))?;
+                self.write_header(size.0, size.1)?;
+                self.size = Some(size);
+                size
+            }
+        };
+        let pixels = plane_image(display, width, height);
+
+        match self.format {
+            VideoFormat::Gif => {
+                let changed = self.pending.as_ref().is_none_or(|pending| pending.pixels != pixels);
+                if changed {
+                    if let Some(pending) = self.pending.take() {
+                        self.write_gif_frame(&pending, self.frames)?;
+                    }
+                    self.pending = Some(PendingFrame { pixels, start: self.frames });
+                }
+            },
+            VideoFormat::Y4m => {
+                let mut planes = [Vec::with_capacity(pixels.len()), Vec::with_capacity(pixels.len()), Vec::with_capacity(pixels.len())];
+                for pixel in &pixels {
+                    let (y, u, v) = to_yuv(self.palette.color(*pixel));
+                    planes[0].push(y);
+                    planes[1].push(u);
+                    planes[2].push(v);
+                }
+                self.writer.write_all(b"FRAME\n")?;
+                for plane in planes {
+                    self.writer.write_all(&plane)?;
+                }
+            },
+            VideoFormat::Ppm => {
+                writeln!(self.writer, "P6\n{} {}\n255", width, height)?;
+                let rgb: Vec<u8> = pixels.iter()
+                                        .flat_map(|pixel| {
+                                            let [_, red, green, blue] = self.palette.color(*pixel).to_be_bytes();
+                                            [red, green, blue]
+                                        })
+                                        .collect();
+                self.writer.write_all(&rgb)?;
+            }
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Write out whatever is still buffered and end the file
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.format == VideoFormat::Gif && self.size.is_some() {
+            if let Some(pending) = self.pending.take() {
+                self.write_gif_frame(&pending, self.frames)?;
+            }
+            self.writer.write_all(&[0x3B])?;
+        }
+        self.writer.flush()
+    }
+
+    fn write_header(&mut self, width: usize, height: usize) -> io::Result<()> {
+        match self.format {
+            VideoFormat::Gif => {
+                // GIF sizes are 16 bit, checked once here for every frame
+                let (Ok(gif_width), Ok(gif_height)) = (u16::try_from(width), u16::try_from(height)) else {
+                    let message = format!("{}x{} is too big for a GIF, which is at most {}x{}", width, height, u16::MAX, u16::MAX);
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+                };
+
+                let mut header = b"GIF89a".to_vec();
+                header.extend_from_slice(&gif_width.to_le_bytes());
+                header.extend_from_slice(&gif_height.to_le_bytes());
+                // Global color table of 4 colors, background color 0
+                header.extend_from_slice(&[0xF1, 0, 0]);
+                for planes in 0..4 {
+                    let [_, red, green, blue] = self.palette.color(planes).to_be_bytes();
+                    header.extend_from_slice(&[red, green, blue]);
+                }
+                // Loop forever
+                header.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+                header.extend_from_slice(b"NETSCAPE2.0");
+                header.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+                self.writer.write_all(&header)
+            },
+            VideoFormat::Y4m => writeln!(self.writer, "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444", width, height),
+            VideoFormat::Ppm => Ok(())
+        }
+    }
+
+    /// Write a frame that stayed on screen from its start until frame `end`
+    fn write_gif_frame(&mut self, frame: &PendingFrame, end: u64) -> io::Result<()> {
+        // write_header made sure these fit in a u16
+        let Some((width, height)) = self.size else {
+            return Ok(());
+        };
+
+        // Delays are rounded so their running total stays exactly on 60 Hz
+        let ticks = |frame: u64| frame * GIF_TICKS_PER_SECOND / FRAMES_PER_SECOND;
+        let delay = (ticks(end) - ticks(frame.start)).min(u16::MAX as u64) as u16;
+
+        let mut bytes = vec![0x21, 0xF9, 0x04, 0x00];
+        bytes.extend_from_slice(&delay.to_le_bytes());
+        bytes.extend_from_slice(&[0x00, 0x00]);
+
+        bytes.push(0x2C);
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(&(width as u16).to_le_bytes());
+        bytes.extend_from_slice(&(height as u16).to_le_bytes());
+        bytes.push(0x00);
+
+        // Smallest code size allowed, enough for the 4 colors
+        bytes.push(2);
+        for block in lzw_encode(&frame.pixels, 2).chunks(255) {
+            bytes.push(block.len() as u8);
+            bytes.extend_from_slice(block);
+        }
+        bytes.push(0x00);
+
+        self.writer.write_all(&bytes)
+    }
+}
+
+/// Plane bits of every pixel at `width`x`height`, by nearest neighbour
+fn plane_image(display: &Display, width: usize, height: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(display.get_pixel_planes(x * display.width() / width, y * display.height() / height));
+        }
+    }
+    pixels
+}
+
+/// 0RGB to studio range BT.601 YUV, what Y4M players assume
+fn to_yuv(color: u32) -> (u8, u8, u8) {
+    let red = ((color >> 16) & 0xFF) as i32;
+    let green = ((color >> 8) & 0xFF) as i32;
+    let blue = (color & 0xFF) as i32;
+
+    let y = ((66 * red + 129 * green + 25 * blue + 128) >> 8) + 16;
+    let u = ((-38 * red - 74 * green + 112 * blue + 128) >> 8) + 128;
+    let v = ((112 * red - 94 * green - 18 * blue + 128) >> 8) + 128;
+    (y as u8, u as u8, v as u8)
+}
+
+/// GIF flavoured LZW: variable width codes packed from the low bit up,
+/// starting one bit wider than `min_code_size`
+fn lzw_encode(pixels: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut output = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    let mut emit = |code: u16, width: u32, output: &mut Vec<u8>| {
+        buffer |= (code as u32) << bits;
+        bits += width;
+        while bits >= 8 {
+            output.push(buffer as u8);
+            buffer >>= 8;
+            bits -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut width = min_code_size + 1;
+    emit(clear, width, &mut output);
+
+    let mut current: Option<u16> = None;
+    for pixel in pixels {
+        let Some(prefix) = current else {
+            current = Some(*pixel as u16);
+            continue;
+        };
+
+        if let Some(code) = table.get(&(prefix, *pixel)) {
+            current = Some(*code);
+            continue;
+        }
+
+        emit(prefix, width, &mut output);
+        if next_code < LZW_MAX_CODES {
+            table.insert((prefix, *pixel), next_code);
+            // Decoders widen codes as soon as the table reaches the next power of two
+            if next_code == 1 << width && width < 12 {
+                width += 1;
+            }
+            next_code += 1;
+        } else {
+            emit(clear, width, &mut output);
+            table.clear();
+            next_code = end + 1;
+            width = min_code_size + 1;
+        }
+        current = Some(*pixel as u16);
+    }
+
+    if let Some(prefix) = current {
+        emit(prefix, width, &mut output);
+    }
+    emit(end, width, &mut output);
+    if bits > 0 {
+        output.push(buffer as u8);
+    }
+    output
+}
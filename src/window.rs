@@ -0,0 +1,698 @@
+//! minifb frontend: opens a window, forwards key presses to the machine and
+//! scales its 64x32 display up to the window size.
+//!
+//! The window can be resized while running. The display keeps its 2:1 shape
+//! and is letterboxed in the palette's `silence` color.
+
+use std::{
+    cell::RefCell, fs::File, io::{self, BufRead, BufWriter, Write}, net::SocketAddr, path::{Path, PathBuf}, rc::Rc, sync::mpsc::{self, Receiver}, thread, time::{
+        Duration,
+        Instant
+    }
+};
+
+use minifb::{
+    Key,
+    KeyRepeat,
+    ScaleMode,
+    Window,
+    WindowOptions
+};
+
+use crate::{
+    debugger::{self, Debugger},
+    display::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH},
+    gdb::GdbServer,
+    config::Config,
+    keymap::Keymap,
+    machine::Chip8Machine,
+    movie::Movie,
+    phosphor::{PhosphorMode, MAX_PHOSPHOR_FRAMES},
+    render::{Palette, Renderer, Scaling},
+    rewind::RewindBuffer,
+    savestate::SaveState,
+    screenshot::{ImageFormat, Screenshot},
+    timer::TIMER_HZ,
+    video::{VideoFormat, VideoRecorder}
+};
+
+pub const SCALE: usize = 16;
+pub const WIDTH: usize = DISPLAY_WIDTH * SCALE;
+pub const HEIGHT: usize = DISPLAY_HEIGHT * SCALE;
+
+/// F1-F4 load save state slots 1-4, with shift held they save
+pub const STATE_SLOT_KEYS: [Key; 4] = [Key::F1, Key::F2, Key::F3, Key::F4];
+
+/// Hold to run emulation backwards
+pub const REWIND_KEY: Key = Key::Backspace;
+
+/// Save the current frame as shown in the window, with shift held at the
+/// display's own resolution
+pub const SCREENSHOT_KEY: Key = Key::F12;
+
+/// Start or stop recording a GIF
+pub const RECORD_KEY: Key = Key::F9;
+
+/// Host pixels per CHIP-8 pixel in recordings, smaller than the window to
+/// keep files small
+pub const RECORD_SCALE: usize = 4;
+
+/// Host pixels kept around the display for the beep indicator
+pub const BEEP_BORDER: usize = SCALE;
+
+/// Switch between integer and fit scaling
+pub const SCALING_KEY: Key = Key::F10;
+
+#[derive(Debug)]
+pub enum EmulatorError {
+    DisplayCreationError
+}
+
+/// How the window opens
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowSettings {
+    /// Size the window opens at, it can be resized after
+    pub width: usize,
+    pub height: usize,
+    pub scaling: Scaling,
+    /// minifb has no real fullscreen, so this is a borderless window on top
+    /// of everything in the top left corner of the screen, at the size above.
+    /// Backends without borderless windows, like Wayland, give a normal one.
+    pub fullscreen: bool,
+    /// Flicker filter for what's shown in the window
+    pub phosphor: PhosphorMode
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        WindowSettings {
+            width: WIDTH,
+            height: HEIGHT,
+            scaling: Scaling::default(),
+            fullscreen: false,
+            phosphor: PhosphorMode::default()
+        }
+    }
+}
+
+impl WindowSettings {
+    /// Window `scale` times the size of the 64x32 display
+    pub fn set_scale(&mut self, scale: usize) {
+        self.width = DISPLAY_WIDTH * scale.max(1);
+        self.height = DISPLAY_HEIGHT * scale.max(1);
+    }
+
+    /// Start from `base` and apply the `[window]` sections of `config` that
+    /// apply to `rom`. Errors name the line they're on.
+    pub fn from_config(base: WindowSettings, config: &Config, rom: Option<&str>) -> Result<Self, String> {
+        let mut settings = base;
+
+        for section in config.sections("window", rom) {
+            for entry in &section.entries {
+                let error = |message: String| format!("line {}: {}", entry.line, message);
+                let size = || entry.value.parse::<usize>().ok().filter(|size| *size > 0);
+
+                match entry.name.as_str() {
+                    "scale" => settings.set_scale(size().ok_or_else(|| error(format!("invalid scale '{}'", entry.value)))?),
+                    "width" => settings.width = size().ok_or_else(|| error(format!("invalid width '{}'", entry.value)))?,
+                    "height" => settings.height = size().ok_or_else(|| error(format!("invalid height '{}'", entry.value)))?,
+                    "scaling" => {
+                        settings.scaling = Scaling::from_name(&entry.value)
+                                            .ok_or_else(|| error(format!("unknown scaling '{}', expected integer or fit", entry.value)))?;
+                    },
+                    "fullscreen" => {
+                        settings.fullscreen = match entry.value.to_ascii_lowercase().as_str() {
+                            "on" | "yes" | "true" | "1" => true,
+                            "off" | "no" | "false" | "0" => false,
+                            _ => return Err(error(format!("expected on or off for fullscreen, got '{}'", entry.value)))
+                        };
+                    },
+                    "phosphor" => {
+                        settings.phosphor = PhosphorMode::parse(&entry.value)
+                                            .ok_or_else(|| error(format!("invalid phosphor mode '{}', expected off, fade or blend with 1-{} frames", entry.value, MAX_PHOSPHOR_FRAMES)))?;
+                    },
+                    name => return Err(error(format!("unknown window setting '{}'", name)))
+                }
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+pub struct Chip8Window {
+    window: Window,
+    renderer: Renderer,
+    beep_indicator: bool
+}
+
+impl Chip8Window {
+    pub fn new(name: &str, settings: &WindowSettings) -> Result<Self, minifb::Error> {
+        let options = WindowOptions {
+            borderless: settings.fullscreen,
+            title: !settings.fullscreen,
+            topmost: settings.fullscreen,
+            resize: !settings.fullscreen,
+            // The buffer is always made the window's size, this only shows
+            // while a resize hasn't been caught up with
+            scale_mode: ScaleMode::UpperLeft,
+            ..WindowOptions::default()
+        };
+        let mut window = Window::new(name, settings.width, settings.height, options)?;
+        if settings.fullscreen {
+            window.set_position(0, 0);
+        }
+
+        let mut renderer = Renderer::new(SCALE, Palette::default());
+        renderer.set_scaling(settings.scaling);
+        renderer.set_phosphor(settings.phosphor);
+
+        Ok(
+            Chip8Window {
+                window,
+                renderer,
+                beep_indicator: false
+            }
+        )
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    pub fn renderer(&mut self) -> &mut Renderer {
+        &mut self.renderer
+    }
+
+    /// Leave a border around the display that lights up in the palette's
+    /// buzzer color while the sound timer runs
+    pub fn set_beep_indicator(&mut self, on: bool) {
+        self.beep_indicator = on;
+    }
+
+    /// Render the machine's display and show it in the window
+    pub fn present(&mut self, display: &Display, beeping: bool) -> Result<(), minifb::Error> {
+        // Drawn at whatever size the window has now, so resizing just works
+        let (width, height) = self.window.get_size();
+        self.renderer.set_frame(Some((width.max(1), height.max(1))));
+        self.renderer.set_margin(if self.beep_indicator { BEEP_BORDER } else { 0 });
+        self.renderer.set_beeping(self.beep_indicator && beeping);
+        self.renderer.render(display);
+        self.window.update_with_buffer(self.renderer.buffer(), self.renderer.width(), self.renderer.height())
+    }
+}
+
+/// Presses CHIP-8 keys for the host keys bound to them in a [`Keymap`]
+struct Chip8KeyboardCallback {
+    keys: Rc<RefCell<[bool; 16]>>,
+    bindings: Vec<(Key, usize)>,
+    held: Vec<Key>
+}
+
+impl Chip8KeyboardCallback {
+    pub fn new(keys: Rc<RefCell<[bool; 16]>>, keymap: &Keymap) -> Self {
+        let bindings = keymap.bindings()
+                            .filter_map(|(host, key)| Some((host_key(host)?, key as usize)))
+                            .collect();
+
+        Chip8KeyboardCallback {
+            keys,
+            bindings,
+            held: Vec::new()
+        }
+    }
+}
+
+
+impl minifb::InputCallback for Chip8KeyboardCallback {
+    fn add_char(&mut self, _uni_char: u32) {
+
+    }
+
+    fn set_key_state(&mut self, pressed_key: minifb::Key, pressed: bool) {
+        self.held.retain(|held| *held != pressed_key);
+        if pressed {
+            self.held.push(pressed_key);
+        }
+
+        // A CHIP-8 key stays down while any of its host keys is held
+        for (host, key) in &self.bindings {
+            if *host == pressed_key {
+                let down = self.bindings.iter().any(|(other, bound)| bound == key && self.held.contains(other));
+                self.keys.borrow_mut()[*key] = down;
+            }
+        }
+    }
+}
+
+/// The minifb key for a name in [`HOST_KEYS`](crate::keymap::HOST_KEYS)
+fn host_key(name: &str) -> Option<Key> {
+    let key = match name {
+        "0" => Key::Key0, "1" => Key::Key1, "2" => Key::Key2, "3" => Key::Key3, "4" => Key::Key4,
+        "5" => Key::Key5, "6" => Key::Key6, "7" => Key::Key7, "8" => Key::Key8, "9" => Key::Key9,
+        "a" => Key::A, "b" => Key::B, "c" => Key::C, "d" => Key::D, "e" => Key::E, "f" => Key::F,
+        "g" => Key::G, "h" => Key::H, "i" => Key::I, "j" => Key::J, "k" => Key::K, "l" => Key::L,
+        "m" => Key::M, "n" => Key::N, "o" => Key::O, "p" => Key::P, "q" => Key::Q, "r" => Key::R,
+        "s" => Key::S, "t" => Key::T, "u" => Key::U, "v" => Key::V, "w" => Key::W, "x" => Key::X,
+        "y" => Key::Y, "z" => Key::Z,
+        "up" => Key::Up, "down" => Key::Down, "left" => Key::Left, "right" => Key::Right,
+        "space" => Key::Space, "enter" => Key::Enter, "tab" => Key::Tab, "escape" => Key::Escape,
+        "apostrophe" => Key::Apostrophe, "comma" => Key::Comma, "period" => Key::Period,
+        "semicolon" => Key::Semicolon, "slash" => Key::Slash, "minus" => Key::Minus,
+        "equal" => Key::Equal, "backquote" => Key::Backquote,
+        "leftbracket" => Key::LeftBracket, "rightbracket" => Key::RightBracket, "backslash" => Key::Backslash,
+        "leftshift" => Key::LeftShift, "rightshift" => Key::RightShift,
+        "leftctrl" => Key::LeftCtrl, "rightctrl" => Key::RightCtrl,
+        "numpad0" => Key::NumPad0, "numpad1" => Key::NumPad1, "numpad2" => Key::NumPad2,
+        "numpad3" => Key::NumPad3, "numpad4" => Key::NumPad4, "numpad5" => Key::NumPad5,
+        "numpad6" => Key::NumPad6, "numpad7" => Key::NumPad7, "numpad8" => Key::NumPad8,
+        "numpad9" => Key::NumPad9,
+        "numpadenter" => Key::NumPadEnter, "numpadplus" => Key::NumPadPlus, "numpadminus" => Key::NumPadMinus,
+        _ => return None
+    };
+    Some(key)
+}
+
+pub struct Chip8Emulator {
+    machine: Chip8Machine,
+    window: Chip8Window,
+    keyboard: Rc<RefCell<[bool; 16]>>,
+    state_path: Option<PathBuf>,
+    screenshot_format: ImageFormat,
+    recorder: Option<VideoRecorder>,
+    movie_recording: Option<(Movie, PathBuf)>,
+    movie_playback: Option<Movie>,
+    rewind: RewindBuffer,
+    debugger: Option<(Debugger, Receiver<String>)>,
+    gdb: Option<GdbServer>
+}
+
+impl Chip8Emulator {
+    pub fn new() -> Result<Self, EmulatorError> {
+        Self::with_machine(Chip8Machine::new())
+    }
+
+    /// Open a window for a machine that's already set up
+    pub fn with_machine(machine: Chip8Machine) -> Result<Self, EmulatorError> {
+        Self::with_settings(machine, &WindowSettings::default())
+    }
+
+    /// Open a window with `settings` for a machine that's already set up
+    pub fn with_settings(machine: Chip8Machine, settings: &WindowSettings) -> Result<Self, EmulatorError> {
+
+        let mut window = Chip8Window::new("CHIP-8 Emulator", settings)
+                                        .map_err(|_e| EmulatorError::DisplayCreationError)?;
+
+        let keyboard = Rc::new(RefCell::new([false; 16]));
+        window.window.set_input_callback(Box::new(Chip8KeyboardCallback::new(keyboard.clone(), &Keymap::default())));
+
+        // Only touched again when there is a speaker to attach
+        #[allow(unused_mut)]
+        let mut machine = machine;
+
+        #[cfg(feature = "audio")]
+        match crate::speaker::Speaker::new() {
+            Ok(speaker) => machine.set_audio(Box::new(speaker)),
+            Err(error) => eprintln!("Could not open audio output, running silent: {:?}", error)
+        }
+
+        Ok(
+            Chip8Emulator {
+                machine,
+                window,
+                keyboard,
+                state_path: None,
+                screenshot_format: ImageFormat::default(),
+                recorder: None,
+                movie_recording: None,
+                movie_playback: None,
+                rewind: RewindBuffer::default(),
+                debugger: None,
+                gdb: None
+            }
+        )
+    }
+
+    /// Use `keymap` for the keypad instead of the QWERTY preset
+    pub fn set_keymap(&mut self, keymap: &Keymap) {
+        *self.keyboard.borrow_mut() = [false; 16];
+        self.window.window.set_input_callback(Box::new(Chip8KeyboardCallback::new(self.keyboard.clone(), keymap)));
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.window.renderer.palette = palette;
+    }
+
+    /// Show when the machine beeps with a border around the display, see
+    /// [`Chip8Window::set_beep_indicator`]
+    pub fn set_beep_indicator(&mut self, on: bool) {
+        self.window.set_beep_indicator(on);
+    }
+
+    /// Set the instruction rate in Hz
+    pub fn set_clock(&mut self, hz: u32) {
+        self.machine.set_clock(hz);
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), &'static str> {
+        self.machine.load_rom(rom)
+    }
+
+    /// Where save state slots go: slot n is saved to `path` with
+    /// `.state<n>` appended. Without one the save state hotkeys do nothing.
+    pub fn set_state_path(&mut self, path: PathBuf) {
+        self.state_path = Some(path);
+    }
+
+    /// File for save state `slot` (starting at 1)
+    pub fn state_slot_path(&self, slot: usize) -> Option<PathBuf> {
+        let mut path = self.state_path.clone()?.into_os_string();
+        path.push(format!(".state{}", slot));
+        Some(path.into())
+    }
+
+    fn handle_state_keys(&mut self) {
+        let saving = self.window.window.is_key_down(Key::LeftShift) || self.window.window.is_key_down(Key::RightShift);
+
+        for (index, key) in STATE_SLOT_KEYS.into_iter().enumerate() {
+            if !self.window.window.is_key_pressed(key, KeyRepeat::No) {
+                continue;
+            }
+            let Some(path) = self.state_slot_path(index + 1) else {
+                continue;
+            };
+
+            if saving {
+                match self.machine.save_state().save(&path) {
+                    Ok(()) => eprintln!("Saved state to {}", path.display()),
+                    Err(error) => eprintln!("Could not save state to {}: {}", path.display(), error)
+                }
+            } else {
+                match SaveState::load(&path) {
+                    Ok(state) => {
+                        self.machine.load_state(&state);
+                        eprintln!("Loaded state from {}", path.display());
+                    },
+                    Err(error) => eprintln!("Could not load state from {}: {}", path.display(), error)
+                }
+            }
+        }
+    }
+
+    pub fn set_screenshot_format(&mut self, format: ImageFormat) {
+        self.screenshot_format = format;
+    }
+
+    /// Save the current frame in the ROM's directory, named after the ROM
+    /// and the frame number. `native` saves it at the display's own
+    /// resolution instead of the window's scale.
+    pub fn save_screenshot(&mut self, native: bool) -> io::Result<PathBuf> {
+        let scale = if native { 1 } else { self.window.renderer.scale() };
+        let screenshot = Screenshot::new(self.screenshot_format, scale, self.window.renderer.palette);
+        let path = self.output_path(&screenshot.file_name(&self.rom_name(), self.machine.frame()));
+
+        screenshot.save(&self.machine.display, &path)?;
+        Ok(path)
+    }
+
+    /// Name of the ROM, without its directory or extension
+    fn rom_name(&self) -> String {
+        self.state_path.as_ref()
+            .and_then(|path| path.file_stem())
+            .and_then(|name| name.to_str())
+            .unwrap_or("chip8")
+            .to_string()
+    }
+
+    /// Files like screenshots go next to the ROM
+    fn output_path(&self, file_name: &str) -> PathBuf {
+        let directory = self.state_path.as_ref().and_then(|path| path.parent()).unwrap_or(Path::new(""));
+        directory.join(file_name)
+    }
+
+    fn handle_screenshot_key(&mut self) {
+        if !self.window.window.is_key_pressed(SCREENSHOT_KEY, KeyRepeat::No) {
+            return;
+        }
+
+        let native = self.window.window.is_key_down(Key::LeftShift) || self.window.window.is_key_down(Key::RightShift);
+        match self.save_screenshot(native) {
+            Ok(path) => eprintln!("Saved screenshot to {}", path.display()),
+            Err(error) => eprintln!("Could not save screenshot: {}", error)
+        }
+    }
+
+    fn handle_scaling_key(&mut self) {
+        if !self.window.window.is_key_pressed(SCALING_KEY, KeyRepeat::No) {
+            return;
+        }
+
+        let scaling = match self.window.renderer.scaling() {
+            Scaling::Integer => Scaling::Fit,
+            Scaling::Fit => Scaling::Integer
+        };
+        self.window.renderer.set_scaling(scaling);
+        eprintln!("Scaling: {}", scaling.name());
+    }
+
+    /// Add every frame shown from now on to `recorder`, replacing any
+    /// recording already running
+    pub fn start_recording(&mut self, recorder: VideoRecorder) {
+        if let Err(error) = self.stop_recording() {
+            eprintln!("Could not finish recording: {}", error);
+        }
+        self.recorder = Some(recorder);
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Finish the recording, if there is one
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(())
+        }
+    }
+
+    fn handle_record_key(&mut self) {
+        if !self.window.window.is_key_pressed(RECORD_KEY, KeyRepeat::No) {
+            return;
+        }
+
+        if let Some(recorder) = self.recorder.take() {
+            let frames = recorder.frames();
+            match recorder.finish() {
+                Ok(()) => eprintln!("Stopped recording after {} frames", frames),
+                Err(error) => eprintln!("Could not finish recording: {}", error)
+            }
+            return;
+        }
+
+        let format = VideoFormat::Gif;
+        let file_name = format!("{}-{:06}.{}", self.rom_name(), self.machine.frame(), format.extension());
+        let path = self.output_path(&file_name);
+        match File::create(&path) {
+            Ok(file) => {
+                let recorder = VideoRecorder::new(Box::new(BufWriter::new(file)), format, RECORD_SCALE, self.window.renderer.palette);
+                self.recorder = Some(recorder);
+                eprintln!("Recording to {}", path.display());
+            },
+            Err(error) => eprintln!("Could not record to {}: {}", path.display(), error)
+        }
+    }
+
+    /// Show the display, and add it to the recording if there is one
+    fn present(&mut self) {
+        self.window.present(&self.machine.display, self.machine.is_beeping()).unwrap();
+
+        if let Some(recorder) = self.recorder.as_mut()
+            && let Err(error) = recorder.record_frame(&self.machine.display) {
+            eprintln!("Recording stopped: {}", error);
+            self.recorder = None;
+        }
+    }
+
+    /// Record the keys held on every frame into `movie`, saved to `path` when
+    /// the window closes
+    pub fn record_movie(&mut self, movie: Movie, path: PathBuf) {
+        self.movie_recording = Some((movie, path));
+    }
+
+    /// Take the keys from `movie` instead of the keyboard until it ends
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.movie_playback = Some(movie);
+    }
+
+    /// Keys for the frame about to run, from the movie being played or the
+    /// keyboard, noted in the movie being recorded
+    fn frame_keys(&mut self) -> [bool; 16] {
+        let frame = self.machine.frame();
+
+        if let Some(movie) = &self.movie_playback
+            && frame >= movie.length {
+            match movie.check_end(&self.machine) {
+                Ok(()) => eprintln!("Movie finished after {} frames, in sync", movie.length),
+                Err(error) => eprintln!("{}", error)
+            }
+            self.movie_playback = None;
+        }
+
+        let keys = match &self.movie_playback {
+            Some(movie) => movie.keys_at(frame),
+            None => *self.keyboard.borrow()
+        };
+        if let Some((movie, _)) = self.movie_recording.as_mut() {
+            movie.record(frame, keys);
+        }
+        keys
+    }
+
+    fn save_movie(&mut self) {
+        let Some((mut movie, path)) = self.movie_recording.take() else {
+            return;
+        };
+
+        movie.finish(&self.machine);
+        match movie.save(&path) {
+            Ok(()) => eprintln!("Saved movie of {} frames to {}", movie.length, path.display()),
+            Err(error) => eprintln!("Could not save movie to {}: {}", path.display(), error)
+        }
+    }
+
+    /// Keep up to `frames` frames of history in at most `memory_budget`
+    /// bytes for rewinding. 0 frames turns rewinding off.
+    pub fn set_rewind(&mut self, frames: usize, memory_budget: usize) {
+        self.rewind = RewindBuffer::new(frames, memory_budget);
+    }
+
+    /// Start paused with a debugger REPL on the terminal. Commands are read
+    /// on a separate thread and run between frames.
+    pub fn enable_debugger(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("Debugger paused at\n{}\nType help for commands", debugger::location(&self.machine));
+        prompt();
+        self.debugger = Some((Debugger::new(), receiver));
+    }
+
+    /// Serve the GDB remote protocol on `port` of the loopback interface
+    pub fn enable_gdb(&mut self, port: u16) -> io::Result<SocketAddr> {
+        let server = GdbServer::bind(("127.0.0.1", port))?;
+        let addr = server.local_addr()?;
+        self.gdb = Some(server);
+        Ok(addr)
+    }
+
+    /// Run commands typed since the last frame, then the frame itself
+    fn run_debugger_frame(&mut self) {
+        let Some((debugger, commands)) = self.debugger.as_mut() else {
+            return;
+        };
+
+        while let Ok(line) = commands.try_recv() {
+            match debugger.execute(&mut self.machine, &line) {
+                Ok(output) if output.is_empty() => (),
+                Ok(output) => println!("{}", output),
+                Err(error) => println!("{}", error)
+            }
+            if debugger.is_paused() {
+                prompt();
+            }
+        }
+
+        if let Some(reason) = debugger.run_frame(&mut self.machine) {
+            println!("Stopped: {}\n{}", reason, debugger::location(&self.machine));
+            let views = debugger.views(&self.machine);
+            if !views.is_empty() {
+                println!("{}", views);
+            }
+            prompt();
+        }
+    }
+
+    pub fn machine(&self) -> &Chip8Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Chip8Machine {
+        &mut self.machine
+    }
+
+    /// Run one emulated frame per 60 Hz of real time until the window closes.
+    /// Input is sampled and the display presented once per frame.
+    pub fn run(&mut self) {
+        let interval = Duration::from_secs(1) / TIMER_HZ;
+        let mut next_time = Instant::now() + interval;
+        while self.window.is_open() && !self.machine.has_exited() {
+            let sleep_time = next_time.saturating_duration_since(Instant::now());
+            thread::sleep(sleep_time);
+            next_time += interval;
+            self.handle_state_keys();
+            self.handle_screenshot_key();
+            self.handle_record_key();
+            self.handle_scaling_key();
+
+            // Go back one frame per frame, stopping at the oldest one kept
+            if self.window.window.is_key_down(REWIND_KEY) {
+                if let Some(state) = self.rewind.pop() {
+                    self.machine.load_state(&state);
+                }
+                self.present();
+                continue;
+            }
+
+            self.machine.keys = self.frame_keys();
+
+            if let Some(gdb) = self.gdb.as_mut() {
+                if !gdb.is_paused() {
+                    self.rewind.push(&self.machine.save_state());
+                }
+                if let Err(error) = gdb.run_frame(&mut self.machine) {
+                    eprintln!("GDB server error: {}", error);
+                }
+                self.present();
+                continue;
+            }
+
+            if let Some((debugger, _)) = &self.debugger {
+                // Nothing happens while paused, so there's nothing to remember
+                if !debugger.is_paused() {
+                    self.rewind.push(&self.machine.save_state());
+                }
+                self.run_debugger_frame();
+                self.present();
+                continue;
+            }
+
+            self.rewind.push(&self.machine.save_state());
+
+            // Keep the window up after a fault so the last frame can be seen
+            if self.machine.halted().is_none()
+                && let Err(fault) = self.machine.run_frame() {
+                eprintln!("CPU halted: {}", fault);
+            }
+
+            self.present();
+        }
+
+        if let Err(error) = self.stop_recording() {
+            eprintln!("Could not finish recording: {}", error);
+        }
+        self.save_movie();
+    }
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
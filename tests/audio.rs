@@ -0,0 +1,52 @@
+//! Checks the beeper against the sound timer with the recording backend.
+
+use chip8::{asm::assemble, audio::BeepEvent, Chip8Machine, Platform, RecordingAudio};
+
+/// Run `source` for `frames` frames and return the beeps it made
+fn beeps(source: &str, frames: u32) -> Vec<(u64, bool)> {
+    let program = assemble(source, Platform::Chip8).unwrap();
+    let audio = RecordingAudio::new();
+
+    // Fast enough that the delay loop below sees DT reach 0 on the frame it
+    // does
+    let mut machine = Chip8Machine::new();
+    machine.set_clock(600);
+    machine.set_audio(Box::new(audio.clone()));
+    machine.load_rom(&program.bytes).unwrap();
+    for _ in 0..frames {
+        machine.run_frame().unwrap();
+    }
+
+    audio.events().into_iter().map(|BeepEvent { frame, playing }| (frame, playing)).collect()
+}
+
+#[test]
+fn beep_lasts_as_many_frames_as_st() {
+    let source = "
+        v0 := 3
+        buzzer := v0
+        : spin
+        jump spin
+    ";
+    assert_eq!(beeps(source, 10), vec![(0, true), (3, false)]);
+}
+
+#[test]
+fn beeps_start_and_stop_on_the_right_frames() {
+    // Beep for 3 frames, wait for the delay timer to run out, then beep
+    // for 2 more
+    let source = "
+        v0 := 3
+        buzzer := v0
+        v1 := 10
+        delay := v1
+        : wait
+        v2 := delay
+        if v2 != 0 then jump wait
+        v0 := 2
+        buzzer := v0
+        : spin
+        jump spin
+    ";
+    assert_eq!(beeps(source, 20), vec![(0, true), (3, false), (10, true), (12, false)]);
+}
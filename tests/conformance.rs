@@ -0,0 +1,116 @@
+//! Runs the bundled Timendus test ROMs without a window and checks what they
+//! draw.
+//!
+//! Every ROM is run for a fixed number of frames and its framebuffer is
+//! compared with a golden image in `tests/golden`. They're plain PBM
+//! screenshots, one line of 0s and 1s per row. Run with `UPDATE_GOLDEN=1` to
+//! write the current framebuffers as the new golden images after a
+//! deliberate change.
+//!
+//! corax+ and flags also draw a mark after every test they run, a tick when
+//! it passed and a cross when it failed, so those are counted too.
+
+use std::{env, fs, path::PathBuf};
+
+use chip8::{Chip8Machine, Display, ImageFormat, Screenshot};
+
+/// Instructions per second the ROMs run at. The frame counts below are
+/// enough for each ROM to finish at this speed.
+const CLOCK_HZ: u32 = 600;
+
+/// The pass mark: a blank row, then the three rows of a tick
+const PASS_MARK: [u8; 4] = [0b000, 0b101, 0b110, 0b100];
+/// The fail mark: a blank row, then the three rows of a cross
+const FAIL_MARK: [u8; 4] = [0b000, 0b101, 0b010, 0b101];
+
+fn run_rom(name: &str, frames: u32) -> Chip8Machine {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(name);
+    let rom = fs::read(&path).unwrap_or_else(|error| panic!("Could not open {}: {}", path.display(), error));
+
+    let mut machine = Chip8Machine::new();
+    machine.set_clock(CLOCK_HZ);
+    machine.load_rom(&rom).unwrap();
+    for _ in 0..frames {
+        machine.run_frame().unwrap();
+    }
+    machine
+}
+
+fn assert_golden(name: &str, display: &Display) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.pbm", name));
+    let screenshot = Screenshot { format: ImageFormat::Pbm, ..Screenshot::default() };
+    let actual = String::from_utf8(screenshot.encode(display)).unwrap();
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|error| panic!("Could not open {}: {}", path.display(), error));
+    assert!(actual == expected, "{} doesn't match {}\n\nexpected:\n{}\nactual:\n{}", name, path.display(), expected, actual);
+}
+
+/// Count the pass and fail marks on screen. A mark is 3 pixels wide and must
+/// have nothing else lit around it, so the letters next to it don't count.
+fn count_marks(display: &Display) -> (usize, usize) {
+    let lit = |x: isize, y: isize| {
+        x >= 0 && y >= 0 && (x as usize) < display.width() && (y as usize) < display.height() && display.get_pixel(x as usize, y as usize)
+    };
+
+    let mut passed = 0;
+    let mut failed = 0;
+    for y in -1..display.height() as isize {
+        for x in -1..display.width() as isize {
+            let rows: Vec<u8> = (0..4)
+                                .map(|row| (0..3).fold(0, |bits, column| (bits << 1) | lit(x + column, y + row) as u8))
+                                .collect();
+            let border_clear = (-1..5).all(|row| !lit(x - 1, y + row) && !lit(x + 3, y + row))
+                               && (0..3).all(|column| !lit(x + column, y + 4));
+
+            if border_clear && rows == PASS_MARK {
+                passed += 1;
+            } else if border_clear && rows == FAIL_MARK {
+                failed += 1;
+            }
+        }
+    }
+    (passed, failed)
+}
+
+#[test]
+fn chip8_logo() {
+    let machine = run_rom("1-chip8-logo.ch8", 60);
+    assert_golden("1-chip8-logo", &machine.display);
+}
+
+#[test]
+fn ibm_logo() {
+    let machine = run_rom("2-ibm-logo.ch8", 60);
+    assert_golden("2-ibm-logo", &machine.display);
+}
+
+#[test]
+fn corax_plus() {
+    let machine = run_rom("3-corax+.ch8", 120);
+    let (passed, failed) = count_marks(&machine.display);
+    assert_eq!(failed, 0, "corax+ reports failed tests");
+    assert_eq!(passed, 22, "corax+ didn't finish all its tests");
+
+    assert_golden("3-corax+", &machine.display);
+}
+
+#[test]
+fn flags() {
+    let machine = run_rom("4-flags.ch8", 180);
+    let (passed, failed) = count_marks(&machine.display);
+    assert_eq!(failed, 0, "flags reports failed tests");
+    assert_eq!(passed, 47, "flags didn't finish all its tests");
+
+    assert_golden("4-flags", &machine.display);
+}
+
+#[test]
+fn keypad_menu() {
+    let machine = run_rom("6-keypad.ch8", 60);
+    assert_golden("6-keypad", &machine.display);
+}
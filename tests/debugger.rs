@@ -0,0 +1,113 @@
+//! Debugger commands: reading memory stops at its end instead of wrapping,
+//! a halted machine stays paused and views follow the machine as it steps.
+
+use chip8::{debugger::StopReason, Chip8Machine, Debugger, Platform};
+
+fn run(machine: &mut Chip8Machine, line: &str) -> String {
+    Debugger::new().execute(machine, line).unwrap()
+}
+
+#[test]
+fn dump_stops_at_the_end_of_memory() {
+    let mut machine = Chip8Machine::new();
+    machine.cpu.ram[0xFFF] = 0xAB;
+
+    let output = run(&mut machine, "x 0xFF0 16");
+    assert!(!output.contains("left"), "{}", output);
+
+    let output = run(&mut machine, "x 0xFF8 0xFFFFFFFF");
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines, ["0FF8  00 00 00 00 00 00 00 AB", "(only 8 bytes left before the end of memory)"]);
+}
+
+#[test]
+fn disassembly_stops_at_the_end_of_memory() {
+    let mut machine = Chip8Machine::new();
+
+    let output = run(&mut machine, "dis 0xFF8 8");
+    assert_eq!(output.lines().count(), 5);
+    assert!(output.starts_with(" 0FF8"), "{}", output);
+    assert!(output.ends_with("(only 4 instructions left before the end of memory)"), "{}", output);
+
+    // A whole instruction still fits in the last two bytes, half of one
+    // doesn't
+    assert_eq!(run(&mut machine, "dis 0xFFE 1").lines().count(), 1);
+    assert_eq!(run(&mut machine, "dis 0xFFF 1"), "(only 0 instructions left before the end of memory)");
+
+    // XO-CHIP memory ends where u16 addresses do
+    machine.set_platform(Platform::XoChip);
+    let output = run(&mut machine, "dis 0xFFFC 0xFFFFFFFF");
+    assert_eq!(output.lines().count(), 3, "{}", output);
+}
+
+/// Run frames until the debugger stops
+fn run_until_stopped(debugger: &mut Debugger, machine: &mut Chip8Machine) -> StopReason {
+    loop {
+        if let Some(reason) = debugger.run_frame(machine) {
+            return reason;
+        }
+    }
+}
+
+/// V0 += 1 forever
+const COUNTER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+#[test]
+fn continuing_a_halted_machine_stays_paused() {
+    // 0000 isn't an instruction
+    let mut machine = Chip8Machine::new();
+    machine.load_rom(&[0x00, 0x00]).unwrap();
+    let mut debugger = Debugger::new();
+
+    assert_eq!(debugger.execute(&mut machine, "c"), Ok(String::from("Running")));
+    assert_eq!(machine.halted(), None);
+    assert!(matches!(debugger.run_frame(&mut machine), Some(StopReason::Fault(_))));
+    assert!(debugger.is_paused());
+    let fault = machine.halted().unwrap();
+
+    // Said once for each command, without running into the fault again
+    for command in ["c", "continue", "s", "step 3", "n", "f"] {
+        let error = debugger.execute(&mut machine, command).unwrap_err();
+        assert_eq!(error, format!("Can't run, the CPU halted: {}", fault));
+        assert!(debugger.is_paused());
+        assert_eq!(debugger.run_frame(&mut machine), None);
+    }
+
+    // Everything else still works
+    assert!(debugger.execute(&mut machine, "regs").is_ok());
+}
+
+#[test]
+fn views_follow_each_step() {
+    let mut machine = Chip8Machine::new();
+    machine.load_rom(&COUNTER).unwrap();
+    let mut debugger = Debugger::new();
+    assert_eq!(debugger.views(&machine), "");
+
+    let output = debugger.execute(&mut machine, "display regs").unwrap();
+    assert!(output.starts_with("V0=00 "), "{}", output);
+    debugger.execute(&mut machine, "display 0x200 2").unwrap();
+    debugger.execute(&mut machine, "display regs").unwrap();
+    debugger.execute(&mut machine, "display stack").unwrap();
+    assert_eq!(debugger.views(&machine).lines().count(), 5);
+
+    for count in 1..=3 {
+        debugger.execute(&mut machine, "s 2").unwrap();
+        assert_eq!(run_until_stopped(&mut debugger, &mut machine), StopReason::Step);
+        let views = debugger.views(&machine);
+        assert!(views.starts_with(&format!("V0={:02X} ", count)), "{}", views);
+        assert!(views.contains("0200  70 01\nStack is empty"), "{}", views);
+    }
+
+    // Edits show them too
+    let output = debugger.execute(&mut machine, "poke 0x201 5").unwrap();
+    assert!(output.ends_with("0200  70 05\nStack is empty"), "{}", output);
+    let output = debugger.execute(&mut machine, "p").unwrap();
+    assert!(output.contains("V0=03"), "{}", output);
+
+    assert_eq!(debugger.execute(&mut machine, "undisplay"), Ok(String::new()));
+    assert_eq!(debugger.views(&machine), "");
+    assert_eq!(debugger.execute(&mut machine, "p").unwrap().lines().count(), 1);
+
+    assert!(debugger.execute(&mut machine, "display 0x1000").is_err());
+}
@@ -0,0 +1,23 @@
+//! Disassembles the bundled ROMs and assembles the output again, which has
+//! to give back the exact same bytes on every platform.
+
+use std::{fs, path::PathBuf};
+
+use chip8::{asm::assemble, disasm::disassemble, Platform};
+
+const ROMS: [&str; 5] = ["1-chip8-logo.ch8", "2-ibm-logo.ch8", "3-corax+.ch8", "4-flags.ch8", "6-keypad.ch8"];
+
+#[test]
+fn disassembly_reassembles_to_the_same_rom() {
+    for name in ROMS {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(name);
+        let rom = fs::read(&path).unwrap_or_else(|error| panic!("Could not open {}: {}", path.display(), error));
+
+        for platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip] {
+            let source = disassemble(&rom, platform);
+            let program = assemble(&source, platform)
+                            .unwrap_or_else(|error| panic!("{} on {}: {}", name, platform.name(), error));
+            assert!(program.bytes == rom, "{} on {} doesn't reassemble to the same bytes", name, platform.name());
+        }
+    }
+}
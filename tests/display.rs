@@ -0,0 +1,124 @@
+//! The logical display: XOR drawing, collisions, the screen edges and
+//! clearing, directly and through DXYN/00E0, and how a Renderer shows it.
+
+use chip8::{Chip8Machine, Display, Palette, Quirks, Renderer};
+
+/// Run `program` for `count` instructions with `quirks`
+fn run(program: &[u8], quirks: Quirks, count: usize) -> Chip8Machine {
+    let mut machine = Chip8Machine::new();
+    machine.set_quirks(quirks);
+    machine.load_rom(program).unwrap();
+    for _ in 0..count {
+        machine.tick().unwrap();
+    }
+    machine
+}
+
+/// V0 := x, V1 := y, I := the sprite after the program, D012 for a 2 row
+/// sprite that is 8 pixels wide
+fn draw_at(x: u8, y: u8) -> Vec<u8> {
+    vec![0x60, x, 0x61, y, 0xA2, 0x0A, 0xD0, 0x12, 0x12, 0x08, 0xFF, 0xFF]
+}
+
+fn lit(display: &Display) -> Vec<(usize, usize)> {
+    let mut pixels = Vec::new();
+    for y in 0..display.height() {
+        for x in 0..display.width() {
+            if display.get_pixel(x, y) {
+                pixels.push((x, y));
+            }
+        }
+    }
+    pixels
+}
+
+#[test]
+fn flipping_xors_and_reports_collisions() {
+    let mut display = Display::new();
+    assert!(!display.flip_pixel(3, 4));
+    assert!(display.get_pixel(3, 4));
+
+    // Flipping a lit pixel turns it off and collides
+    assert!(display.flip_pixel(3, 4));
+    assert!(!display.get_pixel(3, 4));
+
+    display.set_pixel(5, 5, true);
+    assert!(display.flip_pixel(5, 5));
+    assert_eq!(lit(&display), []);
+}
+
+#[test]
+fn clear_blanks_everything() {
+    let mut display = Display::new();
+    display.set_pixel(0, 0, true);
+    display.set_pixel(63, 31, true);
+    display.clear();
+    assert_eq!(lit(&display), []);
+
+    // 00E0 after drawing
+    let mut program = draw_at(10, 10);
+    program[8..10].copy_from_slice(&[0x00, 0xE0]);
+    let machine = run(&program, Quirks::modern(), 4);
+    assert_eq!(lit(&machine.display).len(), 16);
+    let machine = run(&program, Quirks::modern(), 5);
+    assert_eq!(lit(&machine.display), []);
+}
+
+#[test]
+fn drawing_twice_erases_and_sets_vf() {
+    // Draw, then jump back to draw again
+    let mut program = draw_at(10, 10);
+    program[8..10].copy_from_slice(&[0x12, 0x06]);
+
+    let machine = run(&program, Quirks::modern(), 4);
+    assert_eq!(lit(&machine.display).len(), 16);
+    assert_eq!(machine.cpu.registers[0xF], 0);
+
+    let machine = run(&program, Quirks::modern(), 6);
+    assert_eq!(lit(&machine.display), []);
+    assert_eq!(machine.cpu.registers[0xF], 1);
+}
+
+#[test]
+fn sprites_clip_or_wrap_at_the_edges() {
+    let clip = Quirks { clip_sprites: true, ..Quirks::modern() };
+    let wrap = Quirks { clip_sprites: false, ..Quirks::modern() };
+
+    // Four columns past the right edge, one row past the bottom
+    let clipped = run(&draw_at(60, 31), clip, 4);
+    assert_eq!(lit(&clipped.display), [(60, 31), (61, 31), (62, 31), (63, 31)]);
+
+    let wrapped = run(&draw_at(60, 31), wrap, 4);
+    let pixels = lit(&wrapped.display);
+    assert_eq!(pixels.len(), 16);
+    assert!(pixels.contains(&(0, 0)) && pixels.contains(&(3, 0)) && pixels.contains(&(63, 31)));
+
+    // The start position always wraps, whatever the quirk
+    let machine = run(&draw_at(64 + 2, 32 + 1), clip, 4);
+    assert_eq!(lit(&machine.display).len(), 16);
+    assert!(machine.display.get_pixel(2, 1));
+}
+
+#[test]
+fn renderer_scales_pixels_into_palette_colors() {
+    let palette = Palette::default();
+    let mut display = Display::new();
+    display.set_pixel(1, 0, true);
+
+    let mut renderer = Renderer::new(2, palette);
+    let buffer = renderer.render(&display).to_vec();
+    assert_eq!((renderer.width(), renderer.height()), (128, 64));
+
+    // Pixel (1, 0) covers host pixels (2-3, 0-1)
+    for (x, y) in [(2, 0), (3, 0), (2, 1), (3, 1)] {
+        assert_eq!(buffer[y * 128 + x], palette.foreground);
+    }
+    for (x, y) in [(0, 0), (1, 1), (4, 0), (2, 2)] {
+        assert_eq!(buffer[y * 128 + x], palette.background);
+    }
+
+    // Rendering never changes the display
+    let before = display.clone();
+    renderer.render(&display);
+    assert_eq!(display, before);
+}
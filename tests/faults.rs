@@ -0,0 +1,46 @@
+//! What the fault policies do with a PC that leaves memory.
+
+use chip8::{Chip8CPU, Chip8Fault, Display, FaultPolicy};
+
+/// A CPU running `program` from 0x200 under `policy`
+fn cpu(program: &[u8], policy: FaultPolicy) -> Chip8CPU {
+    let mut cpu = Chip8CPU::new();
+    cpu.load_rom(program).unwrap();
+    cpu.fault_policy = policy;
+    cpu
+}
+
+fn tick(cpu: &mut Chip8CPU) -> Result<(), Chip8Fault> {
+    cpu.tick(&mut Display::new(), &[false; 16])
+}
+
+#[test]
+fn jump_to_the_last_byte_wraps_to_the_start() {
+    // JP 0xFFF, where only half an instruction fits
+    for policy in [FaultPolicy::Wrap, FaultPolicy::Ignore] {
+        let mut cpu = cpu(&[0x1F, 0xFF], policy);
+        assert_eq!(tick(&mut cpu), Ok(()));
+        assert_eq!(cpu.pc, 0x000, "{:?}", policy);
+    }
+
+    let mut cpu = cpu(&[0x1F, 0xFF], FaultPolicy::Halt);
+    let fault = Chip8Fault::PcOutOfRange { pc: 0x200, opcode: 0x1FFF, target: 0xFFF };
+    assert_eq!(tick(&mut cpu), Err(fault));
+    assert_eq!(cpu.pc, 0x200);
+}
+
+#[test]
+fn jump_past_the_end_wraps_around() {
+    // V0 := 2, JP V0 0xFFF lands on 0x1001
+    let mut cpu = cpu(&[0x60, 0x02, 0xBF, 0xFF], FaultPolicy::Wrap);
+    tick(&mut cpu).unwrap();
+    tick(&mut cpu).unwrap();
+    assert_eq!(cpu.pc, 0x001);
+}
+
+#[test]
+fn jump_to_the_last_whole_instruction_is_fine() {
+    let mut cpu = cpu(&[0x1F, 0xFE], FaultPolicy::Halt);
+    assert_eq!(tick(&mut cpu), Ok(()));
+    assert_eq!(cpu.pc, 0xFFE);
+}
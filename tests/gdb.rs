@@ -0,0 +1,168 @@
+//! Drives the GDB server with a scripted client over a real TCP socket.
+//!
+//! The server only does work inside `run_frame`, so every exchange sends a
+//! packet and then runs frames until the reply has come back.
+
+use std::{
+    fs,
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    path::PathBuf
+};
+
+use chip8::{gdb::GdbServer, Chip8Machine};
+
+/// Frames to wait for a reply before giving up
+const REPLY_FRAMES: usize = 1000;
+
+struct Client {
+    stream: TcpStream,
+    input: Vec<u8>
+}
+
+impl Client {
+    fn connect(server: &GdbServer) -> Self {
+        let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        Client { stream, input: Vec::new() }
+    }
+
+    fn send_raw(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    fn send(&mut self, body: &str) {
+        let checksum = body.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.send_raw(format!("${}#{:02x}", body, checksum).as_bytes());
+    }
+
+    /// The body of the next complete packet received, skipping acks
+    fn next_reply(&mut self) -> Option<String> {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(count) => self.input.extend_from_slice(&buffer[..count]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => panic!("Client read failed: {}", error)
+            }
+        }
+
+        let start = self.input.iter().position(|byte| *byte == b'$')?;
+        let end = self.input[start..].iter().position(|byte| *byte == b'#')? + start;
+        if self.input.len() < end + 3 {
+            return None;
+        }
+
+        let body = String::from_utf8(self.input[start + 1..end].to_vec()).unwrap();
+        self.input.drain(..end + 3);
+        Some(body)
+    }
+}
+
+fn setup() -> (GdbServer, Chip8Machine, Client) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("2-ibm-logo.ch8");
+    let mut machine = Chip8Machine::new();
+    machine.load_rom(&fs::read(path).unwrap()).unwrap();
+
+    let mut server = GdbServer::bind("127.0.0.1:0").unwrap();
+    let client = Client::connect(&server);
+    // Accepting the client pauses the machine
+    while !server.is_connected() {
+        server.run_frame(&mut machine).unwrap();
+    }
+    (server, machine, client)
+}
+
+/// Run frames until a reply arrives
+fn wait_reply(server: &mut GdbServer, machine: &mut Chip8Machine, client: &mut Client) -> String {
+    for _ in 0..REPLY_FRAMES {
+        server.run_frame(machine).unwrap();
+        if let Some(reply) = client.next_reply() {
+            return reply;
+        }
+    }
+    panic!("No reply from the server");
+}
+
+fn exchange(server: &mut GdbServer, machine: &mut Chip8Machine, client: &mut Client, body: &str) -> String {
+    client.send(body);
+    wait_reply(server, machine, client)
+}
+
+#[test]
+fn queries_registers_and_memory() {
+    let (mut server, mut machine, mut client) = setup();
+
+    let features = exchange(&mut server, &mut machine, &mut client, "qSupported:swbreak+");
+    assert!(features.contains("PacketSize="), "{}", features);
+    assert!(features.contains("qXfer:features:read+"), "{}", features);
+
+    // V0-VF, I, PC, SP, DT, ST with PC at 0x200, little endian
+    let registers = exchange(&mut server, &mut machine, &mut client, "g");
+    assert_eq!(registers.len(), 2 * (16 + 2 + 2 + 3));
+    assert_eq!(&registers[36..40], "0002");
+
+    // The IBM logo starts with 00E0, CLS
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "m200,2"), "00e0");
+}
+
+#[test]
+fn breakpoints_continue_and_step() {
+    let (mut server, mut machine, mut client) = setup();
+
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "Z0,206,2"), "OK");
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "c"), "T05swbreak:;");
+    assert_eq!(machine.cpu.pc, 0x206);
+
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "z0,206,2"), "OK");
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "s"), "S05");
+    assert_eq!(machine.cpu.pc, 0x208);
+}
+
+#[test]
+fn survives_malformed_packets() {
+    let (mut server, mut machine, mut client) = setup();
+
+    // Empty packet
+    client.send_raw(b"$#00");
+    assert_eq!(wait_reply(&mut server, &mut machine, &mut client), "");
+
+    // First character that isn't ASCII
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "\u{e9}x"), "");
+
+    // Ranges that overflow
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "mffffffffffffffff,2"), "E01");
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "Mffffffffffffffff,2:0000"), "E01");
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "qXfer:features:read:target.xml:1,ffffffffffffffff"), "E01");
+
+    // A packet that never ends is dropped, and the next one still works
+    let mut endless = vec![b'$'];
+    endless.resize(100_000, b'a');
+    client.send_raw(&endless);
+    for _ in 0..10 {
+        server.run_frame(&mut machine).unwrap();
+    }
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "m200,2"), "00e0");
+    assert!(server.is_connected());
+}
+
+#[test]
+fn resume_address_has_to_be_in_ram() {
+    let (mut server, mut machine, mut client) = setup();
+
+    // CHIP-8 has 4K, so 0x1000 is just past the end
+    for packet in ["c1000", "s1000", "cffff", "s10000", "cffffffffffffffffffff", "s20z"] {
+        assert_eq!(exchange(&mut server, &mut machine, &mut client, packet), "E01", "{}", packet);
+        assert_eq!(machine.cpu.pc, 0x200, "{}", packet);
+        assert!(server.is_paused(), "{}", packet);
+    }
+
+    // The last instruction in memory is fine, the step runs from there
+    machine.cpu.ram[0xFFE..].copy_from_slice(&[0x12, 0x04]);
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "sffe"), "S05");
+    assert_eq!(machine.cpu.pc, 0x204);
+
+    assert_eq!(exchange(&mut server, &mut machine, &mut client, "s202"), "S05");
+    assert_eq!(machine.cpu.pc, 0x204);
+}
@@ -0,0 +1,121 @@
+//! Keymap presets, `[keymap]` sections and the errors a bad one gives.
+
+use chip8::{config::Config, keymap::KEYMAP_PRESETS, Keymap};
+
+fn from_config(text: &str, rom: Option<&str>) -> Result<Keymap, String> {
+    Keymap::from_config(Keymap::default(), &Config::parse(text)?, rom)
+}
+
+#[test]
+fn presets() {
+    for name in KEYMAP_PRESETS {
+        let keymap = Keymap::preset(name).unwrap();
+        assert!((0..16).all(|key| !keymap.hosts(key).is_empty()), "{}", name);
+    }
+    assert_eq!(Keymap::preset("QWERTY"), Some(Keymap::default()));
+    assert_eq!(Keymap::preset("colemak"), None);
+
+    assert_eq!(Keymap::qwerty().hosts(0x1), ["1"]);
+    assert_eq!(Keymap::qwerty().hosts(0xF), ["v"]);
+    assert_eq!(Keymap::azerty().hosts(0x5), ["z"]);
+    assert_eq!(Keymap::dvorak().hosts(0x5), ["comma"]);
+    assert_eq!(Keymap::arrows().hosts(0x2), ["2", "up"]);
+}
+
+#[test]
+fn sections_rebind_keys() {
+    let text = "
+        [keymap]
+        preset = azerty
+        5 = Z space
+
+        [keymap pong]
+        1 = up
+        4 = down
+    ";
+
+    let keymap = from_config(text, None).unwrap();
+    assert_eq!(keymap.hosts(0x5), ["z", "space"]);
+    assert_eq!(keymap.hosts(0x1), ["1"]);
+
+    // The ROM's section applies on top
+    let keymap = from_config(text, Some("pong")).unwrap();
+    assert_eq!(keymap.hosts(0x1), ["up"]);
+    assert_eq!(keymap.hosts(0x4), ["down"]);
+    assert_eq!(keymap.hosts(0x5), ["z", "space"]);
+
+    // Swapping two keys around is fine
+    let keymap = from_config("[keymap]\n4 = w\n5 = q", None).unwrap();
+    assert_eq!(keymap.hosts(0x4), ["w"]);
+    assert_eq!(keymap.hosts(0x5), ["q"]);
+
+    // So is a ROM section binding a key again
+    assert!(from_config("[keymap]\n5 = space\n[keymap pong]\n5 = up", Some("pong")).is_ok());
+}
+
+#[test]
+fn unknown_names() {
+    let error = from_config("[keymap]\n5 = w banana", None).unwrap_err();
+    assert_eq!(error, "line 2: unknown host key 'banana'");
+
+    let error = from_config("[keymap]\n\nG = w", None).unwrap_err();
+    assert_eq!(error, "line 3: 'g' isn't a CHIP-8 key, expected 0-9 or A-F");
+
+    let error = from_config("[keymap]\n10 = w", None).unwrap_err();
+    assert!(error.starts_with("line 2: '10' isn't a CHIP-8 key"), "{}", error);
+
+    let error = from_config("[keymap]\npreset = colemak", None).unwrap_err();
+    assert!(error.starts_with("line 2: unknown keymap preset 'colemak'"), "{}", error);
+}
+
+#[test]
+fn chip8_key_bound_twice_in_a_section() {
+    let error = from_config("[keymap]\n5 = w\n6 = e\n5 = space", None).unwrap_err();
+    assert_eq!(error, "line 4: CHIP-8 key 5 is already bound on line 2");
+
+    // Hex digits in either case are the same key
+    let error = from_config("[keymap]\na = up\nA = down", None).unwrap_err();
+    assert_eq!(error, "line 3: CHIP-8 key A is already bound on line 2");
+}
+
+#[test]
+fn host_key_on_two_chip8_keys() {
+    // Q stays on 4 from the preset
+    let error = from_config("[keymap]\n5 = q", None).unwrap_err();
+    assert_eq!(error, "line 2: host key 'q' is bound to both CHIP-8 key 4 and 5");
+
+    let error = from_config("[keymap]\npreset = arrows\n[keymap pong]\n1 = up", Some("pong")).unwrap_err();
+    assert_eq!(error, "line 4: host key 'up' is bound to both CHIP-8 key 1 and 2");
+
+    // Only the sections for the ROM that runs count
+    assert!(from_config("[keymap]\npreset = arrows\n[keymap pong]\n1 = up", Some("tetris")).is_ok());
+
+    let mut keymap = Keymap::empty();
+    keymap.bind(0x1, &["space"]).unwrap();
+    keymap.bind(0x2, &["space"]).unwrap();
+    let error = Keymap::from_config(keymap, &Config::default(), None).unwrap_err();
+    assert_eq!(error, "host key 'space' is bound to both CHIP-8 key 1 and 2");
+}
+
+#[test]
+fn malformed_lines() {
+    assert_eq!(from_config("[keymap]\n5 w", None).unwrap_err(), "line 2: expected name = value");
+    assert_eq!(from_config("5 = w", None).unwrap_err(), "line 1: setting outside of a section");
+    assert_eq!(from_config("[keymap\n5 = w", None).unwrap_err(), "line 1: section header is missing its ]");
+    assert_eq!(from_config("[keymap a b]", None).unwrap_err(), "line 1: expected [section] or [section rom]");
+
+    // Comments are fine
+    let keymap = from_config("# keys\n[keymap]\n5 = space # jump", None).unwrap();
+    assert_eq!(keymap.hosts(0x5), ["space"]);
+}
+
+#[test]
+fn bind_checks_its_arguments() {
+    let mut keymap = Keymap::empty();
+    assert_eq!(keymap.bind(16, &["a"]), Err(String::from("there is no CHIP-8 key 10")));
+    assert_eq!(keymap.bind(0, &["F13"]), Err(String::from("unknown host key 'F13'")));
+    assert_eq!(keymap, Keymap::empty());
+
+    keymap.bind(0xA, &["Up", "numpad5"]).unwrap();
+    assert_eq!(keymap.bindings().collect::<Vec<_>>(), [("up", 0xA), ("numpad5", 0xA)]);
+}
@@ -0,0 +1,165 @@
+//! Movies have to survive being saved and notice when playback goes
+//! somewhere the recording didn't.
+
+use chip8::{asm::assemble, Chip8Machine, Movie, Platform, Quirks, RngKind};
+
+/// Counts the frames key 5 is held in v2
+const KEY_COUNTER: &str = "
+    v1 := 5
+    : main
+    if v1 key then v2 += 1
+    jump main
+";
+
+fn keys(held: &[usize]) -> [bool; 16] {
+    std::array::from_fn(|key| held.contains(&key))
+}
+
+fn machine(rom: &[u8]) -> Chip8Machine {
+    let mut machine = Chip8Machine::new();
+    machine.load_rom(rom).unwrap();
+    machine.seed_rng(RngKind::Lcg, 99);
+    machine
+}
+
+/// Run `machine` for `frames` frames, recording the keys `input` holds on
+/// each one into `movie`, or playing them back from it when `input` is None
+fn run(machine: &mut Chip8Machine, movie: &mut Movie, frames: u64, input: Option<&dyn Fn(u64) -> [bool; 16]>) {
+    for _ in 0..frames {
+        let frame = machine.frame();
+        machine.keys = match input {
+            Some(input) => {
+                let keys = input(frame);
+                movie.record(frame, keys);
+                keys
+            },
+            None => movie.keys_at(frame)
+        };
+        machine.run_frame().unwrap();
+    }
+}
+
+#[test]
+fn text_round_trip() {
+    let rom = assemble(KEY_COUNTER, Platform::Chip8).unwrap().bytes;
+    let mut machine = machine(&rom);
+    machine.set_platform(Platform::SuperChip);
+    machine.set_quirks(Quirks::vip());
+    machine.set_clock(1000);
+
+    let mut movie = Movie::new(&rom, &machine, RngKind::Lcg, 99);
+    let input = |frame: u64| if (10..20).contains(&frame) { keys(&[5]) } else if frame >= 30 { keys(&[0, 15]) } else { keys(&[]) };
+    run(&mut machine, &mut movie, 40, Some(&input));
+    movie.finish(&machine);
+
+    assert_eq!(movie.inputs, vec![(10, keys(&[5])), (20, keys(&[])), (30, keys(&[0, 15]))]);
+    assert_eq!(Movie::from_text(&movie.to_text()), Ok(movie.clone()));
+
+    // Without an end line too
+    movie.end_hash = None;
+    assert_eq!(Movie::from_text(&movie.to_text()), Ok(movie));
+}
+
+#[test]
+fn recording_after_a_rewind_replaces_what_came_after() {
+    let mut movie = Movie::new(&[], &Chip8Machine::new(), RngKind::Lcg, 0);
+    movie.record(0, keys(&[]));
+    movie.record(5, keys(&[1]));
+    movie.record(6, keys(&[1]));
+    movie.record(10, keys(&[2]));
+    movie.record(15, keys(&[]));
+    assert_eq!(movie.inputs, vec![(5, keys(&[1])), (10, keys(&[2])), (15, keys(&[]))]);
+
+    // Back to frame 8, where key 1 is still held, and let go of it
+    movie.record(8, keys(&[]));
+    assert_eq!(movie.inputs, vec![(5, keys(&[1])), (8, keys(&[]))]);
+    assert_eq!(movie.keys_at(7), keys(&[1]));
+    assert_eq!(movie.keys_at(20), keys(&[]));
+
+    // Back to exactly the frame a change was recorded on
+    movie.record(5, keys(&[3]));
+    assert_eq!(movie.inputs, vec![(5, keys(&[3]))]);
+}
+
+#[test]
+fn playback_reports_a_desync() {
+    let rom = assemble(KEY_COUNTER, Platform::Chip8).unwrap().bytes;
+    let mut recorded = machine(&rom);
+    let mut movie = Movie::new(&rom, &recorded, RngKind::Lcg, 99);
+    let input = |frame: u64| if (3..8).contains(&frame) { keys(&[5]) } else { keys(&[]) };
+    run(&mut recorded, &mut movie, 20, Some(&input));
+    movie.finish(&recorded);
+
+    // Played back as recorded
+    let mut replayed = machine(&rom);
+    assert!(movie.mismatches(&rom, &replayed, RngKind::Lcg, 99).is_empty());
+    run(&mut replayed, &mut movie, 20, None);
+    assert_eq!(movie.check_end(&replayed), Ok(()));
+    assert_eq!(replayed.cpu.registers, recorded.cpu.registers);
+
+    // Played back with a key held too long
+    let mut desynced = machine(&rom);
+    let mut changed = movie.clone();
+    changed.inputs[1].0 += 1;
+    run(&mut desynced, &mut changed, 20, None);
+    let error = changed.check_end(&desynced).unwrap_err();
+    assert!(error.contains("Desync") && error.contains("frame 20"), "{}", error);
+
+    // A different ROM is caught before running
+    let mut other = rom.clone();
+    other[1] = 6;
+    assert_eq!(movie.mismatches(&other, &machine(&other), RngKind::Lcg, 99).len(), 1);
+}
+
+/// Shifts V1 into V0 under the VIP quirks, and V0 in place otherwise
+const SHIFT_QUIRK: [u8; 8] = [0x61, 0x06, 0x80, 0x16, 0x12, 0x04, 0x00, 0x00];
+
+#[test]
+fn movie_keeps_its_quirks() {
+    let mut recorded = machine(&SHIFT_QUIRK);
+    recorded.set_quirks(Quirks::vip());
+    let mut movie = Movie::new(&SHIFT_QUIRK, &recorded, RngKind::Lcg, 99);
+    run(&mut recorded, &mut movie, 10, Some(&|_| keys(&[])));
+    movie.finish(&recorded);
+    assert_eq!(recorded.cpu.registers[0], 3);
+
+    let movie = Movie::from_text(&movie.to_text()).unwrap();
+    let mut replayed = Chip8Machine::new();
+    movie.configure(&mut replayed);
+    replayed.load_rom(&SHIFT_QUIRK).unwrap();
+    assert!(movie.mismatches(&SHIFT_QUIRK, &replayed, RngKind::Lcg, 99).is_empty());
+    run(&mut replayed, &mut movie.clone(), 10, None);
+    assert_eq!(movie.check_end(&replayed), Ok(()));
+}
+
+/// The frontend plays a movie with the quirks it was recorded with, and
+/// refuses options that would change them
+#[cfg(feature = "minifb")]
+#[test]
+fn frontend_plays_a_movie_with_its_quirks() {
+    use std::{fs, process::Command};
+
+    let directory = std::env::temp_dir().join(format!("chip8-movie-test-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let rom = directory.join("shift.ch8");
+    let movie = directory.join("shift.c8m");
+    fs::write(&rom, SHIFT_QUIRK).unwrap();
+
+    let chip8 = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_chip-8")).args(args).arg(&rom).output().unwrap();
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    chip8(&["--headless", "10", "--quirks", "vip", "--seed", "1", "--record-movie", movie.to_str().unwrap()]);
+    assert!(fs::read_to_string(&movie).unwrap().contains("shift_uses_vy=1"));
+
+    let played = chip8(&["--headless", "10", "--play-movie", movie.to_str().unwrap()]);
+    assert!(played.contains("in sync") && !played.contains("desync"), "{}", played);
+
+    for option in [["--platform", "chip8"], ["--quirks", "modern"], ["--clock", "600"], ["--seed", "1"]] {
+        let refused = chip8(&[option[0], option[1], "--headless", "10", "--play-movie", movie.to_str().unwrap()]);
+        assert!(refused.contains("can't be used with --play-movie"), "{}", refused);
+    }
+
+    fs::remove_dir_all(&directory).unwrap();
+}
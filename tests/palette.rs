@@ -0,0 +1,100 @@
+//! Palette presets, colors from the config file and which color each
+//! combination of XO-CHIP planes gets.
+
+use chip8::{
+    config::Config,
+    render::{parse_color, PALETTE_COLORS, PALETTE_PRESETS},
+    Display, Palette, Renderer
+};
+
+fn from_config(text: &str) -> Result<Palette, String> {
+    Palette::from_config(Palette::default(), &Config::parse(text)?, None)
+}
+
+#[test]
+fn presets() {
+    for name in PALETTE_PRESETS {
+        assert!(Palette::preset(name).is_some(), "{}", name);
+        assert_eq!(Palette::preset(&name.to_ascii_uppercase()), Palette::preset(name));
+    }
+    assert_eq!(Palette::preset("default"), Some(Palette::default()));
+    assert_eq!(Palette::preset("octo"), Some(Palette::octo()));
+    assert_eq!(Palette::preset("lcd"), Some(Palette::lcd()));
+    assert_eq!(Palette::preset("hotdog"), Some(Palette::hotdog()));
+    assert_eq!(Palette::preset("grey"), Some(Palette::gray()));
+    assert_eq!(Palette::preset("solarized"), None);
+}
+
+#[test]
+fn parse_colors() {
+    assert_eq!(parse_color("#FF8000"), Some(0x00FF8000));
+    assert_eq!(parse_color("#ff8000"), Some(0x00FF8000));
+    assert_eq!(parse_color("0x123456"), Some(0x00123456));
+    assert_eq!(parse_color("000000"), Some(0));
+
+    for invalid in ["", "#", "#FFF", "#FF80000", "#GG0000", "red", "#-12345", "+12345", "0x12345"] {
+        assert_eq!(parse_color(invalid), None, "{}", invalid);
+    }
+}
+
+#[test]
+fn set_colors_by_name() {
+    let mut palette = Palette::default();
+    for (index, name) in PALETTE_COLORS.iter().enumerate() {
+        palette.set(name, &format!("#00000{}", index)).unwrap();
+    }
+    assert_eq!(palette, Palette { background: 0, foreground: 1, plane2: 2, overlap: 3, buzzer: 4, silence: 5 });
+
+    assert_eq!(palette.set("Foreground", "#ABCDEF"), Ok(()));
+    assert_eq!(palette.foreground, 0x00ABCDEF);
+
+    let error = palette.set("foreground", "#ABC").unwrap_err();
+    assert_eq!(error, "'#ABC' isn't a color, expected #RRGGBB");
+    let error = palette.set("border", "#ABCDEF").unwrap_err();
+    assert!(error.starts_with("unknown color 'border'"), "{}", error);
+    assert_eq!(palette.foreground, 0x00ABCDEF);
+}
+
+#[test]
+fn config_sections() {
+    let palette = from_config("[palette]\npreset = lcd\nbuzzer = #FF0000").unwrap();
+    assert_eq!(palette, Palette { buzzer: 0x00FF0000, ..Palette::lcd() });
+
+    // A ROM's section on top of the general one
+    let config = Config::parse("[palette]\npreset = octo\n[palette pong]\nforeground = #FFFFFF").unwrap();
+    assert_eq!(Palette::from_config(Palette::default(), &config, Some("tetris")), Ok(Palette::octo()));
+    let pong = Palette::from_config(Palette::default(), &config, Some("pong")).unwrap();
+    assert_eq!(pong, Palette { foreground: 0x00FFFFFF, ..Palette::octo() });
+
+    assert_eq!(from_config("[palette]\n\nbackground = #12345G").unwrap_err(), "line 3: '#12345G' isn't a color, expected #RRGGBB");
+    assert!(from_config("[palette]\npreset = neon").unwrap_err().starts_with("line 2: unknown palette 'neon'"));
+    assert!(from_config("[palette]\nplane3 = #000000").unwrap_err().starts_with("line 2: unknown color 'plane3'"));
+}
+
+#[test]
+fn colors_for_planes() {
+    let palette = Palette::hotdog();
+    assert_eq!(palette.color(0b00), palette.background);
+    assert_eq!(palette.color(0b01), palette.foreground);
+    assert_eq!(palette.color(0b10), palette.plane2);
+    assert_eq!(palette.color(0b11), palette.overlap);
+
+    // Only the two planes count
+    assert_eq!(palette.color(0b101), palette.foreground);
+
+    assert_eq!(palette.indicator(true), palette.buzzer);
+    assert_eq!(palette.indicator(false), palette.silence);
+}
+
+#[test]
+fn renderer_colors_each_plane() {
+    // Pixel x gets plane bits x
+    let mut pixels = vec![0; 64 * 32];
+    pixels[..4].copy_from_slice(&[0b00, 0b01, 0b10, 0b11]);
+    let display = Display::from_parts(64, 32, false, 0b11, pixels).unwrap();
+
+    let palette = Palette::octo();
+    let mut renderer = Renderer::new(1, palette);
+    let buffer = renderer.render(&display);
+    assert_eq!(buffer[..4], [palette.background, palette.foreground, palette.plane2, palette.overlap]);
+}
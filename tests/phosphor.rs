@@ -0,0 +1,112 @@
+//! Phosphor filters: how a dark pixel fades out or blends over the frames,
+//! and frame counts out of range.
+
+use chip8::{
+    phosphor::{Phosphor, PhosphorMode, MAX_PHOSPHOR_FRAMES},
+    Display, Palette
+};
+
+/// Red on black, so the red channel shows how much glow is left
+fn palette() -> Palette {
+    Palette { background: 0x00000000, foreground: 0x00FF0000, ..Palette::default() }
+}
+
+/// Red channel of pixel (0, 0) for each frame of `lit`, where the pixel is
+/// on or off
+fn red_over_frames(mode: PhosphorMode, lit: &[bool]) -> Vec<u32> {
+    let mut phosphor = Phosphor::new(mode);
+    let mut display = Display::new();
+    lit.iter().map(|on| {
+        display.set_pixel(0, 0, *on);
+        phosphor.apply(&display, &palette())[0] >> 16
+    }).collect()
+}
+
+/// On for one frame, then off for `dark` frames
+fn flash(dark: usize) -> Vec<bool> {
+    let mut lit = vec![true];
+    lit.resize(dark + 1, false);
+    lit
+}
+
+#[test]
+fn parse_modes() {
+    assert_eq!(PhosphorMode::parse("off"), Some(PhosphorMode::Off));
+    assert_eq!(PhosphorMode::parse("fade"), Some(PhosphorMode::Fade(4)));
+    assert_eq!(PhosphorMode::parse("Blend"), Some(PhosphorMode::Blend(2)));
+    assert_eq!(PhosphorMode::parse("fade:8"), Some(PhosphorMode::Fade(8)));
+    assert_eq!(PhosphorMode::parse("blend:60"), Some(PhosphorMode::Blend(60)));
+
+    for invalid in ["", "glow", "off:2", "fade:0", "fade:61", "fade:-1", "fade:", "blend:x", "fade:4294967296"] {
+        assert_eq!(PhosphorMode::parse(invalid), None, "{}", invalid);
+    }
+}
+
+#[test]
+fn off_shows_the_display() {
+    assert_eq!(red_over_frames(PhosphorMode::Off, &flash(2)), [255, 0, 0]);
+}
+
+#[test]
+fn fade_over_n_frames() {
+    // A fifth less each frame, gone on the fifth
+    assert_eq!(red_over_frames(PhosphorMode::Fade(4), &flash(6)), [255, 204, 153, 102, 51, 0, 0]);
+    assert_eq!(red_over_frames(PhosphorMode::Fade(1), &flash(3)), [255, 127, 0, 0]);
+
+    // Lighting it again mid fade starts over
+    let lit = [true, false, false, true, false];
+    assert_eq!(red_over_frames(PhosphorMode::Fade(4), &lit), [255, 204, 153, 255, 204]);
+
+    // Pixels that were never lit don't glow
+    let mut phosphor = Phosphor::new(PhosphorMode::Fade(4));
+    assert!(phosphor.apply(&Display::new(), &palette()).iter().all(|color| *color == 0));
+}
+
+#[test]
+fn fade_keeps_the_planes_color() {
+    let palette = Palette { background: 0, plane2: 0x000000FF, ..palette() };
+    let mut phosphor = Phosphor::new(PhosphorMode::Fade(1));
+    let mut display = Display::new();
+    display.select_planes(0b10);
+    display.set_pixel(0, 0, true);
+    phosphor.apply(&display, &palette);
+    display.clear();
+    assert_eq!(phosphor.apply(&display, &palette)[0], 0x0000007F);
+}
+
+#[test]
+fn blend_averages_the_last_frames() {
+    assert_eq!(red_over_frames(PhosphorMode::Blend(2), &flash(3)), [255, 127, 0, 0]);
+    assert_eq!(red_over_frames(PhosphorMode::Blend(3), &[true, false, true, true, true]), [255, 127, 170, 170, 255]);
+}
+
+#[test]
+fn frame_counts_out_of_range() {
+    // 0 frames: nothing is left over
+    assert_eq!(red_over_frames(PhosphorMode::Fade(0), &flash(2)), [255, 0, 0]);
+    assert_eq!(red_over_frames(PhosphorMode::Blend(0), &flash(2)), [255, 0, 0]);
+
+    // Huge counts are held to a second of frames
+    let faded = red_over_frames(PhosphorMode::Fade(u32::MAX), &flash(MAX_PHOSPHOR_FRAMES as usize + 1));
+    assert!(faded[MAX_PHOSPHOR_FRAMES as usize] > 0);
+    assert_eq!(faded[MAX_PHOSPHOR_FRAMES as usize + 1], 0);
+
+    let mut lit = vec![true; 100];
+    lit.resize(100 + MAX_PHOSPHOR_FRAMES as usize, false);
+    let blended = red_over_frames(PhosphorMode::Blend(u32::MAX), &lit);
+    assert!(blended[100 + MAX_PHOSPHOR_FRAMES as usize - 2] > 0);
+    assert_eq!(blended[100 + MAX_PHOSPHOR_FRAMES as usize - 1], 0);
+}
+
+#[test]
+fn switching_resolution_starts_over() {
+    let mut phosphor = Phosphor::new(PhosphorMode::Fade(4));
+    let mut display = Display::new();
+    display.set_pixel(0, 0, true);
+    phosphor.apply(&display, &palette());
+
+    display.set_hires(true);
+    let colors = phosphor.apply(&display, &palette());
+    assert_eq!(colors.len(), 128 * 64);
+    assert!(colors.iter().all(|color| *color == 0));
+}
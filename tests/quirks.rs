@@ -0,0 +1,107 @@
+//! One small program per quirk, run under every preset.
+
+use chip8::{Chip8Machine, Quirks};
+
+const PRESETS: [&str; 4] = ["vip", "schip", "xochip", "modern"];
+
+/// Run `program` for `count` instructions with the quirks of `preset`
+fn run(preset: &str, program: &[u8], count: usize) -> Chip8Machine {
+    let mut machine = Chip8Machine::new();
+    machine.set_quirks(Quirks::from_name(preset).unwrap());
+    machine.set_clock(600);
+    machine.load_rom(program).unwrap();
+    for _ in 0..count {
+        machine.tick().unwrap();
+    }
+    machine
+}
+
+#[test]
+fn presets_by_name() {
+    assert_eq!(Quirks::from_name("VIP"), Some(Quirks::vip()));
+    assert_eq!(Quirks::from_name("super-chip"), Some(Quirks::schip()));
+    assert_eq!(Quirks::from_name("xo-chip"), Some(Quirks::xochip()));
+    assert_eq!(Quirks::from_name("modern"), Some(Quirks::default()));
+    assert_eq!(Quirks::from_name("chip8"), None);
+}
+
+#[test]
+fn vf_reset() {
+    for preset in PRESETS {
+        let resets = Quirks::from_name(preset).unwrap().logic_resets_vf;
+        // VF := 5, V1 := 3, V2 := 5, then OR, AND or XOR V1 with V2
+        for op in [0x21, 0x22, 0x23] {
+            let machine = run(preset, &[0x6F, 0x05, 0x61, 0x03, 0x62, 0x05, 0x81, op], 4);
+            assert_eq!(machine.cpu.registers[0xF], if resets { 0 } else { 5 }, "{} 812{:X}", preset, op & 0xF);
+        }
+    }
+}
+
+#[test]
+fn memory_increment() {
+    for preset in PRESETS {
+        let increments = Quirks::from_name(preset).unwrap().load_store_increments_i;
+        let expected = if increments { 0x302 } else { 0x300 };
+
+        // I := 0x300, then store or load V0-V1
+        for op in [0x55, 0x65] {
+            let machine = run(preset, &[0xA3, 0x00, 0xF1, op], 2);
+            assert_eq!(machine.cpu.i, expected, "{} F1{:02X}", preset, op);
+        }
+    }
+}
+
+#[test]
+fn shifting() {
+    for preset in PRESETS {
+        let uses_vy = Quirks::from_name(preset).unwrap().shift_uses_vy;
+
+        // V0 := 0x10, V1 := 6, then shift right or left into V0
+        let machine = run(preset, &[0x60, 0x10, 0x61, 0x06, 0x80, 0x16], 3);
+        assert_eq!(machine.cpu.registers[0], if uses_vy { 0x03 } else { 0x08 }, "{} 8016", preset);
+
+        let machine = run(preset, &[0x60, 0x10, 0x61, 0x06, 0x80, 0x1E], 3);
+        assert_eq!(machine.cpu.registers[0], if uses_vy { 0x0C } else { 0x20 }, "{} 801E", preset);
+    }
+}
+
+#[test]
+fn jumping() {
+    for preset in PRESETS {
+        let uses_vx = Quirks::from_name(preset).unwrap().jump_uses_vx;
+
+        // V0 := 4, V2 := 0x10, then B204
+        let machine = run(preset, &[0x60, 0x04, 0x62, 0x10, 0xB2, 0x04], 3);
+        assert_eq!(machine.cpu.pc, if uses_vx { 0x214 } else { 0x208 }, "{}", preset);
+    }
+}
+
+#[test]
+fn clipping() {
+    for preset in PRESETS {
+        let clips = Quirks::from_name(preset).unwrap().clip_sprites;
+
+        // Draw a row of 8 pixels at (60, 0): the last 4 go past the edge
+        let machine = run(preset, &[0x60, 0x3C, 0x61, 0x00, 0xA2, 0x08, 0xD0, 0x11, 0xFF], 4);
+        assert!(machine.display.get_pixel(63, 0), "{}", preset);
+        assert_eq!(machine.display.get_pixel(0, 0), !clips, "{}", preset);
+    }
+}
+
+#[test]
+fn display_wait() {
+    // I := sprite, draw it, V0 += 1, then loop
+    let program = [0xA2, 0x08, 0xD0, 0x01, 0x70, 0x01, 0x12, 0x06, 0x80];
+
+    for preset in PRESETS {
+        let waits = Quirks::from_name(preset).unwrap().wait_for_vblank;
+
+        // The instruction after the draw only runs once the frame is over
+        let mut machine = run(preset, &program, 3);
+        assert_eq!(machine.cpu.registers[0], if waits { 0 } else { 1 }, "{}", preset);
+
+        while !machine.tick().unwrap() {}
+        machine.tick().unwrap();
+        assert_eq!(machine.cpu.registers[0], 1, "{}", preset);
+    }
+}
@@ -0,0 +1,127 @@
+//! How a Renderer fits the display into a window: the scale it picks, where
+//! it puts the display and what happens when the window is too small.
+
+use chip8::{render::Scaling, Display, Palette, Renderer};
+
+/// Render `display` into a `width`x`height` frame and return the renderer
+fn render(display: &Display, scaling: Scaling, width: usize, height: usize) -> Renderer {
+    let mut renderer = Renderer::new(1, Palette::default());
+    renderer.set_scaling(scaling);
+    renderer.set_frame(Some((width, height)));
+    renderer.render(display);
+    renderer
+}
+
+/// Left, top, right and bottom edges (exclusive) of the display in the frame
+fn placement(renderer: &Renderer) -> (usize, usize, usize, usize) {
+    let border = Palette::default().silence;
+    let buffer = renderer.buffer();
+    let inside: Vec<(usize, usize)> = (0..renderer.height())
+                                        .flat_map(|y| (0..renderer.width()).map(move |x| (x, y)))
+                                        .filter(|(x, y)| buffer[y * renderer.width() + x] != border)
+                                        .collect();
+    let left = inside.iter().map(|(x, _)| *x).min().unwrap();
+    let top = inside.iter().map(|(_, y)| *y).min().unwrap();
+    let right = inside.iter().map(|(x, _)| *x).max().unwrap() + 1;
+    let bottom = inside.iter().map(|(_, y)| *y).max().unwrap() + 1;
+    (left, top, right, bottom)
+}
+
+#[test]
+fn fixed_scale_without_a_frame() {
+    let mut renderer = Renderer::new(3, Palette::default());
+    renderer.render(&Display::new());
+    assert_eq!((renderer.width(), renderer.height(), renderer.scale()), (192, 96, 3));
+    assert_eq!(renderer.buffer().len(), 192 * 96);
+
+    // Scale 0 is 1
+    let mut renderer = Renderer::new(0, Palette::default());
+    renderer.render(&Display::new());
+    assert_eq!((renderer.width(), renderer.height()), (64, 32));
+}
+
+#[test]
+fn integer_scaling() {
+    let display = Display::new();
+
+    // Exactly 16 times the display
+    let renderer = render(&display, Scaling::Integer, 1024, 512);
+    assert_eq!(renderer.scale(), 16);
+    assert_eq!(placement(&renderer), (0, 0, 1024, 512));
+
+    // 720 high leaves bars above and below
+    let renderer = render(&display, Scaling::Integer, 1280, 720);
+    assert_eq!(renderer.scale(), 20);
+    assert_eq!(placement(&renderer), (0, 40, 1280, 680));
+
+    // 1000 isn't a multiple of 64, so bars on all sides
+    let renderer = render(&display, Scaling::Integer, 1000, 700);
+    assert_eq!(renderer.scale(), 15);
+    assert_eq!(placement(&renderer), (20, 110, 980, 590));
+
+    // A tall window is limited by its width
+    let renderer = render(&display, Scaling::Integer, 300, 1000);
+    assert_eq!(renderer.scale(), 4);
+    assert_eq!(placement(&renderer), (22, 436, 278, 564));
+}
+
+#[test]
+fn fit_scaling() {
+    let display = Display::new();
+
+    // Fills the width, though that's 15.6 host pixels per CHIP-8 pixel
+    let renderer = render(&display, Scaling::Fit, 1000, 700);
+    assert_eq!(renderer.scale(), 15);
+    assert_eq!(placement(&renderer), (0, 100, 1000, 600));
+
+    // Limited by the height, keeping the 2:1 shape
+    let renderer = render(&display, Scaling::Fit, 1000, 301);
+    assert_eq!(placement(&renderer), (199, 0, 801, 301));
+
+    // The last CHIP-8 pixel still reaches the edge
+    let mut display = Display::new();
+    display.set_pixel(63, 31, true);
+    let renderer = render(&display, Scaling::Fit, 1000, 500);
+    let buffer = renderer.buffer();
+    assert_eq!(buffer[499 * 1000 + 999], Palette::default().foreground);
+    assert_eq!(buffer[499 * 1000 + 984], Palette::default().background);
+}
+
+#[test]
+fn hires_display() {
+    let mut display = Display::new();
+    display.set_hires(true);
+
+    let renderer = render(&display, Scaling::Integer, 1280, 720);
+    assert_eq!(renderer.scale(), 10);
+    assert_eq!(placement(&renderer), (0, 40, 1280, 680));
+}
+
+#[test]
+fn margin() {
+    let mut renderer = Renderer::new(1, Palette::default());
+    renderer.set_frame(Some((1024, 512)));
+    renderer.set_margin(10);
+    renderer.render(&Display::new());
+    assert_eq!(renderer.scale(), 15);
+    assert_eq!(placement(&renderer), (32, 16, 992, 496));
+}
+
+#[test]
+fn windows_smaller_than_the_display() {
+    for scaling in [Scaling::Integer, Scaling::Fit] {
+        // The display stays at 1 host pixel per pixel and is cut off
+        let renderer = render(&Display::new(), scaling, 40, 20);
+        assert_eq!((renderer.width(), renderer.height()), (40, 20), "{:?}", scaling);
+        assert_eq!(renderer.scale(), 1);
+        assert_eq!(placement(&renderer), (0, 0, 40, 20));
+
+        // Smaller one way only
+        let renderer = render(&Display::new(), scaling, 100, 20);
+        assert_eq!(placement(&renderer), (18, 0, 82, 20), "{:?}", scaling);
+
+        // Nothing at all
+        let renderer = render(&Display::new(), scaling, 0, 0);
+        assert!(renderer.buffer().is_empty());
+    }
+}
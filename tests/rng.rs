@@ -0,0 +1,67 @@
+//! CXNN: a seeded generator gives the same numbers every run, masked by NN.
+
+use chip8::{Chip8Machine, Rng, RngKind};
+
+/// V0 := random & `mask` over and over, returning each value
+fn cxnn(kind: RngKind, seed: u64, mask: u8, count: usize) -> Vec<u8> {
+    let mut machine = Chip8Machine::new();
+    machine.load_rom(&[0xC0, mask, 0x12, 0x00]).unwrap();
+    machine.seed_rng(kind, seed);
+
+    let mut values = Vec::new();
+    for _ in 0..count {
+        machine.tick().unwrap();
+        values.push(machine.cpu.registers[0]);
+        machine.tick().unwrap();
+    }
+    values
+}
+
+#[test]
+fn names() {
+    assert_eq!(RngKind::from_name("XorShift"), Some(RngKind::XorShift));
+    assert_eq!(RngKind::from_name("lcg"), Some(RngKind::Lcg));
+    assert_eq!(RngKind::from_name("mt19937"), None);
+    for kind in [RngKind::XorShift, RngKind::Lcg] {
+        assert_eq!(RngKind::from_name(kind.name()), Some(kind));
+    }
+}
+
+#[test]
+fn same_seed_same_sequence() {
+    for kind in [RngKind::XorShift, RngKind::Lcg] {
+        let first = cxnn(kind, 1234, 0xFF, 64);
+        assert_eq!(cxnn(kind, 1234, 0xFF, 64), first, "{:?}", kind);
+        assert_ne!(cxnn(kind, 1235, 0xFF, 64), first, "{:?}", kind);
+
+        // Exactly the generator's bytes
+        let mut rng = Rng::new(kind, 1234);
+        let expected: Vec<u8> = (0..64).map(|_| rng.next_byte()).collect();
+        assert_eq!(first, expected, "{:?}", kind);
+
+        // And not stuck on a few values
+        let mut distinct = first.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() > 32, "{:?} {:?}", kind, first);
+    }
+
+    // Seed 0 still gives a working generator
+    assert!(cxnn(RngKind::XorShift, 0, 0xFF, 16).iter().any(|value| *value != 0));
+}
+
+#[test]
+fn cxnn_masks_with_nn() {
+    for kind in [RngKind::XorShift, RngKind::Lcg] {
+        let unmasked = cxnn(kind, 7, 0xFF, 256);
+        for mask in [0x00, 0x01, 0x0F, 0xA5, 0xF0] {
+            let masked = cxnn(kind, 7, mask, 256);
+            let expected: Vec<u8> = unmasked.iter().map(|value| value & mask).collect();
+            assert_eq!(masked, expected, "{:?} mask {:#04X}", kind, mask);
+        }
+
+        // Every bit the mask allows shows up
+        let bits = cxnn(kind, 7, 0x0F, 256).iter().fold(0, |bits, value| bits | value);
+        assert_eq!(bits, 0x0F, "{:?}", kind);
+    }
+}
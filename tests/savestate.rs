@@ -0,0 +1,95 @@
+//! Save states have to survive the trip through bytes and put a machine back
+//! exactly where it was.
+
+use chip8::{asm::assemble, savestate::SaveState, Chip8Machine, Platform, RngKind};
+
+/// Draws random sprites at random places and beeps now and then, so RNG,
+/// display, timers and memory all change from frame to frame
+const BUSY_PROGRAM: &str = "
+    : main
+    v0 := random 0x3F
+    v1 := random 0x1F
+    v2 := random 0x0F
+    i := hex v2
+    sprite v0 v1 5
+    i := counter
+    load v3
+    v3 += 1
+    save v3
+    buzzer := v2
+    jump main
+    : counter
+    0
+";
+
+fn busy_machine() -> Chip8Machine {
+    let program = assemble(BUSY_PROGRAM, Platform::Chip8).unwrap();
+    let mut machine = Chip8Machine::new();
+    machine.load_rom(&program.bytes).unwrap();
+    machine.seed_rng(RngKind::XorShift, 1234);
+    machine
+}
+
+fn run(machine: &mut Chip8Machine, frames: usize) {
+    for _ in 0..frames {
+        machine.run_frame().unwrap();
+    }
+}
+
+#[test]
+fn bytes_round_trip() {
+    let mut machine = busy_machine();
+    run(&mut machine, 30);
+
+    let state = machine.save_state();
+    assert_eq!(SaveState::from_bytes(&state.to_bytes()), Ok(state));
+}
+
+#[test]
+fn rejects_damaged_states() {
+    let mut machine = busy_machine();
+    run(&mut machine, 30);
+    let bytes = machine.save_state().to_bytes();
+
+    let mut magic = bytes.clone();
+    magic[0] = b'X';
+    assert_eq!(SaveState::from_bytes(&magic), Err("Not a save state"));
+
+    let mut version = bytes.clone();
+    version[4] = version[4].wrapping_add(1);
+    assert_eq!(SaveState::from_bytes(&version), Err("Unsupported save state version"));
+
+    // Magic, version and length come before the payload
+    let mut payload = bytes.clone();
+    payload[10] ^= 0xFF;
+    assert_eq!(SaveState::from_bytes(&payload), Err("Save state checksum mismatch"));
+
+    let mut crc = bytes.clone();
+    *crc.last_mut().unwrap() ^= 0xFF;
+    assert_eq!(SaveState::from_bytes(&crc), Err("Save state checksum mismatch"));
+
+    assert!(SaveState::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn loading_replays_the_same_frames() {
+    let mut machine = busy_machine();
+    run(&mut machine, 30);
+
+    let saved = SaveState::from_bytes(&machine.save_state().to_bytes()).unwrap();
+    run(&mut machine, 120);
+    let first = machine.save_state();
+
+    machine.load_state(&saved);
+    assert_eq!(machine.save_state(), saved);
+    run(&mut machine, 120);
+    let second = machine.save_state();
+
+    assert_eq!(second.cpu.ram, first.cpu.ram);
+    assert_eq!(second.cpu.registers, first.cpu.registers);
+    assert_eq!(second.cpu.pc, first.cpu.pc);
+    assert_eq!(second.display, first.display);
+    assert_eq!(second, first);
+    // And the run actually went somewhere
+    assert_ne!(first, saved);
+}
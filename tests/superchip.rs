@@ -0,0 +1,147 @@
+//! SUPER-CHIP instructions, and what DXY0 does on each platform.
+
+use chip8::{cpu::BIG_FONT_START, Chip8Fault, Chip8Machine, Platform};
+
+/// A machine on `platform` with `program` loaded at 0x200
+fn machine(platform: Platform, program: &[u8]) -> Chip8Machine {
+    let mut machine = Chip8Machine::new();
+    machine.set_platform(platform);
+    machine.load_rom(program).unwrap();
+    machine
+}
+
+/// Run `count` instructions
+fn step(machine: &mut Chip8Machine, count: usize) {
+    for _ in 0..count {
+        machine.tick().unwrap();
+    }
+}
+
+fn lit(machine: &Chip8Machine) -> usize {
+    machine.display.pixels().iter().filter(|planes| **planes != 0).count()
+}
+
+/// `prefix`, then I := sprite and D010 with a solid 32 byte sprite after it
+fn big_sprite_program(prefix: &[u8]) -> Vec<u8> {
+    let sprite = 0x200 + prefix.len() as u16 + 4;
+    let mut program = prefix.to_vec();
+    program.extend_from_slice(&(0xA000 | sprite).to_be_bytes());
+    program.extend_from_slice(&[0xD0, 0x10]);
+    program.extend_from_slice(&[0xFF; 32]);
+    program
+}
+
+#[test]
+fn dxy0_draws_nothing_on_chip8() {
+    let mut machine = machine(Platform::Chip8, &big_sprite_program(&[]));
+    step(&mut machine, 2);
+    assert_eq!(machine.halted(), None);
+    assert_eq!(lit(&machine), 0);
+    assert_eq!(machine.cpu.registers[0xF], 0);
+    assert_eq!(machine.cpu.pc, 0x204);
+}
+
+#[test]
+fn dxy0_draws_16x16_on_superchip_and_xochip() {
+    for platform in [Platform::SuperChip, Platform::XoChip] {
+        let mut machine = machine(platform, &big_sprite_program(&[]));
+        step(&mut machine, 2);
+        assert_eq!(lit(&machine), 16 * 16, "{:?}", platform);
+        assert!(machine.display.get_pixel(15, 15));
+        assert!(!machine.display.get_pixel(16, 0));
+
+        // In hires too, and drawing it again collides
+        let mut machine = self::machine(platform, &big_sprite_program(&[0x00, 0xFF]));
+        step(&mut machine, 3);
+        assert_eq!(lit(&machine), 16 * 16, "{:?}", platform);
+        machine.cpu.pc -= 2;
+        step(&mut machine, 1);
+        assert_eq!(lit(&machine), 0);
+        assert_eq!(machine.cpu.registers[0xF], 1);
+    }
+}
+
+#[test]
+fn hires_and_lores() {
+    let mut machine = machine(Platform::SuperChip, &[0x00, 0xFF, 0x00, 0xFE]);
+    machine.display.set_pixel(0, 0, true);
+
+    step(&mut machine, 1);
+    assert!(machine.display.is_hires());
+    assert_eq!((machine.display.width(), machine.display.height()), (128, 64));
+
+    step(&mut machine, 1);
+    assert!(!machine.display.is_hires());
+    assert_eq!((machine.display.width(), machine.display.height()), (64, 32));
+}
+
+#[test]
+fn scrolling() {
+    // 00C3, 00FB, 00FC, 00FC
+    let mut machine = machine(Platform::SuperChip, &[0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC]);
+    machine.display.set_pixel(10, 0, true);
+    machine.display.set_pixel(10, 31, true);
+
+    // Down, blanking the top and dropping what goes off the bottom
+    step(&mut machine, 1);
+    assert_eq!(lit(&machine), 1);
+    assert!(machine.display.get_pixel(10, 3));
+
+    step(&mut machine, 1);
+    assert!(machine.display.get_pixel(14, 3));
+
+    step(&mut machine, 1);
+    assert!(machine.display.get_pixel(10, 3));
+
+    // Off the left edge
+    step(&mut machine, 1);
+    assert!(machine.display.get_pixel(6, 3));
+    machine.cpu.pc -= 2;
+    step(&mut machine, 1);
+    machine.cpu.pc -= 2;
+    step(&mut machine, 1);
+    assert_eq!(lit(&machine), 0);
+}
+
+#[test]
+fn big_font() {
+    // V0 := 9, I := big digit V0
+    let mut machine = machine(Platform::SuperChip, &[0x60, 0x09, 0xF0, 0x30]);
+    step(&mut machine, 2);
+    assert_eq!(machine.cpu.i, BIG_FONT_START + 9 * 10);
+}
+
+#[test]
+fn rpl_flags_round_trip() {
+    // V0-V3 := 1-4, save them to the flags, clear them, load them back
+    let program = [
+        0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04,
+        0xF3, 0x75,
+        0x60, 0x00, 0x61, 0x00, 0x62, 0x00, 0x63, 0x00,
+        0xF3, 0x85
+    ];
+    let mut machine = machine(Platform::SuperChip, &program);
+    step(&mut machine, 5);
+    assert_eq!(machine.cpu.rpl[..4], [1, 2, 3, 4]);
+    step(&mut machine, 4);
+    assert_eq!(machine.cpu.registers[..4], [0, 0, 0, 0]);
+    step(&mut machine, 1);
+    assert_eq!(machine.cpu.registers[..4], [1, 2, 3, 4]);
+}
+
+#[test]
+fn rpl_flag_count_depends_on_the_platform() {
+    // SUPER-CHIP has 8 flags, so V0-V7 fit and V0-V8 don't
+    let mut machine = machine(Platform::SuperChip, &[0xF7, 0x75, 0xF8, 0x75]);
+    step(&mut machine, 1);
+    let fault = Chip8Fault::InvalidOpcode { pc: 0x202, opcode: 0xF875 };
+    assert_eq!(machine.tick(), Err(fault));
+
+    // XO-CHIP has 16
+    let mut machine = self::machine(Platform::XoChip, &[0xFF, 0x75, 0xFF, 0x85]);
+    step(&mut machine, 2);
+
+    // CHIP-8 has none
+    let mut machine = self::machine(Platform::Chip8, &[0xF0, 0x75]);
+    assert!(machine.tick().is_err());
+}
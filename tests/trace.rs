@@ -0,0 +1,105 @@
+//! The tracer writes one line per event for each category it was asked for,
+//! and nothing for the others.
+
+use std::{cell::RefCell, io::{self, Write}, rc::Rc};
+
+use chip8::{Chip8Machine, TraceCategories, Tracer};
+
+/// V0 := 5, DT := V0, I := sprite, draw it at (V0, V0), then loop forever
+const PROGRAM: [u8; 11] = [0x60, 0x05, 0xF0, 0x15, 0xA2, 0x0A, 0xD0, 0x01, 0x12, 0x08, 0x80];
+
+/// A sink the test can still read after handing it to the tracer
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run the program for `count` instructions, one per frame, with key 5 held
+/// from the start, and return the trace lines
+fn trace(categories: &str, count: usize) -> Vec<String> {
+    let sink = Shared::default();
+    let mut machine = Chip8Machine::new();
+    machine.set_clock(60);
+    machine.load_rom(&PROGRAM).unwrap();
+    machine.set_tracer(Some(Tracer::new(TraceCategories::parse(categories).unwrap(), Box::new(sink.clone()))));
+    machine.keys[5] = true;
+    for _ in 0..count {
+        machine.tick().unwrap();
+    }
+
+    let text = String::from_utf8(sink.0.borrow().clone()).unwrap();
+    text.lines().map(String::from).collect()
+}
+
+/// The lines of `category`
+fn category<'a>(lines: &'a [String], category: &str) -> Vec<&'a String> {
+    lines.iter().filter(|line| line.split_whitespace().nth(1) == Some(category)).collect()
+}
+
+#[test]
+fn parse_categories() {
+    let categories = TraceCategories::parse("instructions, draws").unwrap();
+    assert_eq!(categories, TraceCategories { instructions: true, draws: true, ..TraceCategories::default() });
+    assert_eq!(TraceCategories::parse("ins,draw,keys,timers"), Ok(TraceCategories::all()));
+    assert_eq!(TraceCategories::parse("all"), Ok(TraceCategories::all()));
+    assert!(!TraceCategories::parse("").unwrap().any());
+    assert!(TraceCategories::parse("draws,sound").unwrap_err().contains("'sound'"));
+}
+
+#[test]
+fn instructions() {
+    let lines = trace("instructions", 5);
+    assert_eq!(lines.len(), 5);
+    assert_eq!(category(&lines, "INS").len(), 5);
+
+    // Frame, address, opcode and mnemonic, then the registers before it runs
+    let first = lines[0].split_whitespace().collect::<Vec<_>>();
+    assert_eq!(first[..7], ["0", "INS", "0200", "6005", "LD", "V0,", "0x05"]);
+    assert!(lines[0].contains("V0:00 ") && lines[0].ends_with("I:0000 SP:0 DT:00 ST:00"), "{}", lines[0]);
+    assert!(lines[1].starts_with("       1 INS   0202 F015 LD DT, V0") && lines[1].contains("V0:05 "), "{}", lines[1]);
+    assert!(lines[4].contains("0208 1208 JP 0x208"), "{}", lines[4]);
+}
+
+#[test]
+fn draws() {
+    let lines = trace("draws", 6);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("DRAW  (5, 5) rows 1 from 020A"), "{}", lines[0]);
+    assert!(lines[0].contains("VF:00 "), "{}", lines[0]);
+}
+
+#[test]
+fn input() {
+    // Only when the keys change
+    let lines = trace("input", 5);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("KEYS  .....5.........."), "{}", lines[0]);
+}
+
+#[test]
+fn timers() {
+    // Once a frame while a timer is running
+    let lines = trace("timers", 4);
+    let timers = category(&lines, "TIMER");
+    assert_eq!(timers.len(), 3);
+    assert!(timers[0].contains("DT 04 ST 00"), "{}", timers[0]);
+    assert!(timers[2].contains("DT 02 ST 00"), "{}", timers[2]);
+}
+
+#[test]
+fn everything() {
+    let lines = trace("all", 6);
+    for name in ["INS", "DRAW", "KEYS", "TIMER"] {
+        assert!(!category(&lines, name).is_empty(), "no {} in {:#?}", name, lines);
+    }
+    assert_eq!(category(&lines, "INS").len(), 6);
+}
@@ -0,0 +1,51 @@
+//! GIF recordings have 16 bit sizes, anything bigger has to be refused.
+
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc
+};
+
+use chip8::{video::{VideoFormat, VideoRecorder}, Display, Palette};
+
+/// A writer the test can still read after the recorder is done with it
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn gif_header_has_the_scaled_size() {
+    let buffer = SharedBuffer::default();
+    let mut recorder = VideoRecorder::new(Box::new(buffer.clone()), VideoFormat::Gif, 4, Palette::default());
+    recorder.record_frame(&Display::new()).unwrap();
+    recorder.finish().unwrap();
+
+    let bytes = buffer.0.borrow();
+    assert_eq!(&bytes[..6], b"GIF89a");
+    assert_eq!(&bytes[6..10], &[0, 1, 128, 0]);
+    assert_eq!(bytes.last(), Some(&0x3B));
+}
+
+#[test]
+fn gif_too_big_for_16_bits_is_an_error() {
+    // 64 * 1024 is one more than a u16 holds
+    let buffer = SharedBuffer::default();
+    let mut recorder = VideoRecorder::new(Box::new(buffer.clone()), VideoFormat::Gif, 1024, Palette::default());
+    let error = recorder.record_frame(&Display::new()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(buffer.0.borrow().is_empty());
+
+    // A scale so big the size doesn't even fit in a usize
+    let mut recorder = VideoRecorder::new(Box::new(io::sink()), VideoFormat::Ppm, usize::MAX, Palette::default());
+    assert_eq!(recorder.record_frame(&Display::new()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,159 @@
+//! XO-CHIP instructions: bit-planes, long loads, register ranges and audio.
+
+use chip8::{audio::pattern_frequency, Chip8Machine, Platform, Tone, Waveform};
+
+/// An XO-CHIP machine with `program` loaded at 0x200
+fn machine(program: &[u8]) -> Chip8Machine {
+    let mut machine = Chip8Machine::new();
+    machine.set_platform(Platform::XoChip);
+    machine.load_rom(program).unwrap();
+    machine
+}
+
+/// Run `count` instructions
+fn step(machine: &mut Chip8Machine, count: usize) {
+    for _ in 0..count {
+        machine.tick().unwrap();
+    }
+}
+
+/// The planes of the first 8 pixels of the top row
+fn top_row(machine: &Chip8Machine) -> Vec<u8> {
+    (0..8).map(|x| machine.display.get_pixel_planes(x, 0)).collect()
+}
+
+/// V0 := 0, select `planes`, I := sprite, draw one row at (0, 0). The
+/// sprite is F0 for the first plane drawn and 0F for the second.
+fn draw_in(planes: u8) -> Vec<u8> {
+    vec![0x60, 0x00, planes, 0x01, 0xA2, 0x0A, 0xD0, 0x01, 0x12, 0x08, 0xF0, 0x0F]
+}
+
+#[test]
+fn plane_selection() {
+    // Plane 1 is selected to begin with
+    let machine = machine(&[]);
+    assert_eq!(machine.display.selected_planes(), 0b01);
+
+    let mut machine = self::machine(&draw_in(0xF1));
+    step(&mut machine, 4);
+    assert_eq!(top_row(&machine), [1, 1, 1, 1, 0, 0, 0, 0]);
+
+    let mut machine = self::machine(&draw_in(0xF2));
+    step(&mut machine, 4);
+    assert_eq!(top_row(&machine), [2, 2, 2, 2, 0, 0, 0, 0]);
+
+    // Both: the sprite for plane 2 follows the one for plane 1
+    let mut machine = self::machine(&draw_in(0xF3));
+    step(&mut machine, 4);
+    assert_eq!(machine.display.selected_planes(), 0b11);
+    assert_eq!(top_row(&machine), [1, 1, 1, 1, 2, 2, 2, 2]);
+
+    // None: drawing does nothing
+    let mut machine = self::machine(&draw_in(0xF0));
+    step(&mut machine, 4);
+    assert_eq!(top_row(&machine), [0; 8]);
+    assert_eq!(machine.cpu.registers[0xF], 0);
+}
+
+#[test]
+fn clearing_only_touches_the_selected_planes() {
+    // Draw in both planes, then select plane 2 and clear it
+    let program = [
+        0x60, 0x00, 0xF3, 0x01, 0xA2, 0x0E, 0xD0, 0x01,
+        0xF2, 0x01, 0x00, 0xE0, 0x12, 0x0C, 0xF0, 0x0F
+    ];
+
+    let mut machine = machine(&program);
+    step(&mut machine, 4);
+    assert_eq!(top_row(&machine), [1, 1, 1, 1, 2, 2, 2, 2]);
+    step(&mut machine, 2);
+    assert_eq!(top_row(&machine), [1, 1, 1, 1, 0, 0, 0, 0]);
+}
+
+#[test]
+fn long_load() {
+    // I := long 0x1234, V0 := 1
+    let mut machine = machine(&[0xF0, 0x00, 0x12, 0x34, 0x60, 0x01]);
+    step(&mut machine, 1);
+    assert_eq!(machine.cpu.i, 0x1234);
+    assert_eq!(machine.cpu.pc, 0x204);
+    step(&mut machine, 1);
+    assert_eq!(machine.cpu.registers[0], 1);
+}
+
+#[test]
+fn skips_step_over_all_of_a_long_load() {
+    // V0 := 1, a skip that's taken, I := long 0x1234, V2 := 5
+    for skip in [[0x30, 0x01], [0x40, 0x02], [0x50, 0x00], [0x90, 0x10], [0xE0, 0xA1]] {
+        let mut program = vec![0x60, 0x01];
+        program.extend_from_slice(&skip);
+        program.extend_from_slice(&[0xF0, 0x00, 0x12, 0x34, 0x62, 0x05]);
+
+        let mut machine = machine(&program);
+        step(&mut machine, 2);
+        assert_eq!(machine.cpu.pc, 0x208, "{:02X}{:02X}", skip[0], skip[1]);
+        step(&mut machine, 1);
+        assert_eq!(machine.cpu.registers[2], 5);
+        assert_eq!(machine.cpu.i, 0);
+    }
+
+    // Not taken, the long load runs
+    let mut machine = machine(&[0x60, 0x01, 0x30, 0x02, 0xF0, 0x00, 0x12, 0x34]);
+    step(&mut machine, 3);
+    assert_eq!((machine.cpu.i, machine.cpu.pc), (0x1234, 0x208));
+}
+
+#[test]
+fn save_and_load_register_ranges() {
+    // V1-V3 := 1-3, I := 0x300, save V1-V3, then save V3-V1
+    let program = [0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xA3, 0x00, 0x51, 0x32, 0xA3, 0x10, 0x53, 0x12];
+    let mut machine = machine(&program);
+    step(&mut machine, 5);
+    assert_eq!(machine.cpu.ram[0x300..0x304], [1, 2, 3, 0]);
+    assert_eq!(machine.cpu.i, 0x300);
+    step(&mut machine, 2);
+    assert_eq!(machine.cpu.ram[0x310..0x313], [3, 2, 1]);
+    assert_eq!(machine.cpu.i, 0x310);
+
+    // I := 0x300, load V4-V6, then load V9-V7
+    let mut machine = self::machine(&[0xA3, 0x00, 0x54, 0x63, 0x59, 0x73]);
+    machine.cpu.ram[0x300..0x303].copy_from_slice(&[7, 8, 9]);
+    step(&mut machine, 3);
+    assert_eq!(machine.cpu.registers[4..10], [7, 8, 9, 9, 8, 7]);
+    assert_eq!(machine.cpu.i, 0x300);
+
+    // A single register
+    let mut machine = self::machine(&[0xA3, 0x00, 0x55, 0x53]);
+    machine.cpu.ram[0x300] = 42;
+    step(&mut machine, 2);
+    assert_eq!(machine.cpu.registers[5], 42);
+}
+
+#[test]
+fn audio_pattern_and_pitch() {
+    // I := pattern, load it, V0 := 0x70, pitch := V0
+    let mut pattern = [0u8; 16];
+    pattern[0] = 0x80;
+    pattern[15] = 0x01;
+    let mut program = vec![0xA2, 0x08, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A];
+    program.extend_from_slice(&pattern);
+
+    let mut machine = machine(&program);
+    assert_eq!((machine.cpu.audio_pattern, machine.cpu.pitch), (None, 64));
+    step(&mut machine, 2);
+    assert_eq!(machine.cpu.audio_pattern, Some(pattern));
+    step(&mut machine, 2);
+    assert_eq!(machine.cpu.pitch, 0x70);
+
+    // 4000 bits a second at pitch 64, doubling every 48 steps
+    assert_eq!(pattern_frequency(64), 4000.0 / 128.0);
+    assert_eq!(pattern_frequency(112), 8000.0 / 128.0);
+    assert_eq!(pattern_frequency(16), 2000.0 / 128.0);
+
+    // Played MSB first, one bit per 1/128th of the period
+    let tone = Tone { waveform: Waveform::Pattern(pattern), frequency: pattern_frequency(0x70), volume: 1.0 };
+    assert_eq!(tone.sample(0.0), 1.0);
+    assert_eq!(tone.sample(1.5 / 128.0), -1.0);
+    assert_eq!(tone.sample(126.5 / 128.0), -1.0);
+    assert_eq!(tone.sample(127.5 / 128.0), 1.0);
+}